@@ -0,0 +1,182 @@
+//! Sending alerts for services that are down or unhealthy straight to an
+//! Alertmanager, for setups without a Prometheus server on the host
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::{header, Body, Client, Request, Uri};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What's wrong with a service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    /// Not in its expected state (e.g. not running)
+    Down,
+    /// Running, but its healthcheck fails
+    Unhealthy,
+}
+
+impl Problem {
+    fn alert_name(&self) -> &'static str {
+        match self {
+            Problem::Down => "ComposeServiceDown",
+            Problem::Unhealthy => "ComposeServiceUnhealthy",
+        }
+    }
+
+    fn summary(&self, compose_name: &str, service_name: &str) -> String {
+        match self {
+            Problem::Down => format!("Service {} of {} is down", service_name, compose_name),
+            Problem::Unhealthy => {
+                format!("Service {} of {} is unhealthy", service_name, compose_name)
+            }
+        }
+    }
+}
+
+struct Observation {
+    at: Instant,
+    problem: Option<Problem>,
+}
+
+struct FiringAlert {
+    problem: Problem,
+    starts_at: DateTime<Utc>,
+}
+
+/// Labels and annotations added to every alert
+pub struct AlertTemplate<'a> {
+    pub labels: &'a HashMap<String, String>,
+    pub annotations: &'a HashMap<String, String>,
+}
+
+/// Problems of services, and the alerts sent for them, by compose and service
+/// name
+#[derive(Default)]
+pub struct Alerts {
+    observed: Mutex<HashMap<(String, String), Observation>>,
+    firing: Mutex<HashMap<(String, String), FiringAlert>>,
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+impl Alerts {
+    /// Record the current problem of a service, if any
+    pub fn observe(&self, compose_name: &str, service_name: &str, problem: Option<Problem>) {
+        self.observed
+            .lock()
+            .expect("observed problems lock shouldn't be poisoned")
+            .insert(
+                (compose_name.to_string(), service_name.to_string()),
+                Observation {
+                    at: Instant::now(),
+                    problem,
+                },
+            );
+    }
+
+    /// Get the alerts to send: firing ones for the services with a problem
+    /// observed in the last `max_age`, and resolved ones for the services that
+    /// had a problem the last time but no longer do (or weren't observed
+    /// since). Firing alerts end after `valid_for`, unless they are sent
+    /// again.
+    pub fn alerts_to_send(
+        &self,
+        max_age: Duration,
+        valid_for: Duration,
+        template: &AlertTemplate,
+    ) -> Vec<Value> {
+        let now = Utc::now();
+        let mut observed = self
+            .observed
+            .lock()
+            .expect("observed problems lock shouldn't be poisoned");
+        // Forget services that are gone
+        observed.retain(|_, observation| observation.at.elapsed() <= max_age);
+        let mut firing = self
+            .firing
+            .lock()
+            .expect("firing alerts lock shouldn't be poisoned");
+        let mut alerts = vec![];
+        let alert = |(compose_name, service_name): &(String, String),
+                     problem: Problem,
+                     starts_at: DateTime<Utc>,
+                     ends_at: DateTime<Utc>| {
+            let mut labels: Map<String, Value> = template
+                .labels
+                .iter()
+                .map(|(name, value)| (name.clone(), Value::from(value.as_str())))
+                .collect();
+            labels.insert("alertname".to_string(), problem.alert_name().into());
+            labels.insert("compose_name".to_string(), compose_name.as_str().into());
+            labels.insert("service_name".to_string(), service_name.as_str().into());
+            let mut annotations: Map<String, Value> = template
+                .annotations
+                .iter()
+                .map(|(name, value)| (name.clone(), Value::from(value.as_str())))
+                .collect();
+            annotations
+                .entry("summary")
+                .or_insert_with(|| problem.summary(compose_name, service_name).into());
+            json!({
+                "labels": labels,
+                "annotations": annotations,
+                "startsAt": timestamp(starts_at),
+                "endsAt": timestamp(ends_at),
+            })
+        };
+        // Resolve alerts whose problem went away or changed
+        firing.retain(|key, firing_alert| {
+            let problem = observed
+                .get(key)
+                .and_then(|observation| observation.problem);
+            if problem == Some(firing_alert.problem) {
+                return true;
+            }
+            alerts.push(alert(
+                key,
+                firing_alert.problem,
+                firing_alert.starts_at,
+                now,
+            ));
+            false
+        });
+        for (key, observation) in observed.iter() {
+            let Some(problem) = observation.problem else {
+                continue;
+            };
+            let firing_alert = firing.entry(key.clone()).or_insert(FiringAlert {
+                problem,
+                starts_at: now,
+            });
+            let ends_at = now + valid_for;
+            alerts.push(alert(key, problem, firing_alert.starts_at, ends_at));
+        }
+        alerts
+    }
+}
+
+/// Post the given alerts to the Alertmanager with the given base URL
+pub async fn send(base_url: &Uri, alerts: &[Value]) -> Result<(), Box<dyn std::error::Error>> {
+    let url = format!(
+        "{}/api/v2/alerts",
+        base_url.to_string().trim_end_matches('/')
+    );
+    let request = Request::post(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(
+            header::USER_AGENT,
+            concat!("compose-apps-exporter/", env!("CARGO_PKG_VERSION")),
+        )
+        .body(Body::from(serde_json::to_string(alerts)?))?;
+    let response = tokio::time::timeout(Duration::from_secs(10), Client::new().request(request))
+        .await
+        .map_err(|_| "Timed out")??;
+    if !response.status().is_success() {
+        return Err(format!("Alertmanager responded with {}", response.status()).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+//! jemalloc as the global allocator, and its statistics as metrics, to
+//! investigate memory growth of long-running instances (`jemalloc` feature)
+
+use tikv_jemalloc_ctl::{epoch, stats};
+
+use crate::registry::Samples;
+
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// Add the allocator statistics to the given samples
+pub fn add_metrics(samples: &mut Samples) -> Result<(), Box<dyn std::error::Error>> {
+    // The statistics are cached, advancing the epoch refreshes them
+    epoch::advance()?;
+    let allocated = stats::allocated::read()?;
+    let active = stats::active::read()?;
+    // Share of the active pages not actually allocated
+    let fragmentation = if active > 0 {
+        (active - allocated) as f64 / active as f64
+    } else {
+        0.0
+    };
+    samples.add(
+        "compose_apps_exporter_allocator_allocated_bytes",
+        &[],
+        allocated,
+    );
+    samples.add("compose_apps_exporter_allocator_active_bytes", &[], active);
+    samples.add(
+        "compose_apps_exporter_allocator_resident_bytes",
+        &[],
+        stats::resident::read()?,
+    );
+    samples.add(
+        "compose_apps_exporter_allocator_mapped_bytes",
+        &[],
+        stats::mapped::read()?,
+    );
+    samples.add(
+        "compose_apps_exporter_allocator_metadata_bytes",
+        &[],
+        stats::metadata::read()?,
+    );
+    samples.add(
+        "compose_apps_exporter_allocator_retained_bytes",
+        &[],
+        stats::retained::read()?,
+    );
+    samples.add(
+        "compose_apps_exporter_allocator_fragmentation_ratio",
+        &[],
+        fragmentation,
+    );
+    Ok(())
+}
@@ -0,0 +1,265 @@
+//! Grouping labels assigned to apps by name or path, so e.g. production and
+//! staging apps (or the apps of different customers) on the same host can be
+//! told apart without per-app relabeling rules in Prometheus
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::apps::AppConfig;
+
+/// Labels the exporter sets itself, which path label rules can't override
+static RESERVED_LABEL_NAMES: [&str; 23] = [
+    "compose_name",
+    "service_name",
+    "config_path",
+    "state",
+    "expected",
+    "error_class",
+    "path",
+    "volume",
+    "image",
+    "driver",
+    "secret",
+    "config",
+    "source",
+    "network",
+    "dependency",
+    "condition",
+    "capability",
+    "platform",
+    "source_host",
+    "ulimit",
+    "limit",
+    "app",
+    "error_code",
+];
+
+/// Labels to assign to the apps matching `compose_name` and/or `path`, as
+/// configured
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AppLabelsRule {
+    /// Glob pattern for compose project names
+    #[serde(default)]
+    pub compose_name: Option<String>,
+    /// Glob pattern for compose file paths
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub environment: Option<String>,
+    #[serde(default)]
+    pub tier: Option<String>,
+    /// Any other labels, e.g. to override extra labels for some apps
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+struct Rule {
+    compose_name: Option<glob::Pattern>,
+    path: Option<glob::Pattern>,
+    environment: Option<String>,
+    tier: Option<String>,
+    labels: Vec<(String, String)>,
+}
+
+impl Rule {
+    fn matches(&self, compose_name: Option<&str>, config_path: &Path) -> bool {
+        let compose_name_matches = match (&self.compose_name, compose_name) {
+            (None, _) => true,
+            (Some(pattern), Some(compose_name)) => pattern.matches(compose_name),
+            (Some(_), None) => false,
+        };
+        let path_matches = self
+            .path
+            .as_ref()
+            .is_none_or(|pattern| pattern.matches_path(config_path));
+        compose_name_matches && path_matches
+    }
+}
+
+#[derive(Default)]
+pub struct AppLabels {
+    rules: Vec<Rule>,
+    /// Regexes whose named capture groups, applied to the compose file path,
+    /// become labels
+    path_label_rules: Vec<Regex>,
+    /// Static labels of the apps declared in the config file, by compose file
+    app_static_labels: Vec<(PathBuf, Vec<(String, String)>)>,
+}
+
+impl AppLabels {
+    pub fn new(
+        rules: Vec<AppLabelsRule>,
+        path_label_rules: Vec<String>,
+        apps: &[AppConfig],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let parse_pattern = |pattern: Option<String>| {
+            pattern
+                .map(|pattern| {
+                    glob::Pattern::new(&pattern)
+                        .map_err(|err| format!("Invalid app_labels pattern {:?}: {}", pattern, err))
+                })
+                .transpose()
+        };
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                if rule.compose_name.is_none() && rule.path.is_none() {
+                    return Err("app_labels rules need a compose_name and/or path".into());
+                }
+                if let Some(name) = rule.labels.keys().find(|name| !is_valid_label_name(name)) {
+                    return Err(format!(
+                        "Invalid app_labels labels: {:?} can't be used as a label name",
+                        name
+                    )
+                    .into());
+                }
+                Ok(Rule {
+                    compose_name: parse_pattern(rule.compose_name)?,
+                    path: parse_pattern(rule.path)?,
+                    environment: rule.environment,
+                    tier: rule.tier,
+                    labels: rule.labels.into_iter().collect(),
+                })
+            })
+            .collect::<Result<_, Box<dyn std::error::Error>>>()?;
+        let path_label_rules = path_label_rules
+            .iter()
+            .map(|path_label_rule| {
+                let regex = Regex::new(path_label_rule).map_err(|err| {
+                    format!("Invalid path label rule {:?}: {}", path_label_rule, err)
+                })?;
+                if let Some(name) = regex
+                    .capture_names()
+                    .flatten()
+                    .find(|name| !is_valid_label_name(name))
+                {
+                    return Err(format!(
+                        "Invalid path label rule {:?}: {:?} can't be used as a label name",
+                        path_label_rule, name
+                    ));
+                }
+                Ok(regex)
+            })
+            .collect::<Result<_, _>>()?;
+        let app_static_labels = apps
+            .iter()
+            .filter(|app| !app.compose_files.is_empty())
+            .map(|app| {
+                if let Some(name) = app.labels.keys().find(|name| !is_valid_label_name(name)) {
+                    return Err(format!(
+                        "Invalid labels of app {}: {:?} can't be used as a label name",
+                        app.config_path().display(),
+                        name
+                    ));
+                }
+                let labels = app
+                    .labels
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect();
+                Ok((app.config_path().to_path_buf(), labels))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(AppLabels {
+            rules,
+            path_label_rules,
+            app_static_labels,
+        })
+    }
+
+    /// Get the grouping labels of the app with the given name (if known, i.e.
+    /// if its config resolved) and compose file. Labels from path label rules
+    /// come first, later rules override earlier ones, environment and tier
+    /// rules (and their other labels) override path label rules, and the
+    /// static labels of apps declared in the config file override all of them.
+    pub fn of(&self, compose_name: Option<&str>, config_path: &Path) -> Vec<(String, String)> {
+        let mut labels: Vec<(String, String)> = vec![];
+        let mut set_label = |name: &str, value: String| match labels
+            .iter_mut()
+            .find(|(label_name, _)| label_name == name)
+        {
+            Some((_, label_value)) => *label_value = value,
+            None => labels.push((name.to_string(), value)),
+        };
+        let config_path_str = config_path.to_string_lossy();
+        for regex in &self.path_label_rules {
+            let Some(captures) = regex.captures(&config_path_str) else {
+                continue;
+            };
+            for name in regex.capture_names().flatten() {
+                if let Some(value) = captures.name(name) {
+                    set_label(name, value.as_str().to_string());
+                }
+            }
+        }
+        for rule in self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches(compose_name, config_path))
+        {
+            if let Some(environment) = &rule.environment {
+                set_label("environment", environment.clone());
+            }
+            if let Some(tier) = &rule.tier {
+                set_label("tier", tier.clone());
+            }
+            for (name, value) in &rule.labels {
+                set_label(name, value.clone());
+            }
+        }
+        let static_labels = self
+            .app_static_labels
+            .iter()
+            .filter(|(app_config_path, _)| app_config_path == config_path)
+            .flat_map(|(_, static_labels)| static_labels);
+        for (name, value) in static_labels {
+            set_label(name, value.clone());
+        }
+        labels
+    }
+
+    /// Get the names of all grouping labels apps can get, in the order
+    /// [`AppLabels::of`] sets them
+    pub fn label_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        let capture_names = self
+            .path_label_rules
+            .iter()
+            .flat_map(|regex| regex.capture_names().flatten());
+        let rule_label_names = self.rules.iter().flat_map(|rule| {
+            rule.environment
+                .as_ref()
+                .map(|_| "environment")
+                .into_iter()
+                .chain(rule.tier.as_ref().map(|_| "tier"))
+                .chain(rule.labels.iter().map(|(name, _)| name.as_str()))
+        });
+        let static_label_names = self
+            .app_static_labels
+            .iter()
+            .flat_map(|(_, static_labels)| static_labels.iter().map(|(name, _)| name.as_str()));
+        for name in capture_names
+            .chain(rule_label_names)
+            .chain(static_label_names)
+        {
+            if !names.iter().any(|existing| existing == name) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+}
+
+/// Whether the given name can be used as the name of a label set by the
+/// configuration (e.g. by a path label rule), rather than by the exporter
+pub fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+        && !RESERVED_LABEL_NAMES.contains(&name)
+}
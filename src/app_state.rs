@@ -0,0 +1,56 @@
+//! State of the apps and their services as JSON, served on `/api/v1/apps` from
+//! the same collections as the metrics, for status dashboards and chatbots that
+//! would otherwise have to query Prometheus
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::scrape_error::{ErrorCode, ScrapeError};
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ServiceState {
+    pub service_name: String,
+    /// `None` while the service is in its grace period
+    pub state: Option<String>,
+    pub health: Option<String>,
+    pub image: Option<String>,
+    pub containers_desired: u64,
+    pub containers_running: u64,
+    pub in_maintenance: bool,
+    pub in_downtime: bool,
+    /// Ratio of time the service was available, by availability window (e.g.
+    /// '1h')
+    pub available_ratios: BTreeMap<String, f64>,
+    /// Ratio of time the service was available, by uptime history window (e.g.
+    /// '24h')
+    pub uptime_ratios: BTreeMap<String, f64>,
+}
+
+/// Why collecting an app failed
+#[derive(Serialize, Clone, Debug)]
+pub struct AppError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl From<&ScrapeError> for AppError {
+    fn from(err: &ScrapeError) -> Self {
+        AppError {
+            code: err.code,
+            message: err.to_string().trim_end().to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AppState {
+    pub compose_name: String,
+    pub config_path: String,
+    /// Why the app's compose config is invalid (e.g. 'yaml_error'), if it is
+    pub config_error_class: Option<String>,
+    /// Why collecting the app failed, if it did, in which case it has no
+    /// services
+    pub error: Option<AppError>,
+    /// Sorted by name
+    pub services: Vec<ServiceState>,
+}
@@ -0,0 +1,91 @@
+//! Apps declared explicitly in the config file, with the compose files,
+//! project name, working directory and env file to run docker compose with, as
+//! a deterministic alternative to discovering them with globs
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AppConfig {
+    /// Compose project name, derived by docker compose if not set
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The app's compose files, merged in order. The first one identifies the
+    /// app, e.g. in the `config_path` label.
+    pub compose_files: Vec<PathBuf>,
+    /// Passed as `--project-directory`
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+    /// Passed as `--env-file`
+    #[serde(default)]
+    pub env_file: Option<PathBuf>,
+    /// Static labels to add to the app's metrics, e.g. `team: platform`
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl AppConfig {
+    /// The app's compose file that identifies it
+    pub fn config_path(&self) -> &Path {
+        &self.compose_files[0]
+    }
+
+    /// Arguments to pass to `docker compose` besides `-f` with the first
+    /// compose file
+    pub fn compose_args(&self) -> Vec<OsString> {
+        let mut args = vec![];
+        for compose_file in &self.compose_files[1..] {
+            args.push("-f".into());
+            args.push(compose_file.into());
+        }
+        if let Some(name) = &self.name {
+            args.push("-p".into());
+            args.push(name.into());
+        }
+        if let Some(working_dir) = &self.working_dir {
+            args.push("--project-directory".into());
+            args.push(working_dir.into());
+        }
+        if let Some(env_file) = &self.env_file {
+            args.push("--env-file".into());
+            args.push(env_file.into());
+        }
+        args
+    }
+}
+
+/// The apps declared in the config file
+#[derive(Default)]
+pub struct Apps(Vec<AppConfig>);
+
+impl Apps {
+    pub fn new(apps: Vec<AppConfig>) -> Result<Self, String> {
+        for (index, app) in apps.iter().enumerate() {
+            if app.compose_files.is_empty() {
+                return Err(format!("App {} in apps has no compose_files", index + 1));
+            }
+            if apps[..index]
+                .iter()
+                .any(|other| other.config_path() == app.config_path())
+            {
+                return Err(format!(
+                    "Several apps in apps have compose file {} first",
+                    app.config_path().display()
+                ));
+            }
+        }
+        Ok(Apps(apps))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AppConfig> {
+        self.0.iter()
+    }
+
+    /// Get the declared app identified by the given compose file, if any
+    pub fn get(&self, config_path: &Path) -> Option<&AppConfig> {
+        self.0.iter().find(|app| app.config_path() == config_path)
+    }
+}
@@ -0,0 +1,85 @@
+//! Availability of services over sliding windows (the fraction of the window
+//! they were running and healthy), for SLO-ish visibility without recording
+//! rules
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Observations of a service's availability, oldest first. Each one holds
+/// until the next.
+type Observations = VecDeque<(Instant, bool)>;
+
+#[derive(Default)]
+pub struct Availability {
+    /// By compose and service name
+    observations: Mutex<HashMap<(String, String), Observations>>,
+}
+
+/// Suffix of the availability metric of the given window, e.g. '5m' or '1h'
+pub fn window_suffix(window: Duration) -> String {
+    let minutes = window.as_secs() / 60;
+    if minutes.is_multiple_of(60) {
+        format!("{}h", minutes / 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+impl Availability {
+    /// Record whether the given service is currently available, and get the
+    /// fraction of each of the given windows it was available (as far as
+    /// observed)
+    pub fn observe(
+        &self,
+        compose_name: &str,
+        service_name: &str,
+        available: bool,
+        windows: &[Duration],
+    ) -> Vec<f64> {
+        let now = Instant::now();
+        let mut observations = self
+            .observations
+            .lock()
+            .expect("availability observations lock shouldn't be poisoned");
+        let key = (compose_name.to_string(), service_name.to_string());
+        let observations = observations.entry(key).or_default();
+        observations.push_back((now, available));
+        // Keep the last observation from before the longest window, as it
+        // holds at its start
+        let longest_window = windows.iter().max().copied().unwrap_or_default();
+        while observations
+            .get(1)
+            .is_some_and(|(at, _)| now.duration_since(*at) >= longest_window)
+        {
+            observations.pop_front();
+        }
+        windows
+            .iter()
+            .map(|window| available_ratio(observations, now, *window))
+            .collect()
+    }
+}
+
+fn available_ratio(observations: &Observations, now: Instant, window: Duration) -> f64 {
+    let window_start = now.checked_sub(window).unwrap_or(now);
+    let mut available_time = Duration::ZERO;
+    let mut observed_time = Duration::ZERO;
+    for (i, (at, available)) in observations.iter().enumerate() {
+        let until = observations.get(i + 1).map_or(now, |(next_at, _)| *next_at);
+        let from = (*at).max(window_start);
+        if until <= from {
+            continue;
+        }
+        observed_time += until - from;
+        if *available {
+            available_time += until - from;
+        }
+    }
+    if observed_time.is_zero() {
+        // Only observed just now
+        let last_available = observations.back().is_some_and(|(_, available)| *available);
+        return last_available as u8 as f64;
+    }
+    available_time.as_secs_f64() / observed_time.as_secs_f64()
+}
@@ -0,0 +1,48 @@
+//! Disk usage of the host directories bind-mounted into services, where app
+//! data usually grows without showing up in `docker system df`
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Outcome of measuring the disk usage of a bind-mounted path
+pub struct BindMountUsage {
+    /// Total (apparent) size of the files under the path
+    pub bytes: u64,
+    /// Whether the measurement stopped early because of the depth or time
+    /// limit, in which case `bytes` is a lower bound
+    pub truncated: bool,
+}
+
+/// Measure the disk usage of the given path, not following symlinks and not
+/// descending deeper than `max_depth` directories or for longer than `timeout`.
+/// Entries that can't be read (e.g. for lack of permissions) are skipped.
+pub fn measure(path: &Path, max_depth: usize, timeout: Duration) -> BindMountUsage {
+    let deadline = Instant::now() + timeout;
+    let mut usage = BindMountUsage {
+        bytes: 0,
+        truncated: false,
+    };
+    let mut pending: Vec<(PathBuf, usize)> = vec![(path.to_path_buf(), 0)];
+    while let Some((path, depth)) = pending.pop() {
+        if Instant::now() > deadline {
+            usage.truncated = true;
+            break;
+        }
+        let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if metadata.is_file() {
+            usage.bytes += metadata.len();
+        } else if metadata.is_dir() {
+            if depth >= max_depth {
+                usage.truncated = true;
+                continue;
+            }
+            let Ok(entries) = std::fs::read_dir(&path) else {
+                continue;
+            };
+            pending.extend(entries.flatten().map(|entry| (entry.path(), depth + 1)));
+        }
+    }
+    usage
+}
@@ -0,0 +1,222 @@
+//! Cancellation of collections whose client disconnected (e.g. Prometheus
+//! timing out), so no docker commands keep running for results nobody will
+//! read, and deadlines of collections that must finish in time for the scrape
+//! timeout. Collections are cancelled by dropping their future, which kills the
+//! docker commands it's running.
+
+use std::future::Future;
+use std::io;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::scrape_error::{ErrorCode, ScrapeError};
+
+/// Logs that the collection it was created for was cancelled when dropped,
+/// unless disarmed first. hyper drops the request handler (and thus the
+/// collection and this guard) when the client disconnects.
+pub struct CancelOnDrop {
+    armed: bool,
+}
+
+impl CancelOnDrop {
+    pub fn new() -> Self {
+        CancelOnDrop { armed: true }
+    }
+
+    /// Call once the collection has finished
+    pub fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if self.armed {
+            eprintln!("Client disconnected, cancelling collection");
+        }
+    }
+}
+
+/// Kills the process group it was created for when dropped, unless disarmed
+/// first. Killing only the child process would leave the processes it started
+/// running, e.g. docker itself when the docker command is run through sudo.
+struct KillGroupOnDrop {
+    group_id: Option<u32>,
+}
+
+impl KillGroupOnDrop {
+    /// Call once the child process has exited
+    fn disarm(mut self) {
+        self.group_id = None;
+    }
+}
+
+impl Drop for KillGroupOnDrop {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(group_id) = self.group_id {
+            // The group is gone already if all its processes have exited
+            let _ = nix::sys::signal::killpg(
+                nix::unistd::Pid::from_raw(group_id as i32),
+                nix::sys::signal::Signal::SIGKILL,
+            );
+        }
+    }
+}
+
+/// Like [`Command::output`], but kill the child process and the processes it
+/// started if the returned future is dropped (i.e. the collection is cancelled
+/// or its deadline passes), or if it runs longer than the timeout (failing with
+/// [`io::ErrorKind::TimedOut`])
+pub async fn output(command: Command, timeout: Option<Duration>) -> io::Result<Output> {
+    let mut command = tokio::process::Command::from(command);
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // In a process group of its own, led by the child process
+    #[cfg(unix)]
+    command.process_group(0);
+    let child = command.spawn()?;
+    let kill_group_on_drop = KillGroupOnDrop {
+        group_id: child.id(),
+    };
+    let output = child.wait_with_output();
+    let output = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, output)
+            .await
+            .unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("killed after running for {:?}", timeout),
+                ))
+            }),
+        None => output.await,
+    }?;
+    kill_group_on_drop.disarm();
+    Ok(output)
+}
+
+/// Run the given part of a collection, abandoning it (killing the docker
+/// commands it's running) once the deadline, if any, has passed
+pub async fn before_deadline<T>(
+    deadline: Option<Instant>,
+    future: impl Future<Output = Result<T, Box<dyn std::error::Error>>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let Some(deadline) = deadline else {
+        return future.await;
+    };
+    if Instant::now() >= deadline {
+        // Don't start what can't finish in time anyway
+        return Err(ScrapeError::new(
+            ErrorCode::TimedOut,
+            "Not collected because the scrape timeout was reached",
+        )
+        .into());
+    }
+    tokio::time::timeout_at(deadline.into(), future)
+        .await
+        .unwrap_or_else(|_| {
+            Err(ScrapeError::new(
+                ErrorCode::TimedOut,
+                "Abandoned because the scrape timeout was reached",
+            )
+            .into())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Command starting a background process that outlives it unless killed,
+    /// whose PID it writes to the returned file
+    fn command_with_grandchild(name: &str) -> (Command, std::path::PathBuf) {
+        let pid_path =
+            std::env::temp_dir().join(format!("grandchild-{}-{}", name, std::process::id()));
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("sleep 30 & echo $! > {}; wait", pid_path.display()));
+        (command, pid_path)
+    }
+
+    /// Wait for the background process whose PID is in the given file to be
+    /// killed, failing if it doesn't die within a second
+    async fn assert_killed(pid_path: &std::path::Path) {
+        let pid = std::fs::read_to_string(pid_path).expect("grandchild should have started");
+        let _ = std::fs::remove_file(pid_path);
+        let stat_path = format!("/proc/{}/stat", pid.trim());
+        for _ in 0..100 {
+            // Killed processes linger as zombies if nothing reaps them
+            match std::fs::read_to_string(&stat_path) {
+                Err(_) => return,
+                Ok(stat) if stat.contains(") Z ") => return,
+                Ok(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+        panic!("process {} should have been killed", pid.trim());
+    }
+
+    #[tokio::test]
+    async fn outputs_finished_commands() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo out; echo err >&2; exit 3");
+        let output = output(command, Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn kills_timed_out_commands_with_their_children() {
+        let (command, pid_path) = command_with_grandchild("timeout");
+        let err = output(command, Some(Duration::from_millis(200)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_killed(&pid_path).await;
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn kills_commands_with_their_children_when_dropped() {
+        let (command, pid_path) = command_with_grandchild("drop");
+        let abandoned =
+            tokio::time::timeout(Duration::from_millis(200), output(command, None)).await;
+        assert!(abandoned.is_err());
+        assert_killed(&pid_path).await;
+    }
+
+    #[tokio::test]
+    async fn abandons_collections_past_their_deadline() {
+        let started = std::sync::atomic::AtomicBool::new(false);
+        let past = before_deadline(Some(Instant::now()), async {
+            started.store(true, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        })
+        .await;
+        assert_eq!(
+            ScrapeError::from_boxed(past.unwrap_err()).code,
+            ErrorCode::TimedOut
+        );
+        assert!(!started.load(std::sync::atomic::Ordering::SeqCst));
+
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let abandoned = before_deadline(Some(deadline), async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(())
+        })
+        .await;
+        assert_eq!(
+            ScrapeError::from_boxed(abandoned.unwrap_err()).code,
+            ErrorCode::TimedOut
+        );
+
+        assert_eq!(before_deadline(None, async { Ok(1) }).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,218 @@
+//! Detection of the installed docker and docker compose versions, and selection
+//! of the parsers/flags appropriate for them
+
+use std::{fmt, str::FromStr, time::Duration};
+
+use crate::{exec_docker_cmd, Container};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    /// Parse versions like `24.0.5`, `v2.21.0` or `2.20.2-desktop.1`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid version: {:?}", s);
+        let s = s.trim().trim_start_matches('v');
+        let s = s.split(['-', '+']).next().unwrap_or_default();
+        let mut parts = s.split('.').map(|part| part.parse::<u32>());
+        let mut next_part = || parts.next().transpose().map_err(|_| invalid());
+        let major = next_part()?.ok_or_else(invalid)?;
+        let minor = next_part()?.unwrap_or(0);
+        let patch = next_part()?.unwrap_or(0);
+        Ok(Version::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Oldest docker version shipping with (or supporting) the compose V2 plugin
+static MIN_DOCKER_VERSION: Version = Version::new(20, 10, 0);
+static MIN_COMPOSE_VERSION: Version = Version::new(2, 0, 0);
+/// Since this version, `docker compose config` includes the top-level project
+/// `name`
+static COMPOSE_CONFIG_NAME_VERSION: Version = Version::new(2, 3, 0);
+
+/// Shape of the `docker compose ps --format json` output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsOutputFormat {
+    /// A single JSON array of containers
+    JsonArray,
+    /// One JSON object per container, newline-delimited (since compose 2.21.0,
+    /// though some distribution builds differ)
+    JsonLines,
+}
+
+impl PsOutputFormat {
+    /// Detect the format of the given output from its first non-whitespace
+    /// character, rather than from the compose version
+    fn detect(output: &[u8]) -> Self {
+        match output.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'[') => PsOutputFormat::JsonArray,
+            // Including no output at all, i.e. no containers
+            _ => PsOutputFormat::JsonLines,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Compat {
+    pub docker_version: Version,
+    pub compose_version: Version,
+}
+
+impl Compat {
+    /// Detect the installed docker and docker compose versions, failing if
+    /// either of them is unsupported
+    pub async fn detect(
+        docker_command: impl Fn() -> std::process::Command,
+        command_timeout: Option<Duration>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let exec_docker_global_cmd = |args: &[&str]| {
+            let mut command = docker_command();
+            command.args(args);
+            exec_docker_cmd(command, command_timeout)
+        };
+        let docker_version_output = exec_docker_global_cmd(&["--version"])
+            .await
+            .map_err(|err| format!("Failed to detect docker version: {}", err))?;
+        // e.g. 'Docker version 24.0.5, build ced0996'
+        let docker_version = String::from_utf8_lossy(&docker_version_output)
+            .split_whitespace()
+            .nth(2)
+            .ok_or("Failed to detect docker version: unexpected `docker --version` output")?
+            .trim_end_matches(',')
+            .parse::<Version>()?;
+        let compose_version_output = exec_docker_global_cmd(&["compose", "version", "--short"])
+            .await
+            .map_err(|err| {
+                format!(
+                    "Failed to detect docker compose version (is the docker compose V2 plugin installed?): {}",
+                    err
+                )
+            })?;
+        let compose_version =
+            String::from_utf8_lossy(&compose_version_output).parse::<Version>()?;
+
+        if docker_version < MIN_DOCKER_VERSION {
+            return Err(format!(
+                "docker {} is not supported, the minimum supported version is {}",
+                docker_version, MIN_DOCKER_VERSION
+            )
+            .into());
+        }
+        if compose_version < MIN_COMPOSE_VERSION {
+            return Err(format!(
+                "docker compose {} is not supported, the minimum supported version is {} (compose V2)",
+                compose_version, MIN_COMPOSE_VERSION
+            )
+            .into());
+        }
+
+        Ok(Compat {
+            docker_version,
+            compose_version,
+        })
+    }
+
+    /// Whether `docker compose config` outputs the project name. If not, it
+    /// has to be derived from the project directory instead.
+    pub fn config_includes_name(&self) -> bool {
+        self.compose_version >= COMPOSE_CONFIG_NAME_VERSION
+    }
+
+    /// Parse the output of `docker compose ps --format json`, in either of its
+    /// formats
+    pub fn parse_ps_output(&self, output: &[u8]) -> Result<Vec<Container>, serde_json::Error> {
+        match PsOutputFormat::detect(output) {
+            PsOutputFormat::JsonArray => serde_json::from_slice(output),
+            PsOutputFormat::JsonLines => output
+                .split(|byte| *byte == b'\n')
+                .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+                .map(serde_json::from_slice)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compat() -> Compat {
+        Compat {
+            docker_version: Version::new(24, 0, 5),
+            compose_version: Version::new(2, 21, 0),
+        }
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!("24.0.5".parse(), Ok(Version::new(24, 0, 5)));
+        assert_eq!("v2.21.0\n".parse(), Ok(Version::new(2, 21, 0)));
+        assert_eq!("2.20.2-desktop.1".parse(), Ok(Version::new(2, 20, 2)));
+        assert_eq!("2.24.0+ds1".parse(), Ok(Version::new(2, 24, 0)));
+        assert_eq!("20.10".parse(), Ok(Version::new(20, 10, 0)));
+        assert!("".parse::<Version>().is_err());
+        assert!("dev".parse::<Version>().is_err());
+        assert!("2.x.0".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn orders_versions() {
+        assert!(Version::new(2, 2, 0) < COMPOSE_CONFIG_NAME_VERSION);
+        assert!(Version::new(20, 10, 24) > MIN_DOCKER_VERSION);
+        assert!(Version::new(1, 29, 2) < MIN_COMPOSE_VERSION);
+    }
+
+    #[test]
+    fn parses_ps_output_formats() {
+        let json_array = br#"[{"Name":"web-1","Service":"web","State":"running","Health":"healthy"},{"Name":"db-1","Service":"db","State":"exited","ExitCode":1}]"#;
+        let json_lines = concat!(
+            r#"{"Name":"web-1","Service":"web","State":"running","Health":"healthy"}"#,
+            "\n",
+            r#"{"Name":"db-1","Service":"db","State":"exited","ExitCode":1}"#,
+            "\n\n"
+        );
+        for output in [&json_array[..], json_lines.as_bytes()] {
+            let containers = compat()
+                .parse_ps_output(output)
+                .expect("output should parse");
+            assert_eq!(containers.len(), 2);
+            assert_eq!(containers[0].name, "web-1");
+            assert_eq!(containers[0].health.as_deref(), Some("healthy"));
+            assert_eq!(containers[1].state, "exited");
+            assert_eq!(containers[1].exit_code, Some(1));
+        }
+    }
+
+    #[test]
+    fn parses_empty_ps_output() {
+        for output in [&b""[..], b"\n", b"[]"] {
+            let containers = compat()
+                .parse_ps_output(output)
+                .expect("output should parse");
+            assert!(containers.is_empty());
+        }
+        assert!(compat().parse_ps_output(b"{").is_err());
+    }
+}
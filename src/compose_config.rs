@@ -0,0 +1,326 @@
+//! Model of the resolved compose config, as output by `docker compose config
+//! --format json`
+//!
+//! See <https://github.com/compose-spec/compose-spec/blob/master/spec.md>. Only
+//! the parts relevant to the exporter are modeled, not all of which are read
+//! by every version of it.
+#![allow(dead_code)]
+
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeConfig {
+    /// Not included in the output of older compose versions, see
+    /// [`crate::compat::Compat::config_includes_name`]
+    #[serde(default)]
+    pub name: String,
+    pub services: HashMap<String, ComposeService>,
+    #[serde(default)]
+    pub networks: HashMap<String, ComposeNetwork>,
+    #[serde(default)]
+    pub volumes: HashMap<String, ComposeVolume>,
+    #[serde(default)]
+    pub secrets: HashMap<String, ComposeFileObject>,
+    #[serde(default)]
+    pub configs: HashMap<String, ComposeFileObject>,
+    /// Warnings `docker compose config` printed to stderr
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeService {
+    /// Only set for services with a fixed container name (which can't be
+    /// scaled)
+    #[serde(default)]
+    pub container_name: Option<String>,
+    /// Number of containers to run, superseded by `deploy.replicas`
+    #[serde(default)]
+    pub scale: Option<u32>,
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub healthcheck: Option<ComposeHealthcheck>,
+    #[serde(default)]
+    pub deploy: Option<ComposeDeploy>,
+    /// Networks the service is attached to, by their key in the top-level
+    /// `networks`
+    #[serde(default)]
+    pub networks: HashMap<String, Option<ComposeServiceNetwork>>,
+    #[serde(default)]
+    pub volumes: Vec<ComposeServiceVolume>,
+    /// GPUs to give the service access to (shorthand for a GPU device
+    /// reservation)
+    #[serde(default, deserialize_with = "deserialize_gpus")]
+    pub gpus: Vec<ComposeDeviceRequest>,
+    #[serde(default)]
+    pub secrets: Vec<ComposeServiceFileReference>,
+    #[serde(default)]
+    pub configs: Vec<ComposeServiceFileReference>,
+    /// Services this service depends on, by name
+    #[serde(default, deserialize_with = "deserialize_depends_on")]
+    pub depends_on: HashMap<String, ComposeDependency>,
+    #[serde(default)]
+    pub privileged: bool,
+    /// Linux capabilities added to the container's default set, e.g.
+    /// `NET_ADMIN`
+    #[serde(default)]
+    pub cap_add: Vec<String>,
+    /// e.g. `host`, `none` or `service:other-service`
+    #[serde(default)]
+    pub network_mode: Option<String>,
+    /// PID namespace, e.g. `host`
+    #[serde(default)]
+    pub pid: Option<String>,
+    /// `user[:group]` to run the container's process as, by name or ID
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Whether the container's root file system is mounted read-only
+    #[serde(default)]
+    pub read_only: bool,
+    /// Environment variables, with their resolved values (`None` if declared
+    /// without a value and unset)
+    #[serde(default)]
+    pub environment: HashMap<String, Option<String>>,
+    /// e.g. `nofile`
+    #[serde(default)]
+    pub ulimits: BTreeMap<String, ComposeUlimit>,
+    /// One of: no, always, unless-stopped, on-failure, or
+    /// on-failure:<max retries>
+    #[serde(default)]
+    pub restart: Option<String>,
+}
+
+impl ComposeService {
+    /// Whether the service explicitly disables the healthcheck (possibly
+    /// defined by its image)
+    pub fn healthcheck_disabled(&self) -> bool {
+        self.healthcheck.as_ref().is_some_and(|healthcheck| {
+            healthcheck.disable || healthcheck.test.first().is_some_and(|test| test == "NONE")
+        })
+    }
+}
+
+/// Ulimit of a service, either a single value (for both the soft and hard
+/// limit) or separate ones
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ComposeUlimit {
+    Single(i64),
+    Limits { soft: i64, hard: i64 },
+}
+
+impl ComposeUlimit {
+    /// The soft and hard limit
+    pub fn limits(&self) -> (i64, i64) {
+        match self {
+            ComposeUlimit::Single(limit) => (*limit, *limit),
+            ComposeUlimit::Limits { soft, hard } => (*soft, *hard),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeDependency {
+    /// One of: service_started, service_healthy, or
+    /// service_completed_successfully
+    #[serde(default = "default_dependency_condition")]
+    pub condition: String,
+}
+
+fn default_dependency_condition() -> String {
+    "service_started".to_string()
+}
+
+/// Deserialize the `depends_on` of a service, which older compose versions
+/// output as is, e.g. a list of service names (short syntax)
+fn deserialize_depends_on<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, ComposeDependency>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum DependsOn {
+        Short(Vec<String>),
+        Long(HashMap<String, ComposeDependency>),
+    }
+    Ok(match DependsOn::deserialize(deserializer)? {
+        DependsOn::Short(service_names) => service_names
+            .into_iter()
+            .map(|service_name| {
+                let dependency = ComposeDependency {
+                    condition: default_dependency_condition(),
+                };
+                (service_name, dependency)
+            })
+            .collect(),
+        DependsOn::Long(dependencies) => dependencies,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeHealthcheck {
+    /// e.g. `["CMD", "curl", "-f", "http://localhost"]`, or `["NONE"]`
+    #[serde(default)]
+    pub test: Vec<String>,
+    #[serde(default)]
+    pub disable: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeDeploy {
+    #[serde(default)]
+    pub replicas: Option<u32>,
+    #[serde(default)]
+    pub resources: Option<ComposeResources>,
+    #[serde(default)]
+    pub restart_policy: Option<ComposeRestartPolicy>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeRestartPolicy {
+    /// One of: none, on-failure, or any
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub max_attempts: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeResources {
+    #[serde(default)]
+    pub reservations: Option<ComposeReservations>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeReservations {
+    #[serde(default)]
+    pub devices: Vec<ComposeDeviceRequest>,
+}
+
+/// Deserialize the `gpus` of a service, which older compose versions output as
+/// is, e.g. 'all'
+fn deserialize_gpus<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ComposeDeviceRequest>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Gpus {
+        All(AllDevices),
+        Requests(Vec<ComposeDeviceRequest>),
+    }
+    Ok(match Gpus::deserialize(deserializer)? {
+        Gpus::All(all) => vec![ComposeDeviceRequest {
+            capabilities: vec!["gpu".to_string()],
+            driver: None,
+            count: Some(DeviceCount::All(all)),
+            device_ids: vec![],
+        }],
+        Gpus::Requests(requests) => requests,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeDeviceRequest {
+    /// e.g. `["gpu"]`, or `["gpu", "utility"]`
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// e.g. 'nvidia'
+    #[serde(default)]
+    pub driver: Option<String>,
+    /// Number of devices, if not given by `device_ids`
+    #[serde(default)]
+    pub count: Option<DeviceCount>,
+    #[serde(default)]
+    pub device_ids: Vec<String>,
+}
+
+/// Number of devices to reserve; all of them is 'all' in the compose file but
+/// -1 in the output of some compose versions
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+pub enum DeviceCount {
+    Count(i64),
+    All(AllDevices),
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AllDevices {
+    All,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeServiceNetwork {
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeServiceVolume {
+    /// One of: bind, volume, tmpfs, npipe, or cluster
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Host path of bind mounts, or the key in the top-level `volumes` of
+    /// named volumes
+    #[serde(default)]
+    pub source: Option<String>,
+    pub target: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeNetwork {
+    /// Name of the network on the docker engine, e.g. 'my-app_default'
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub external: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ComposeVolume {
+    /// Name of the volume on the docker engine, e.g. 'my-app_data'
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub external: bool,
+}
+
+/// Secret or config, as declared in the top-level `secrets` or `configs`
+#[derive(Deserialize, Debug)]
+pub struct ComposeFileObject {
+    /// Host path of the file holding the secret or config
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Environment variable holding the secret or config
+    #[serde(default)]
+    pub environment: Option<String>,
+    /// Inline content (configs only)
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub external: bool,
+}
+
+/// Reference of a service to a secret or config, either just its key in the
+/// top-level `secrets` or `configs` (short syntax), or with more options
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ComposeServiceFileReference {
+    Short(String),
+    Long { source: String },
+}
+
+impl ComposeServiceFileReference {
+    /// Key of the secret or config in the top-level `secrets` or `configs`
+    pub fn source(&self) -> &str {
+        match self {
+            ComposeServiceFileReference::Short(source) => source,
+            ComposeServiceFileReference::Long { source } => source,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Gzip compression of the metrics responses, negotiated with the
+//! `Accept-Encoding` header, as the exposition gets large with hundreds of
+//! services
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hyper::header;
+use hyper::HeaderMap;
+use std::io::Write;
+
+/// Whether the client accepts gzip-encoded responses according to the
+/// `Accept-Encoding` header of its request (explicitly or with `*`, and not
+/// with a quality of 0)
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    let mut gzip_quality = None;
+    let mut wildcard_quality = None;
+    for accept_encoding in headers.get_all(header::ACCEPT_ENCODING) {
+        let Ok(accept_encoding) = accept_encoding.to_str() else {
+            continue;
+        };
+        for coding in accept_encoding.split(',') {
+            let mut parameters = coding.split(';').map(str::trim);
+            let coding = parameters.next().unwrap_or_default();
+            let quality: f32 = parameters
+                .find_map(|parameter| parameter.strip_prefix("q="))
+                .map_or(1.0, |value| value.parse().unwrap_or(0.0));
+            if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+                gzip_quality = Some(quality);
+            } else if coding == "*" {
+                wildcard_quality = Some(quality);
+            }
+        }
+    }
+    gzip_quality
+        .or(wildcard_quality)
+        .is_some_and(|quality| quality > 0.0)
+}
+
+/// Compress the given body with gzip
+pub fn gzip(body: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::fast());
+    encoder
+        .write_all(body)
+        .expect("writing to a vector shouldn't fail");
+    encoder
+        .finish()
+        .expect("writing to a vector shouldn't fail")
+}
@@ -0,0 +1,74 @@
+//! Collecting apps concurrently, so a scrape takes roughly as long as its
+//! slowest app rather than the sum of all of them
+
+use futures::stream::{self, StreamExt};
+use std::future::Future;
+
+/// Map the given items with `f`, running up to `max_concurrency` of the
+/// returned futures at a time, returning the results in the order of the items.
+/// The futures belong to the collection awaiting the returned one, i.e. their
+/// docker commands are killed when it's cancelled or its deadline passes.
+pub async fn map<'a, T, R, F: Future<Output = R>>(
+    items: &'a [T],
+    max_concurrency: usize,
+    f: impl Fn(&'a T) -> F,
+) -> Vec<R> {
+    let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+    // Futures don't run until polled, so creating them all upfront is free.
+    // Mapping the items within the stream instead trips up the compiler's
+    // `Send` checks of the futures collecting the apps.
+    let futures: Vec<_> = items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let future = f(item);
+            async move { (index, future.await) }
+        })
+        .collect();
+    let mut mapped = stream::iter(futures).buffer_unordered(max_concurrency.max(1));
+    while let Some((index, result)) = mapped.next().await {
+        results[index] = Some(result);
+    }
+    results
+        .into_iter()
+        .map(|result| result.expect("all items should be mapped"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn maps_in_item_order() {
+        let items: Vec<u64> = (0..20).collect();
+        let mapped = map(&items, 4, |item| async move {
+            // Finish out of order
+            tokio::time::sleep(Duration::from_millis(20 - item)).await;
+            item * 2
+        })
+        .await;
+        assert_eq!(
+            mapped,
+            items.iter().map(|item| item * 2).collect::<Vec<_>>()
+        );
+        assert_eq!(map(&items, 0, |item| async move { *item }).await, items);
+    }
+
+    #[tokio::test]
+    async fn runs_up_to_max_concurrency() {
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let items: Vec<u64> = (0..20).collect();
+        map(&items, 3, |_| async {
+            let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now_running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+        })
+        .await;
+        assert_eq!(max_running.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,44 @@
+//! Whether services run with the config their compose file currently renders
+//! to, by comparing the config hash docker compose labeled their containers
+//! with when creating them with the one it computes now
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::apps::AppConfig;
+use crate::scrape_error::{ErrorCode, ScrapeError};
+use crate::{exec_docker_compose_cmd, DockerCli};
+
+/// Label docker compose sets on the containers it creates to the hash of their
+/// service's config, to know which to recreate on `docker compose up`
+pub static CONFIG_HASH_LABEL: &str = "com.docker.compose.config-hash";
+
+/// Read the hashes of the current configs of the services of the app with the
+/// given compose file (declared in the config file as `app`, if it is), by
+/// service name, as computed by `docker compose config --hash`
+pub async fn read_service_config_hashes(
+    docker: &DockerCli,
+    config_path: &Path,
+    app: Option<&AppConfig>,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let output = exec_docker_compose_cmd(docker, config_path, app, &["config", "--hash", "*"])
+        .await
+        .map_err(|err| ScrapeError::context(err, "Failed to execute `docker compose config`"))?;
+    // e.g. 'web 0123...cdef'
+    String::from_utf8_lossy(&output)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (service_name, hash) = line.trim().split_once(' ').ok_or_else(|| {
+                ScrapeError::new(
+                    ErrorCode::DockerOutputInvalid,
+                    format!(
+                        "Failed to parse `docker compose config --hash` output: {:?}",
+                        line
+                    ),
+                )
+            })?;
+            Ok((service_name.to_string(), hash.trim().to_string()))
+        })
+        .collect()
+}
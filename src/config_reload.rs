@@ -0,0 +1,116 @@
+//! Bookkeeping of configuration reloads (on SIGHUP), exported following the
+//! conventions of Prometheus' own reload metrics so failed reloads are
+//! alertable
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::registry::Samples;
+
+pub struct ConfigReloads {
+    successes: u64,
+    failures: u64,
+    last_successful: bool,
+    /// When the current configuration was applied, initially the start of the
+    /// exporter
+    last_success_at: SystemTime,
+}
+
+impl ConfigReloads {
+    pub fn new() -> Self {
+        ConfigReloads {
+            successes: 0,
+            failures: 0,
+            last_successful: true,
+            last_success_at: SystemTime::now(),
+        }
+    }
+
+    pub fn record(&mut self, successful: bool) {
+        if successful {
+            self.successes += 1;
+            self.last_success_at = SystemTime::now();
+        } else {
+            self.failures += 1;
+        }
+        self.last_successful = successful;
+    }
+
+    /// Add the reload metrics to the given samples, `config_hash` being the
+    /// hash of the configuration currently applied
+    pub fn add_metrics(&self, samples: &mut Samples, config_hash: u64) {
+        samples.add(
+            "compose_apps_exporter_config_reloads_total",
+            &[("outcome", "success")],
+            self.successes,
+        );
+        samples.add(
+            "compose_apps_exporter_config_reloads_total",
+            &[("outcome", "failure")],
+            self.failures,
+        );
+        samples.add(
+            "compose_apps_exporter_config_last_reload_successful",
+            &[],
+            self.last_successful as u8,
+        );
+        samples.add(
+            "compose_apps_exporter_config_last_reload_success_timestamp_seconds",
+            &[],
+            self.last_success_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        );
+        samples.add("compose_apps_exporter_config_hash", &[], config_hash);
+    }
+}
+
+/// Hash a configuration (as serialized to JSON, whose object keys are sorted)
+/// to a value that is exactly representable as a metric value. Tokens are left
+/// out, as the hash is exported and could be used to guess them.
+pub fn config_hash(config: &serde_json::Value) -> u64 {
+    let mut config = config.clone();
+    remove_tokens(&mut config);
+    let mut hasher = DefaultHasher::new();
+    config.to_string().hash(&mut hasher);
+    // Keep 48 bits, which fit in the mantissa of the float metric value
+    hasher.finish() >> 16
+}
+
+/// Remove the tokens (e.g. of tenants) from the given configuration
+fn remove_tokens(config: &mut serde_json::Value) {
+    match config {
+        serde_json::Value::Object(object) => {
+            object.remove("token");
+            object.values_mut().for_each(remove_tokens);
+        }
+        serde_json::Value::Array(array) => array.iter_mut().for_each(remove_tokens),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn hashes_configs_without_their_tokens() {
+        let config = |token: &str, port: u16| {
+            json!({
+                "port": port,
+                "tenants": [{"name": "alice", "token": token, "paths": ["/srv/alice/*"]}],
+            })
+        };
+        assert_eq!(
+            config_hash(&config("secret", 9179)),
+            config_hash(&config("other", 9179))
+        );
+        assert_ne!(
+            config_hash(&config("secret", 9179)),
+            config_hash(&config("secret", 9180))
+        );
+        assert!(config_hash(&config("secret", 9179)) < 1 << 48);
+    }
+}
@@ -0,0 +1,215 @@
+//! Details of containers that `docker compose ps` doesn't report (e.g. their
+//! restart count), kept between scrapes so containers that haven't started or
+//! stopped since they were last inspected aren't inspected again
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Time after which containers are inspected again regardless, as statuses
+/// only tell start times apart to the second within their first minute
+static MAX_AGE: Duration = Duration::from_secs(60);
+
+struct CachedDetails<T> {
+    /// When the container's last run started, in seconds since the epoch
+    started_at: Option<f64>,
+    /// When the container's last run finished, in seconds since the epoch
+    finished_at: Option<f64>,
+    inspected_at: Instant,
+    details: T,
+}
+
+/// Details of containers by ID, valid as long as the container's last run is
+/// still the one it was inspected during
+pub struct ContainerDetailsCache<T>(Mutex<HashMap<String, CachedDetails<T>>>);
+
+impl<T> Default for ContainerDetailsCache<T> {
+    fn default() -> Self {
+        ContainerDetailsCache(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<T: Clone> ContainerDetailsCache<T> {
+    /// Get the details of the container with the given ID, if it was inspected
+    /// recently and its status as reported by `docker compose ps` (e.g. 'Up 5
+    /// minutes (healthy)') shows it hasn't started or stopped since
+    pub fn get(&self, id: &str, status: &str) -> Option<T> {
+        let cache = self
+            .0
+            .lock()
+            .expect("container details lock shouldn't be poisoned");
+        let cached = cache.get(id)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        (cached.inspected_at.elapsed() < MAX_AGE
+            && status_matches(status, cached.started_at, cached.finished_at, now))
+        .then(|| cached.details.clone())
+    }
+
+    /// Cache the details of the container with the given ID, inspected during
+    /// the run that started and finished (if it did) at the given times
+    pub fn insert(
+        &self,
+        id: String,
+        started_at: Option<f64>,
+        finished_at: Option<f64>,
+        details: T,
+    ) {
+        let mut cache = self
+            .0
+            .lock()
+            .expect("container details lock shouldn't be poisoned");
+        // Also forgets removed containers
+        cache.retain(|_, cached| cached.inspected_at.elapsed() < MAX_AGE);
+        cache.insert(
+            id,
+            CachedDetails {
+                started_at,
+                finished_at,
+                inspected_at: Instant::now(),
+                details,
+            },
+        );
+    }
+}
+
+/// Whether the given container status is the one docker would show at the
+/// given time for a run that started and finished at the given times, i.e.
+/// the container hasn't started or stopped since. Statuses of other states
+/// (e.g. restarting) never match.
+fn status_matches(
+    status: &str,
+    started_at: Option<f64>,
+    finished_at: Option<f64>,
+    now: f64,
+) -> bool {
+    let (since, at) = if let Some(uptime) = status.strip_prefix("Up ") {
+        (uptime, started_at)
+    } else if let Some(exited) = status.strip_prefix("Exited (") {
+        let since = exited
+            .split_once(") ")
+            .and_then(|(_, since)| since.strip_suffix(" ago"));
+        match since {
+            Some(since) => (since, finished_at),
+            None => return false,
+        }
+    } else {
+        return false;
+    };
+    let Some(at) = at else {
+        return false;
+    };
+    // Without the health or pause suffix, e.g. ' (healthy)'
+    let since = since.split(" (").next().unwrap_or_default();
+    // The status was formatted a moment before now
+    human_duration(now - at) == since || human_duration(now - at - 1.0) == since
+}
+
+/// Format a duration in seconds the way docker does in container statuses,
+/// e.g. '5 minutes'
+fn human_duration(seconds: f64) -> String {
+    let seconds = seconds as i64;
+    let minutes = seconds / 60;
+    // Rounded, unlike the other units
+    let hours = (seconds as f64 / 3600.0 + 0.5) as i64;
+    if seconds < 1 {
+        "Less than a second".to_string()
+    } else if seconds == 1 {
+        "1 second".to_string()
+    } else if seconds < 60 {
+        format!("{} seconds", seconds)
+    } else if minutes == 1 {
+        "About a minute".to_string()
+    } else if minutes < 60 {
+        format!("{} minutes", minutes)
+    } else if hours == 1 {
+        "About an hour".to_string()
+    } else if hours < 48 {
+        format!("{} hours", hours)
+    } else if hours < 24 * 7 * 2 {
+        format!("{} days", hours / 24)
+    } else if hours < 24 * 30 * 2 {
+        format!("{} weeks", hours / 24 / 7)
+    } else if hours < 24 * 365 * 2 {
+        format!("{} months", hours / 24 / 30)
+    } else {
+        format!("{} years", seconds / 3600 / 24 / 365)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_durations_like_docker() {
+        assert_eq!(human_duration(0.4), "Less than a second");
+        assert_eq!(human_duration(1.9), "1 second");
+        assert_eq!(human_duration(59.0), "59 seconds");
+        assert_eq!(human_duration(119.0), "About a minute");
+        assert_eq!(human_duration(5.0 * 60.0), "5 minutes");
+        assert_eq!(human_duration(90.0 * 60.0), "2 hours");
+        assert_eq!(human_duration(3.0 * 86400.0), "3 days");
+        assert_eq!(human_duration(21.0 * 86400.0), "3 weeks");
+        assert_eq!(human_duration(90.0 * 86400.0), "3 months");
+        assert_eq!(human_duration(3.0 * 365.0 * 86400.0), "3 years");
+    }
+
+    #[test]
+    fn matches_statuses_of_the_same_run() {
+        let now = 1_000_000.0;
+        let started_at = Some(now - 300.0);
+        let finished_at = Some(now - 30.0);
+        assert!(status_matches("Up 5 minutes", started_at, None, now));
+        assert!(status_matches(
+            "Up 5 minutes (healthy)",
+            started_at,
+            None,
+            now
+        ));
+        assert!(status_matches(
+            "Up 5 minutes (Paused)",
+            started_at,
+            None,
+            now
+        ));
+        assert!(status_matches(
+            "Exited (1) 30 seconds ago",
+            started_at,
+            finished_at,
+            now
+        ));
+        // Formatted a moment before now
+        assert!(status_matches(
+            "Exited (1) 29 seconds ago",
+            started_at,
+            finished_at,
+            now
+        ));
+    }
+
+    #[test]
+    fn tells_restarted_containers_apart() {
+        let now = 1_000_000.0;
+        let started_at = Some(now - 300.0);
+        // Restarted since
+        assert!(!status_matches("Up 10 seconds", started_at, None, now));
+        assert!(!status_matches("Up 5 minutes", Some(now - 20.0), None, now));
+        // Stopped since
+        assert!(!status_matches(
+            "Exited (0) 10 seconds ago",
+            started_at,
+            None,
+            now
+        ));
+        assert!(!status_matches(
+            "Restarting (1) 2 seconds ago",
+            started_at,
+            None,
+            now
+        ));
+        assert!(!status_matches("Created", None, None, now));
+    }
+}
@@ -0,0 +1,216 @@
+//! Cross-origin resource sharing (CORS) headers, so browser-based consumers
+//! served from other origins can query the exporter
+
+use hyper::http::HeaderValue;
+use hyper::{header, Body, HeaderMap, Method, Request, Response, StatusCode};
+use std::str::FromStr;
+
+/// How long browsers may cache preflight responses
+static PREFLIGHT_MAX_AGE_SECONDS: &str = "600";
+
+pub struct Cors {
+    /// Allowed origins (e.g. 'https://status.example.com'), or '*' for any
+    allowed_origins: Vec<String>,
+    allowed_methods: HeaderValue,
+}
+
+impl Cors {
+    pub fn new(
+        allowed_origins: Vec<String>,
+        allowed_methods: &[String],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        for allowed_method in allowed_methods {
+            Method::from_str(allowed_method)
+                .map_err(|_| format!("Invalid CORS method {:?}", allowed_method))?;
+        }
+        Ok(Cors {
+            allowed_origins,
+            allowed_methods: HeaderValue::from_str(&allowed_methods.join(", "))?,
+        })
+    }
+
+    /// Get the value of the `Access-Control-Allow-Origin` header for a request
+    /// with the given headers, if its origin is allowed
+    fn allow_origin(&self, request_headers: &HeaderMap) -> Option<HeaderValue> {
+        let origin = request_headers.get(header::ORIGIN)?;
+        self.allowed_origins
+            .iter()
+            .find(|allowed_origin| *allowed_origin == "*" || origin == allowed_origin.as_str())
+            .map(|allowed_origin| {
+                if allowed_origin == "*" {
+                    HeaderValue::from_static("*")
+                } else {
+                    origin.clone()
+                }
+            })
+    }
+
+    /// Get the response to the given request if it's a CORS preflight request
+    pub fn preflight_response(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        if self.allowed_origins.is_empty()
+            || req.method() != Method::OPTIONS
+            || !req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            return None;
+        }
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        if let Some(allow_origin) = self.allow_origin(req.headers()) {
+            let headers = response.headers_mut();
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                self.allowed_methods.clone(),
+            );
+            if let Some(request_headers) = req.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            {
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_HEADERS,
+                    request_headers.clone(),
+                );
+            }
+            headers.insert(
+                header::ACCESS_CONTROL_MAX_AGE,
+                HeaderValue::from_static(PREFLIGHT_MAX_AGE_SECONDS),
+            );
+        }
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("Origin"));
+        Some(response)
+    }
+
+    /// Add the CORS headers to the response of a (non-preflight) request with
+    /// the given headers
+    pub fn apply(&self, request_headers: &HeaderMap, response: &mut Response<Body>) {
+        if self.allowed_origins.is_empty() {
+            return;
+        }
+        if let Some(allow_origin) = self.allow_origin(request_headers) {
+            response
+                .headers_mut()
+                .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        }
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(allowed_origins: &[&str]) -> Cors {
+        Cors::new(
+            allowed_origins
+                .iter()
+                .map(|origin| origin.to_string())
+                .collect(),
+            &["GET".to_string(), "OPTIONS".to_string()],
+        )
+        .expect("CORS config should be valid")
+    }
+
+    fn request(method: Method, headers: &[(&'static str, &'static str)]) -> Request<Body> {
+        let mut request = Request::builder().method(method).uri("/metrics");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn validates_methods() {
+        assert!(Cors::new(vec![], &["GET".to_string(), "GE T".to_string()]).is_err());
+    }
+
+    #[test]
+    fn allows_configured_origins() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ORIGIN,
+            HeaderValue::from_static("https://status.example.com"),
+        );
+        assert_eq!(
+            cors(&["https://other.example.com", "https://status.example.com"])
+                .allow_origin(&headers),
+            Some(HeaderValue::from_static("https://status.example.com"))
+        );
+        assert_eq!(
+            cors(&["*"]).allow_origin(&headers),
+            Some(HeaderValue::from_static("*"))
+        );
+        assert_eq!(
+            cors(&["https://other.example.com"]).allow_origin(&headers),
+            None
+        );
+        assert_eq!(cors(&["*"]).allow_origin(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn responds_to_preflight_requests() {
+        let cors = cors(&["https://status.example.com"]);
+        let response = cors
+            .preflight_response(&request(
+                Method::OPTIONS,
+                &[
+                    ("origin", "https://status.example.com"),
+                    ("access-control-request-method", "GET"),
+                    ("access-control-request-headers", "authorization"),
+                ],
+            ))
+            .expect("preflight request should get a response");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://status.example.com"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, OPTIONS"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "authorization"
+        );
+        assert_eq!(headers[header::VARY], "Origin");
+
+        // Not preflight requests
+        assert!(cors
+            .preflight_response(&request(
+                Method::OPTIONS,
+                &[("origin", "https://status.example.com")]
+            ))
+            .is_none());
+        assert!(cors
+            .preflight_response(&request(
+                Method::GET,
+                &[
+                    ("origin", "https://status.example.com"),
+                    ("access-control-request-method", "GET")
+                ]
+            ))
+            .is_none());
+    }
+
+    #[test]
+    fn adds_headers_to_responses() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ORIGIN,
+            HeaderValue::from_static("https://status.example.com"),
+        );
+        let mut response = Response::new(Body::empty());
+        cors(&["*"]).apply(&headers, &mut response);
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(response.headers()[header::VARY], "Origin");
+
+        let mut response = Response::new(Body::empty());
+        cors(&[]).apply(&headers, &mut response);
+        assert!(response.headers().is_empty());
+    }
+}
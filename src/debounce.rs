@@ -0,0 +1,204 @@
+//! Debouncing of service state transitions, so momentary restarts between
+//! collections don't make alerts flap
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Number of consecutive collections a new value of each state metric must be
+/// observed in before it's exported (0 or 1 exports it right away)
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DebounceConfig {
+    #[serde(default)]
+    pub health: u32,
+    #[serde(default)]
+    pub state: u32,
+}
+
+struct Debounced {
+    exported: String,
+    /// Value differing from the exported one, and the number of consecutive
+    /// collections it was observed in
+    candidate: Option<(String, u32)>,
+}
+
+/// Exported values of the state metrics, by metric, compose and service name
+#[derive(Default)]
+pub struct Debouncer {
+    values: Mutex<HashMap<(&'static str, String, String), Debounced>>,
+}
+
+impl Debouncer {
+    /// Get the currently exported value of a state metric of a service, if
+    /// any was observed yet
+    pub fn exported(
+        &self,
+        metric_name: &'static str,
+        compose_name: &str,
+        service_name: &str,
+    ) -> Option<String> {
+        let values = self
+            .values
+            .lock()
+            .expect("debounced values lock shouldn't be poisoned");
+        let key = (
+            metric_name,
+            compose_name.to_string(),
+            service_name.to_string(),
+        );
+        values.get(&key).map(|debounced| debounced.exported.clone())
+    }
+
+    /// Observe the given value of a state metric of a service, returning the
+    /// value to export: the new value once it has been observed `required`
+    /// times in a row, and the previously exported one until then
+    pub fn observe(
+        &self,
+        metric_name: &'static str,
+        compose_name: &str,
+        service_name: &str,
+        value: &str,
+        required: u32,
+    ) -> String {
+        let mut values = self
+            .values
+            .lock()
+            .expect("debounced values lock shouldn't be poisoned");
+        let key = (
+            metric_name,
+            compose_name.to_string(),
+            service_name.to_string(),
+        );
+        let debounced = values.entry(key).or_insert_with(|| Debounced {
+            exported: value.to_string(),
+            candidate: None,
+        });
+        if debounced.exported == value {
+            debounced.candidate = None;
+            return value.to_string();
+        }
+        let nbro_observations = match &debounced.candidate {
+            Some((candidate, nbro_observations)) if candidate == value => nbro_observations + 1,
+            _ => 1,
+        };
+        if nbro_observations >= required {
+            debounced.exported = value.to_string();
+            debounced.candidate = None;
+        } else {
+            debounced.candidate = Some((value.to_string(), nbro_observations));
+        }
+        debounced.exported.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_first_value_right_away() {
+        let debouncer = Debouncer::default();
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "running", 3),
+            "running"
+        );
+    }
+
+    #[test]
+    fn exports_new_value_after_required_observations() {
+        let debouncer = Debouncer::default();
+        debouncer.observe("state", "web", "app", "running", 3);
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "exited", 3),
+            "running"
+        );
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "exited", 3),
+            "running"
+        );
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "exited", 3),
+            "exited"
+        );
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "exited", 3),
+            "exited"
+        );
+    }
+
+    #[test]
+    fn resets_on_interrupted_transitions() {
+        let debouncer = Debouncer::default();
+        debouncer.observe("state", "web", "app", "running", 2);
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "exited", 2),
+            "running"
+        );
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "running", 2),
+            "running"
+        );
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "exited", 2),
+            "running"
+        );
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "restarting", 2),
+            "running"
+        );
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "restarting", 2),
+            "restarting"
+        );
+    }
+
+    #[test]
+    fn exports_right_away_without_debouncing() {
+        let debouncer = Debouncer::default();
+        for required in [0, 1] {
+            debouncer.observe("health", "web", "app", "healthy", required);
+            assert_eq!(
+                debouncer.observe("health", "web", "app", "unhealthy", required),
+                "unhealthy"
+            );
+        }
+    }
+
+    #[test]
+    fn debounces_per_metric_and_service() {
+        let debouncer = Debouncer::default();
+        debouncer.observe("state", "web", "app", "running", 2);
+        debouncer.observe("health", "web", "app", "healthy", 2);
+        assert_eq!(
+            debouncer.observe("state", "web", "db", "exited", 2),
+            "exited"
+        );
+        assert_eq!(
+            debouncer.observe("health", "web", "app", "unhealthy", 2),
+            "healthy"
+        );
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "running", 2),
+            "running"
+        );
+    }
+
+    #[test]
+    fn reads_exported_values_without_observing() {
+        let debouncer = Debouncer::default();
+        assert_eq!(debouncer.exported("state", "web", "app"), None);
+        debouncer.observe("state", "web", "app", "running", 2);
+        debouncer.observe("state", "web", "app", "exited", 2);
+        assert_eq!(
+            debouncer.exported("state", "web", "app"),
+            Some("running".to_string())
+        );
+        // Reading doesn't count as an observation
+        debouncer.exported("state", "web", "app");
+        assert_eq!(
+            debouncer.observe("state", "web", "app", "exited", 2),
+            "exited"
+        );
+    }
+}
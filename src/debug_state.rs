@@ -0,0 +1,129 @@
+//! The `/debug/state` endpoint, dumping the exporter's internal state for
+//! remote debugging
+
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::overlapping_scrapes::{InFlightState, LastResultState};
+use crate::scrape_error::ErrorCode;
+use crate::{config_paths_from_globs, Exporter, ScrapeRecord};
+
+#[derive(Serialize)]
+pub struct DebugState {
+    version: &'static str,
+    uptime_seconds: f64,
+    docker: DockerState,
+    compose_configs_glob: Vec<String>,
+    /// Config paths the globs currently resolve to
+    discovered_config_paths: Vec<PathBuf>,
+    discovery_error: Option<String>,
+    last_scrape: Option<ScrapeState>,
+    background_collection: BackgroundCollectionState,
+    in_flight_collections: Vec<InFlightState>,
+    /// Results of collections kept to serve scrapes within the minimum
+    /// (scrape) interval
+    last_results: Vec<LastResultState>,
+}
+
+#[derive(Serialize)]
+struct BackgroundCollectionState {
+    /// `None` if apps aren't collected in the background
+    interval_seconds: Option<f64>,
+    last_collection: Option<ScrapeState>,
+    /// Whether no background collection has finished for more than twice the
+    /// interval, e.g. because the task died or collections hang
+    overdue: bool,
+}
+
+#[derive(Serialize)]
+struct DockerState {
+    host: Option<String>,
+    command_prefix: Vec<String>,
+    docker_version: String,
+    compose_version: String,
+}
+
+#[derive(Serialize)]
+struct ScrapeState {
+    timestamp_seconds: f64,
+    age_seconds: f64,
+    duration_seconds: f64,
+    error: Option<String>,
+    error_code: Option<ErrorCode>,
+}
+
+impl ScrapeState {
+    fn of(scrape: &ScrapeRecord) -> Self {
+        ScrapeState {
+            timestamp_seconds: unix_seconds(scrape.at),
+            age_seconds: scrape.at.elapsed().unwrap_or_default().as_secs_f64(),
+            duration_seconds: scrape.duration.as_secs_f64(),
+            error: scrape.error.as_ref().map(|e| e.to_string()),
+            error_code: scrape.error.as_ref().map(|e| e.code),
+        }
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs_f64()
+}
+
+impl DebugState {
+    pub fn of(exporter: &Exporter) -> Self {
+        let config = exporter.config();
+        let docker = &exporter.docker;
+        let (discovered_config_paths, discovery_error) = match config_paths_from_globs(
+            &config.compose_configs_glob,
+            &config.compose_configs_dir,
+            &config.discovery,
+        ) {
+            Ok(paths) => (paths, None),
+            Err(e) => (vec![], Some(e.to_string())),
+        };
+        let last_scrape = exporter
+            .last_scrape
+            .lock()
+            .expect("last scrape lock shouldn't be poisoned")
+            .as_ref()
+            .map(ScrapeState::of);
+        let last_background_collection = exporter
+            .last_background_collection
+            .lock()
+            .expect("last background collection lock shouldn't be poisoned")
+            .as_ref()
+            .map(ScrapeState::of);
+        let collect_interval = Some(config.collect_interval).filter(|interval| !interval.is_zero());
+        let background_collection = BackgroundCollectionState {
+            interval_seconds: collect_interval.map(|interval| interval.as_secs_f64()),
+            overdue: collect_interval.is_some_and(|interval| {
+                let since_last_collection = last_background_collection
+                    .as_ref()
+                    .map_or(exporter.started_at.elapsed().as_secs_f64(), |collection| {
+                        collection.age_seconds
+                    });
+                since_last_collection > 2.0 * interval.as_secs_f64()
+            }),
+            last_collection: last_background_collection,
+        };
+        DebugState {
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_seconds: exporter.started_at.elapsed().as_secs_f64(),
+            docker: DockerState {
+                host: docker.host.clone(),
+                command_prefix: docker.command_prefix.clone(),
+                docker_version: docker.compat.docker_version.to_string(),
+                compose_version: docker.compat.compose_version.to_string(),
+            },
+            compose_configs_glob: config.compose_configs_glob.clone(),
+            discovered_config_paths,
+            discovery_error,
+            last_scrape,
+            background_collection,
+            in_flight_collections: exporter.in_flight_collections.in_flight(),
+            last_results: exporter.in_flight_collections.last_results(),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Whether the `depends_on` conditions of services are currently satisfied,
+//! making e.g. "app is down because its database is unhealthy" visible
+
+use crate::compose_config::ComposeConfig;
+use crate::{replicas, Container};
+
+/// Whether a dependency in the given state satisfies the given `depends_on`
+/// condition, or none if the condition is unknown
+fn is_satisfied(condition: &str, container: Option<&Container>) -> Option<bool> {
+    let state = container.map(|container| container.state.as_str());
+    let health = container.and_then(|container| container.health.as_deref());
+    Some(match condition {
+        "service_started" => state == Some("running"),
+        "service_healthy" => state == Some("running") && health == Some("healthy"),
+        "service_completed_successfully" => {
+            state == Some("exited") && container.and_then(|c| c.exit_code) == Some(0)
+        }
+        _ => return None,
+    })
+}
+
+/// Get the dependencies of the given service, with their condition and whether
+/// it's currently satisfied, sorted by dependency name
+pub fn dependencies<'a>(
+    compose_config: &'a ComposeConfig,
+    service_name: &str,
+    running_containers: &[Container],
+) -> Vec<(&'a str, &'a str, bool)> {
+    let Some(service) = compose_config.services.get(service_name) else {
+        return vec![];
+    };
+    let mut dependencies: Vec<_> = service
+        .depends_on
+        .iter()
+        .filter_map(|(dependency_name, dependency)| {
+            // Of the first container, if the dependency is scaled
+            let container =
+                compose_config
+                    .services
+                    .get(dependency_name)
+                    .and_then(|dependency_service| {
+                        replicas::service_containers(
+                            running_containers,
+                            &compose_config.name,
+                            dependency_name,
+                            dependency_service,
+                        )
+                        .first()
+                        .map(|(_, container)| *container)
+                    });
+            let satisfied = is_satisfied(&dependency.condition, container)?;
+            Some((
+                dependency_name.as_str(),
+                dependency.condition.as_str(),
+                satisfied,
+            ))
+        })
+        .collect();
+    dependencies.sort();
+    dependencies
+}
@@ -0,0 +1,275 @@
+//! Expansion of the compose configs globs, including recursive `**` patterns
+//! with a depth limit, a symlink policy and excluded directories, so deeply
+//! nested stack layouts can be covered by one pattern without walking (or
+//! looping through) the whole file system
+
+use clap::ValueEnum;
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+/// What to do with symlinks below the fixed part of a glob
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Match symlinks and descend into symlinked directories
+    #[default]
+    Follow,
+    /// Ignore symlinks
+    Skip,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryOptions {
+    /// Maximum number of path components a glob may match below its fixed
+    /// part (e.g. `/srv` for `/srv/**/compose.yaml`)
+    pub max_depth: Option<usize>,
+    pub symlinks: SymlinkPolicy,
+    /// Names of directories not to descend into, e.g. `.git` or `backup*`
+    pub exclude_dirs: Vec<Pattern>,
+    /// Globs of paths to skip, along with everything below them, e.g.
+    /// `/etc/compose-apps/_disabled/*`
+    pub exclude_globs: Vec<Pattern>,
+}
+
+enum GlobComponent {
+    /// `**`: any number of directories
+    Recursive,
+    Pattern(Pattern),
+}
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+fn has_wildcards(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+/// Split a glob into its fixed part and the patterns of its remaining
+/// components
+fn split_glob(glob: &str) -> Result<(PathBuf, Vec<GlobComponent>), String> {
+    let mut fixed = PathBuf::new();
+    let mut components = vec![];
+    for component in Path::new(glob).components() {
+        let text = component.as_os_str().to_string_lossy();
+        if components.is_empty() && !has_wildcards(&text) {
+            fixed.push(component);
+        } else if let Component::Normal(_) = component {
+            components.push(if text == "**" {
+                GlobComponent::Recursive
+            } else {
+                GlobComponent::Pattern(
+                    Pattern::new(&text).map_err(|err| format!("Invalid glob: {}", err))?,
+                )
+            });
+        } else if component != Component::CurDir {
+            return Err(format!("Invalid glob: {:?} after a wildcard", text));
+        }
+    }
+    Ok((fixed, components))
+}
+
+struct Walk<'a> {
+    options: &'a DiscoveryOptions,
+    components: &'a [GlobComponent],
+    matches: Vec<PathBuf>,
+    /// Directories `**` was expanded in (canonicalized), to not loop through
+    /// symlinks
+    recursed_into: HashSet<(PathBuf, usize)>,
+}
+
+impl Walk<'_> {
+    /// Entries of the given directory, with whether each is a directory,
+    /// sorted and following the symlink policy
+    fn entries(&self, dir: &Path) -> Vec<(PathBuf, bool)> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return vec![];
+        };
+        let mut entries: Vec<_> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let file_type = entry.file_type().ok()?;
+                let is_dir = if file_type.is_symlink() {
+                    if self.options.symlinks == SymlinkPolicy::Skip {
+                        return None;
+                    }
+                    entry.path().is_dir()
+                } else {
+                    file_type.is_dir()
+                };
+                Some((entry.path(), is_dir))
+            })
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    fn is_excluded(&self, dir: &Path) -> bool {
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        self.options
+            .exclude_dirs
+            .iter()
+            .any(|pattern| pattern.matches(&name))
+    }
+
+    /// Match the glob components from `index` on against the entries below
+    /// `dir`, which is `depth` components below the fixed part
+    fn walk(&mut self, dir: &Path, index: usize, depth: usize) {
+        let Some(component) = self.components.get(index) else {
+            return;
+        };
+        let can_descend = self
+            .options
+            .max_depth
+            .is_none_or(|max_depth| depth < max_depth);
+        match component {
+            GlobComponent::Recursive => {
+                let canonical_dir = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.into());
+                if !self.recursed_into.insert((canonical_dir, index)) {
+                    return;
+                }
+                // `**` matching no directories
+                if index + 1 == self.components.len() {
+                    self.matches.push(dir.to_path_buf());
+                } else {
+                    self.walk(dir, index + 1, depth);
+                }
+                if !can_descend {
+                    return;
+                }
+                for (path, is_dir) in self.entries(dir) {
+                    if is_dir && !self.is_excluded(&path) {
+                        self.walk(&path, index, depth + 1);
+                    }
+                }
+            }
+            GlobComponent::Pattern(pattern) => {
+                if !can_descend {
+                    return;
+                }
+                let is_last = index + 1 == self.components.len();
+                for (path, is_dir) in self.entries(dir) {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    if !pattern.matches_with(&name, MATCH_OPTIONS) {
+                        continue;
+                    }
+                    if is_last {
+                        self.matches.push(path);
+                    } else if is_dir && !self.is_excluded(&path) {
+                        self.walk(&path, index + 1, depth + 1);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether the given path, or a directory it's in, matches one of the given
+/// exclude globs
+fn is_excluded(path: &Path, exclude_globs: &[Pattern]) -> bool {
+    path.ancestors().any(|ancestor| {
+        exclude_globs
+            .iter()
+            .any(|exclude_glob| exclude_glob.matches_path_with(ancestor, MATCH_OPTIONS))
+    })
+}
+
+/// Expand the given glob to the (existing) paths it matches that aren't
+/// excluded, sorted
+pub fn expand(glob: &str, options: &DiscoveryOptions) -> Result<Vec<PathBuf>, String> {
+    let (fixed, components) = split_glob(glob)?;
+    if components.is_empty() {
+        return Ok(if fixed.exists() { vec![fixed] } else { vec![] });
+    }
+    // Relative to the working directory
+    let relative = fixed.as_os_str().is_empty();
+    let fixed = if relative { PathBuf::from(".") } else { fixed };
+    let mut walk = Walk {
+        options,
+        components: &components,
+        matches: vec![],
+        recursed_into: HashSet::new(),
+    };
+    walk.walk(&fixed, 0, 0);
+    let mut matches = walk.matches;
+    if relative {
+        for path in &mut matches {
+            if let Ok(stripped) = path.strip_prefix(".") {
+                *path = stripped.to_path_buf();
+            }
+        }
+    }
+    matches.retain(|path| !is_excluded(path, &options.exclude_globs));
+    matches.sort();
+    matches.dedup();
+    Ok(matches)
+}
+
+/// Maximum number of directories a scan descends below the scanned directory,
+/// if `max_depth` isn't set
+static DEFAULT_SCAN_MAX_DEPTH: usize = 5;
+
+/// Scan the given directory tree for directories containing a compose file,
+/// getting the compose file of each, sorted
+pub fn scan(directory: &Path, options: &DiscoveryOptions) -> Result<Vec<PathBuf>, String> {
+    let options = DiscoveryOptions {
+        max_depth: Some(options.max_depth.unwrap_or(DEFAULT_SCAN_MAX_DEPTH)),
+        ..options.clone()
+    };
+    let glob = Path::new(&Pattern::escape(&directory.to_string_lossy())).join("**");
+    Ok(expand(&glob.to_string_lossy(), &options)?
+        .into_iter()
+        .filter(|path| {
+            COMPOSE_FILE_NAMES
+                .iter()
+                .any(|name| path.join(name).is_file())
+        })
+        .map(|path| compose_file_in(&path))
+        .collect())
+}
+
+/// Names of the compose file of an app in a matched directory, in the order of
+/// preference of docker compose
+static COMPOSE_FILE_NAMES: [&str; 4] = [
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+
+/// Get the compose file of the app in the given directory. If there's none,
+/// this is `docker-compose.yml`, so the app is reported as having an invalid
+/// config.
+pub fn compose_file_in(directory: &Path) -> PathBuf {
+    COMPOSE_FILE_NAMES
+        .iter()
+        .map(|name| directory.join(name))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| directory.join("docker-compose.yml"))
+}
+
+/// Whether the given file overrides another compose file (e.g.
+/// `compose.override.yaml`), rather than being one of its own
+pub fn is_override_file(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| Path::new(stem).extension())
+        .is_some_and(|extension| extension == "override")
+}
+
+/// Get the existing override files of the given compose file, which docker
+/// compose merges into it when run without `-f`, e.g.
+/// `docker-compose.override.yml` for `docker-compose.yml`
+pub fn override_files(config_path: &Path) -> Vec<PathBuf> {
+    let Some(stem) = config_path.file_stem().and_then(|stem| stem.to_str()) else {
+        return vec![];
+    };
+    ["yml", "yaml"]
+        .iter()
+        .map(|extension| config_path.with_file_name(format!("{}.override.{}", stem, extension)))
+        .filter(|path| path.is_file())
+        .collect()
+}
@@ -0,0 +1,135 @@
+//! Recurring downtime windows, during which services are allowed to be down
+//! (e.g. nightly backups stopping a database)
+
+use chrono::{DateTime, Local, TimeDelta};
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Downtime window of an app or service, as configured
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DowntimeWindowConfig {
+    pub compose_name: String,
+    /// If not set, the window applies to all services of the app
+    #[serde(default)]
+    pub service_name: Option<String>,
+    /// Cron expression of when the window starts, e.g. '0 3 * * *' for 3 AM
+    /// every day (in the exporter's local time zone)
+    pub schedule: String,
+    pub duration_minutes: u32,
+}
+
+pub struct DowntimeWindow {
+    compose_name: String,
+    service_name: Option<String>,
+    schedule: Schedule,
+    duration: TimeDelta,
+}
+
+impl TryFrom<DowntimeWindowConfig> for DowntimeWindow {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(config: DowntimeWindowConfig) -> Result<Self, Self::Error> {
+        // The cron crate expects a leading seconds field, which standard
+        // (5-field) cron expressions don't have
+        let schedule = if config.schedule.split_whitespace().count() == 5 {
+            format!("0 {}", config.schedule)
+        } else {
+            config.schedule.clone()
+        };
+        let schedule = Schedule::from_str(&schedule).map_err(|err| {
+            format!(
+                "Invalid downtime window schedule {:?} for {}: {}",
+                config.schedule, config.compose_name, err
+            )
+        })?;
+        Ok(DowntimeWindow {
+            compose_name: config.compose_name,
+            service_name: config.service_name,
+            schedule,
+            duration: TimeDelta::minutes(config.duration_minutes.into()),
+        })
+    }
+}
+
+impl DowntimeWindow {
+    fn applies_to(&self, compose_name: &str, service_name: &str) -> bool {
+        self.compose_name == compose_name
+            && self
+                .service_name
+                .as_ref()
+                .is_none_or(|window_service| window_service == service_name)
+    }
+
+    /// Whether the window is open at the given time, i.e. whether it last
+    /// started less than its duration before it
+    fn is_open(&self, now: DateTime<Local>) -> bool {
+        self.schedule
+            .after(&(now - self.duration))
+            .next()
+            .is_some_and(|start| start <= now)
+    }
+}
+
+/// Whether the given service is currently in one of the given downtime windows
+pub fn is_in_downtime(windows: &[DowntimeWindow], compose_name: &str, service_name: &str) -> bool {
+    let now = Local::now();
+    windows
+        .iter()
+        .any(|window| window.applies_to(compose_name, service_name) && window.is_open(now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn window(service_name: Option<&str>, schedule: &str, duration_minutes: u32) -> DowntimeWindow {
+        DowntimeWindow::try_from(DowntimeWindowConfig {
+            compose_name: "db".to_string(),
+            service_name: service_name.map(String::from),
+            schedule: schedule.to_string(),
+            duration_minutes,
+        })
+        .expect("window should be valid")
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 3, 12, hour, minute, 0)
+            .single()
+            .expect("time shouldn't be ambiguous")
+    }
+
+    #[test]
+    fn parses_schedules() {
+        assert!(DowntimeWindow::try_from(DowntimeWindowConfig {
+            compose_name: "db".to_string(),
+            service_name: None,
+            schedule: "every night".to_string(),
+            duration_minutes: 30,
+        })
+        .is_err());
+        // With and without the seconds field
+        window(None, "0 3 * * *", 30);
+        window(None, "0 0 3 * * *", 30);
+    }
+
+    #[test]
+    fn applies_to_app_or_service() {
+        assert!(window(None, "0 3 * * *", 30).applies_to("db", "postgres"));
+        assert!(window(Some("postgres"), "0 3 * * *", 30).applies_to("db", "postgres"));
+        assert!(!window(Some("postgres"), "0 3 * * *", 30).applies_to("db", "backup"));
+        assert!(!window(None, "0 3 * * *", 30).applies_to("web", "postgres"));
+    }
+
+    #[test]
+    fn is_open_for_its_duration() {
+        let window = window(None, "0 3 * * *", 30);
+        assert!(!window.is_open(at(2, 59)));
+        assert!(window.is_open(at(3, 0)));
+        assert!(window.is_open(at(3, 29)));
+        assert!(!window.is_open(at(3, 31)));
+        assert!(!window.is_open(at(15, 0)));
+    }
+}
@@ -0,0 +1,264 @@
+//! Querying the containers of compose apps from the docker engine API directly
+//! (rather than through `docker compose ps`), matching them to their project
+//! through the labels compose sets on them. The containers' details (e.g.
+//! their resource usage) are read from the API too, but the compose files are
+//! still parsed with the docker CLI.
+
+use bollard::container::{InspectContainerOptions, ListContainersOptions, Stats, StatsOptions};
+use bollard::models::ContainerInspectResponse;
+use bollard::Docker;
+use clap::ValueEnum;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::time::Instant;
+
+use crate::config_drift::CONFIG_HASH_LABEL;
+use crate::scrape_error::{ErrorCode, ScrapeError};
+use crate::telemetry;
+use crate::{
+    parse_engine_time, ComposeProject, Container, COMPOSE_VERSION_LABEL, CONTAINER_NUMBER_LABEL,
+};
+
+static PROJECT_LABEL: &str = "com.docker.compose.project";
+static CONFIG_FILES_LABEL: &str = "com.docker.compose.project.config_files";
+static ONEOFF_LABEL: &str = "com.docker.compose.oneoff";
+static SERVICE_LABEL: &str = "com.docker.compose.service";
+/// Seconds to wait for a response from the engine
+static TIMEOUT_SECONDS: u64 = 30;
+
+/// How to read the containers of apps
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DockerBackend {
+    /// Query the docker engine API
+    Api,
+    /// Run the docker CLI (`docker compose ps`)
+    Cli,
+}
+
+pub struct EngineApi {
+    docker: Docker,
+}
+
+impl EngineApi {
+    /// Connect to the engine at the given docker host, or the one `DOCKER_HOST`
+    /// points to (the local socket by default)
+    pub fn connect(host: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let version = bollard::API_DEFAULT_VERSION;
+        let docker = match host {
+            None => Docker::connect_with_defaults()?,
+            #[cfg(unix)]
+            Some(host) if host.starts_with("unix://") => {
+                Docker::connect_with_unix(host, TIMEOUT_SECONDS, version)?
+            }
+            #[cfg(windows)]
+            Some(host) if host.starts_with("npipe://") => {
+                Docker::connect_with_named_pipe(host, TIMEOUT_SECONDS, version)?
+            }
+            Some(host) if host.starts_with("tcp://") => {
+                Docker::connect_with_http(host, TIMEOUT_SECONDS, version)?
+            }
+            Some(host) => {
+                return Err(format!(
+                    "The docker engine API can't be reached at {}, use the cli docker backend",
+                    host
+                )
+                .into())
+            }
+        };
+        Ok(EngineApi { docker })
+    }
+
+    /// Send the given request of the given kind (for the exporter's own
+    /// metrics)
+    async fn request<T>(
+        &self,
+        kind: &str,
+        request: impl Future<Output = Result<T, bollard::errors::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let started_at = Instant::now();
+        let response = request.await;
+        telemetry::record_command(kind, started_at.elapsed(), response.is_err());
+        response.map_err(|err| {
+            let code = match err {
+                bollard::errors::Error::DockerResponseServerError { .. } => {
+                    ErrorCode::DockerCommandFailed
+                }
+                bollard::errors::Error::JsonDataError { .. }
+                | bollard::errors::Error::JsonSerdeError { .. } => ErrorCode::DockerOutputInvalid,
+                _ => ErrorCode::DockerUnavailable,
+            };
+            ScrapeError::new(code, format!("Docker engine API request failed: {}", err)).into()
+        })
+    }
+
+    /// Check that the engine responds, e.g. for readiness probes
+    pub async fn ping(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.request("api ping", self.docker.ping()).await?;
+        Ok(())
+    }
+
+    /// Read the running containers of the given compose project, like `docker
+    /// compose ps` (without one-off containers, e.g. of `docker compose run`)
+    pub async fn read_compose_containers(
+        &self,
+        project: &str,
+    ) -> Result<Vec<Container>, Box<dyn std::error::Error>> {
+        let filters = HashMap::from([(
+            "label".to_string(),
+            vec![
+                format!("{}={}", PROJECT_LABEL, project),
+                format!("{}=False", ONEOFF_LABEL),
+            ],
+        )]);
+        let summaries = self
+            .request(
+                "api container list",
+                self.docker.list_containers(Some(ListContainersOptions {
+                    filters,
+                    ..Default::default()
+                })),
+            )
+            .await?;
+        let mut containers = vec![];
+        for summary in summaries {
+            let Some(id) = summary.id else {
+                continue;
+            };
+            let inspected = self.inspect_container(&id).await?;
+            let state = inspected.state.unwrap_or_default();
+            let mut labels = inspected
+                .config
+                .and_then(|config| config.labels)
+                .unwrap_or_default();
+            containers.push(Container {
+                id: Some(id),
+                name: inspected
+                    .name
+                    .unwrap_or_default()
+                    .trim_start_matches('/')
+                    .to_string(),
+                service: labels.remove(SERVICE_LABEL).unwrap_or_default(),
+                number: labels
+                    .get(CONTAINER_NUMBER_LABEL)
+                    .and_then(|number| number.parse().ok()),
+                state: state
+                    .status
+                    .map(|status| status.to_string())
+                    .or(summary.state)
+                    .unwrap_or_default(),
+                status: summary.status,
+                // Like `docker compose ps`, empty for containers without a
+                // healthcheck
+                health: Some(
+                    state
+                        .health
+                        .and_then(|health| health.status)
+                        .map(|status| status.to_string())
+                        .filter(|status| status != "none")
+                        .unwrap_or_default(),
+                ),
+                exit_code: state.exit_code,
+                restart_count: inspected.restart_count,
+                oom_killed: state.oom_killed,
+                created_at: inspected.created.as_deref().and_then(parse_engine_time),
+                started_at: state.started_at.as_deref().and_then(parse_engine_time),
+                image_id: inspected.image,
+                compose_version: labels.remove(COMPOSE_VERSION_LABEL),
+                config_hash: labels.remove(CONFIG_HASH_LABEL),
+            });
+        }
+        Ok(containers)
+    }
+
+    /// Inspect the container with the given name or ID, like `docker inspect`
+    pub async fn inspect_container(
+        &self,
+        container: &str,
+    ) -> Result<ContainerInspectResponse, Box<dyn std::error::Error>> {
+        self.request(
+            "api container inspect",
+            self.docker
+                .inspect_container(container, None::<InspectContainerOptions>),
+        )
+        .await
+    }
+
+    /// Read the resource usage of the given (running) container, like `docker
+    /// stats --no-stream`. This takes a couple of seconds, as the engine
+    /// samples the CPU usage.
+    pub async fn read_container_stats(
+        &self,
+        container: &str,
+    ) -> Result<Stats, Box<dyn std::error::Error>> {
+        let options = StatsOptions {
+            stream: false,
+            one_shot: false,
+        };
+        self.request("api container stats", async {
+            self.docker
+                .stats(container, Some(options))
+                .next()
+                .await
+                .transpose()
+        })
+        .await?
+        .ok_or_else(|| {
+            ScrapeError::new(
+                ErrorCode::DockerOutputInvalid,
+                format!("Docker engine API returned no stats for {}", container),
+            )
+            .into()
+        })
+    }
+
+    /// Read all compose projects with containers on the engine, including
+    /// stopped ones, like `docker compose ls --all`
+    pub async fn read_compose_projects(
+        &self,
+    ) -> Result<Vec<ComposeProject>, Box<dyn std::error::Error>> {
+        let filters = HashMap::from([("label".to_string(), vec![PROJECT_LABEL.to_string()])]);
+        let summaries = self
+            .request(
+                "api container list",
+                self.docker.list_containers(Some(ListContainersOptions {
+                    all: true,
+                    filters,
+                    ..Default::default()
+                })),
+            )
+            .await?;
+        // Config files and number of containers per state, by project name
+        let mut projects: BTreeMap<String, (String, BTreeMap<String, usize>)> = BTreeMap::new();
+        for summary in summaries {
+            let Some(labels) = summary.labels else {
+                continue;
+            };
+            let Some(name) = labels.get(PROJECT_LABEL) else {
+                continue;
+            };
+            let (_, states) = projects.entry(name.clone()).or_insert_with(|| {
+                (
+                    labels.get(CONFIG_FILES_LABEL).cloned().unwrap_or_default(),
+                    BTreeMap::new(),
+                )
+            });
+            *states.entry(summary.state.unwrap_or_default()).or_default() += 1;
+        }
+        Ok(projects
+            .into_iter()
+            .map(|(name, (config_files, states))| ComposeProject {
+                name,
+                // In the format of `docker compose ls`
+                status: states
+                    .iter()
+                    .map(|(state, count)| format!("{}({})", state, count))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                config_files,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,81 @@
+//! The state services are expected to be in, so e.g. intentionally stopped
+//! one-shot services don't trigger alerts
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
+
+/// Compose service label declaring the expected state of the service
+pub static EXPECTED_STATE_LABEL: &str = "net.pfiers.compose-apps-exporter.expected-state";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpectedState {
+    #[default]
+    Running,
+    /// e.g. cron-style one-shots that only run now and then
+    Stopped,
+}
+
+impl ExpectedState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExpectedState::Running => "running",
+            ExpectedState::Stopped => "stopped",
+        }
+    }
+
+    /// Whether a service in the given state (see `POSSIBLE_STATES_STATE`) is
+    /// as expected
+    pub fn matches(&self, state: &str) -> bool {
+        match self {
+            ExpectedState::Running => state == "running",
+            ExpectedState::Stopped => {
+                matches!(state, "not_up" | "created" | "exited" | "dead")
+            }
+        }
+    }
+}
+
+impl FromStr for ExpectedState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "running" => Ok(ExpectedState::Running),
+            "stopped" => Ok(ExpectedState::Stopped),
+            _ => Err(format!(
+                "Invalid expected state {:?}, expected 'running' or 'stopped'",
+                s
+            )),
+        }
+    }
+}
+
+/// Expected state of a service, as configured
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExpectedStateRule {
+    pub compose_name: String,
+    pub service_name: String,
+    pub expected: ExpectedState,
+}
+
+/// Get the expected state of a service. The config file takes precedence over
+/// the service's label, and services are expected to be running by default.
+pub fn expected_state(
+    rules: &[ExpectedStateRule],
+    compose_name: &str,
+    service_name: &str,
+    labels: &HashMap<String, String>,
+) -> Result<ExpectedState, String> {
+    let rule = rules
+        .iter()
+        .find(|rule| rule.compose_name == compose_name && rule.service_name == service_name);
+    if let Some(rule) = rule {
+        return Ok(rule.expected);
+    }
+    labels
+        .get(EXPECTED_STATE_LABEL)
+        .map(|label| label.parse())
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
@@ -0,0 +1,546 @@
+//! Exposition of metric families in the format negotiated with the client,
+//! parsing of metrics in the Prometheus text format (e.g. of federated
+//! instances) into metric families, and merging of families
+
+use hyper::header;
+use hyper::HeaderMap;
+use prometheus::proto::{Bucket, Counter, Gauge, LabelPair, Metric, MetricFamily, MetricType};
+use prometheus::TextEncoder;
+use std::collections::HashMap;
+
+use crate::{openmetrics, protobuf, registry};
+
+static TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Format the metrics endpoints serve, negotiated with the `Accept` header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    OpenMetrics,
+    Protobuf,
+}
+
+impl Format {
+    /// Format the client (e.g. Prometheus) prefers according to the `Accept`
+    /// header of its request: the one with the highest quality (`q`), or the
+    /// text format if there's a tie or no `Accept` header
+    pub fn negotiate(headers: &HeaderMap) -> Format {
+        let Some(accept) = headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+        else {
+            return Format::Text;
+        };
+        let mut text_quality: f32 = 0.0;
+        let mut openmetrics_quality: f32 = 0.0;
+        let mut protobuf_quality: f32 = 0.0;
+        for media_range in accept.split(',') {
+            let mut parameters = media_range.split(';').map(str::trim);
+            let media_type = parameters.next().unwrap_or_default();
+            let mut quality = 1.0;
+            let mut is_metric_family = false;
+            let mut is_delimited = false;
+            let mut version = None;
+            for parameter in parameters {
+                match parameter.split_once('=') {
+                    Some(("q", value)) => quality = value.parse().unwrap_or(0.0),
+                    Some(("proto", "io.prometheus.client.MetricFamily")) => is_metric_family = true,
+                    Some(("encoding", "delimited")) => is_delimited = true,
+                    Some(("version", value)) => version = Some(value),
+                    _ => {}
+                }
+            }
+            match media_type {
+                "application/vnd.google.protobuf" if is_metric_family && is_delimited => {
+                    protobuf_quality = protobuf_quality.max(quality)
+                }
+                // Version 0.0.1 is the pre-release version of OpenMetrics 1.0.0
+                "application/openmetrics-text"
+                    if matches!(version, None | Some("1.0.0") | Some("0.0.1")) =>
+                {
+                    openmetrics_quality = openmetrics_quality.max(quality)
+                }
+                "text/plain" | "text/*" | "*/*" => text_quality = text_quality.max(quality),
+                _ => {}
+            }
+        }
+        if protobuf_quality > text_quality && protobuf_quality >= openmetrics_quality {
+            Format::Protobuf
+        } else if openmetrics_quality > text_quality {
+            Format::OpenMetrics
+        } else {
+            Format::Text
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Text => TEXT_CONTENT_TYPE,
+            Format::OpenMetrics => openmetrics::OPENMETRICS_CONTENT_TYPE,
+            Format::Protobuf => protobuf::PROTOBUF_CONTENT_TYPE,
+        }
+    }
+
+    /// Encode the given metric families, none of which may be empty, in the
+    /// format
+    pub fn encode(self, families: &[MetricFamily]) -> Vec<u8> {
+        match self {
+            Format::Text => TextEncoder::new()
+                .encode_to_string(families)
+                .expect("metric families with samples should encode")
+                .into_bytes(),
+            Format::OpenMetrics => openmetrics::encode(families).into_bytes(),
+            Format::Protobuf => protobuf::encode(families),
+        }
+    }
+}
+
+/// A sample line, e.g. `compose_service_up{compose_name="web",...} 1`
+struct Sample<'a> {
+    name: &'a str,
+    labels: Vec<(String, String)>,
+    value: f64,
+    timestamp_ms: Option<i64>,
+}
+
+/// Escape backslashes, double quotes and newlines in a label value, as the text
+/// format requires
+pub fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Undo the escaping of backslashes and newlines (and, in label values, double
+/// quotes) of the text format
+pub fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Parse the labels of a sample, e.g. `compose_name="web",service_name="nginx"}
+/// 1`, returning them and the rest of the line after the closing brace
+fn parse_labels(mut rest: &str) -> Option<(Vec<(String, String)>, &str)> {
+    let mut labels = vec![];
+    loop {
+        rest = rest.trim_start_matches([',', ' ']);
+        if let Some(rest) = rest.strip_prefix('}') {
+            return Some((labels, rest));
+        }
+        let (name, after_name) = rest.split_once('=')?;
+        let after_quote = after_name.strip_prefix('"')?;
+        // Find the closing quote, skipping escaped characters
+        let mut escaped = false;
+        let value_length = after_quote.char_indices().find_map(|(index, c)| {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => return Some(index),
+                _ => {}
+            }
+            None
+        })?;
+        labels.push((
+            name.trim().to_string(),
+            unescape(&after_quote[..value_length]),
+        ));
+        rest = &after_quote[value_length + 1..];
+    }
+}
+
+/// Parse a sample line. Comments, blank lines and lines that can't be parsed
+/// yield `None`.
+fn parse_sample(line: &str) -> Option<Sample<'_>> {
+    if line.starts_with('#') {
+        return None;
+    }
+    let name_end = line.find(['{', ' '])?;
+    let name = &line[..name_end];
+    let (labels, rest) = match line[name_end..].strip_prefix('{') {
+        Some(rest) => parse_labels(rest)?,
+        None => (vec![], &line[name_end..]),
+    };
+    let mut fields = rest.split_whitespace();
+    let value = fields.next()?.parse().ok()?;
+    let timestamp_ms = fields.next().and_then(|timestamp| timestamp.parse().ok());
+    Some(Sample {
+        name,
+        labels,
+        value,
+        timestamp_ms,
+    })
+}
+
+/// Label pairs of a metric, from those of a parsed sample
+fn label_pairs(labels: Vec<(String, String)>) -> Vec<LabelPair> {
+    labels
+        .iter()
+        .map(|(name, value)| registry::label_pair(name, value))
+        .collect()
+}
+
+/// Convert a parsed sample to a metric of the given type
+fn to_metric(sample: Sample, metric_type: MetricType) -> Metric {
+    let mut metric = Metric::from_label(label_pairs(sample.labels));
+    if let Some(timestamp_ms) = sample.timestamp_ms {
+        metric.set_timestamp_ms(timestamp_ms);
+    }
+    if metric_type == MetricType::COUNTER {
+        let mut counter = Counter::default();
+        counter.set_value(sample.value);
+        metric.set_counter(counter);
+    } else {
+        let mut gauge = Gauge::default();
+        gauge.set_value(sample.value);
+        metric.set_gauge(gauge);
+    }
+    metric
+}
+
+/// Add a sample of a histogram's series (`_bucket`, `_sum` or `_count`) to the
+/// histogram's metric with the same labels (besides `le`)
+fn add_to_histogram(family: &mut MetricFamily, suffix: &str, mut sample: Sample) {
+    let upper_bound = sample
+        .labels
+        .iter()
+        .position(|(name, _)| name == "le")
+        .map(|index| sample.labels.remove(index).1);
+    let labels = label_pairs(sample.labels);
+    let index = match family
+        .get_metric()
+        .iter()
+        .position(|metric| metric.get_label() == labels.as_slice())
+    {
+        Some(index) => index,
+        None => {
+            family.mut_metric().push(Metric::from_label(labels));
+            family.get_metric().len() - 1
+        }
+    };
+    let metric = &mut family.mut_metric()[index];
+    let mut histogram = metric.get_histogram().clone();
+    match (suffix, upper_bound) {
+        // The +Inf bucket is implied by the sample count
+        ("_bucket", Some(upper_bound)) if upper_bound != "+Inf" => {
+            if let Ok(upper_bound) = upper_bound.parse() {
+                let mut bucket = Bucket::default();
+                bucket.set_cumulative_count(sample.value as u64);
+                bucket.set_upper_bound(upper_bound);
+                let mut buckets = histogram.get_bucket().to_vec();
+                buckets.push(bucket);
+                histogram.set_bucket(buckets);
+            }
+        }
+        ("_sum", _) => histogram.set_sample_sum(sample.value),
+        ("_count", _) => histogram.set_sample_count(sample.value as u64),
+        _ => {}
+    }
+    metric.set_histogram(histogram);
+}
+
+/// Parse metrics in the text format, e.g. those of a federated instance, into
+/// metric families. Families without a (supported) type are taken as gauges,
+/// which are encoded the same way in the text format. Lines that can't be
+/// parsed are left out, as are families without samples.
+pub fn parse_families(text: &str) -> Vec<MetricFamily> {
+    let mut families: Vec<MetricFamily> = vec![];
+    // Indices into `families`, by name
+    let mut family_indices: HashMap<String, usize> = HashMap::new();
+    let family_index = |name: &str,
+                        families: &mut Vec<MetricFamily>,
+                        family_indices: &mut HashMap<String, usize>| {
+        *family_indices.entry(name.to_string()).or_insert_with(|| {
+            let mut family = MetricFamily::default();
+            family.set_name(name.to_string());
+            family.set_field_type(MetricType::GAUGE);
+            families.push(family);
+            families.len() - 1
+        })
+    };
+    for line in text.lines() {
+        if let Some(comment) = line.strip_prefix("# ") {
+            let mut parts = comment.splitn(3, ' ');
+            let (Some(keyword), Some(name)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            if keyword != "HELP" && keyword != "TYPE" {
+                continue;
+            }
+            let value = parts.next().unwrap_or_default();
+            let index = family_index(name, &mut families, &mut family_indices);
+            let family = &mut families[index];
+            if keyword == "HELP" {
+                family.set_help(unescape(value));
+            } else {
+                family.set_field_type(match value {
+                    "counter" => MetricType::COUNTER,
+                    "histogram" => MetricType::HISTOGRAM,
+                    _ => MetricType::GAUGE,
+                });
+            }
+        } else if let Some(sample) = parse_sample(line) {
+            let histogram_index = ["_bucket", "_sum", "_count"].iter().find_map(|suffix| {
+                let index = family_indices.get(sample.name.strip_suffix(suffix)?)?;
+                (families[*index].get_field_type() == MetricType::HISTOGRAM)
+                    .then_some((*index, suffix))
+            });
+            if let Some((index, suffix)) = histogram_index {
+                add_to_histogram(&mut families[index], suffix, sample);
+                continue;
+            }
+            // Counter families may be declared without the `_total` suffix of
+            // their samples (as in OpenMetrics), the family is named like them
+            let counter_index = sample.name.strip_suffix("_total").and_then(|name| {
+                let index = family_indices.get(name)?;
+                (families[*index].get_field_type() == MetricType::COUNTER).then_some(*index)
+            });
+            if let Some(index) = counter_index {
+                families[index].set_name(sample.name.to_string());
+                family_indices.insert(sample.name.to_string(), index);
+            }
+            let index = family_index(sample.name, &mut families, &mut family_indices);
+            let family = &mut families[index];
+            let metric = to_metric(sample, family.get_field_type());
+            family.mut_metric().push(metric);
+        }
+    }
+    families.retain(|family| !family.get_metric().is_empty());
+    families
+}
+
+/// Add the labels among the given ones a metric doesn't have yet to every
+/// metric of the given families, before its own labels
+pub fn add_missing_labels(families: &mut [MetricFamily], labels: &[(&str, &str)]) {
+    for metric in families
+        .iter_mut()
+        .flat_map(|family| family.mut_metric().iter_mut())
+    {
+        let own_labels = metric.take_label();
+        let mut all_labels: Vec<LabelPair> = labels
+            .iter()
+            .filter(|(name, _)| !own_labels.iter().any(|label| label.name() == *name))
+            .map(|(name, value)| registry::label_pair(name, value))
+            .collect();
+        all_labels.extend(own_labels);
+        metric.set_label(all_labels);
+    }
+}
+
+/// Add the given family to the families, merging its metrics into those of the
+/// family with the same name, if any, whose HELP and TYPE are kept
+pub fn merge_family(families: &mut Vec<MetricFamily>, mut family: MetricFamily) {
+    match families
+        .iter_mut()
+        .find(|existing| existing.name() == family.name())
+    {
+        Some(existing) => existing.mut_metric().extend(family.take_metric()),
+        None => families.push(family),
+    }
+}
+
+/// Add the given families to the families, see [`merge_family`]
+pub fn merge_families(families: &mut Vec<MetricFamily>, other_families: Vec<MetricFamily>) {
+    for family in other_families {
+        merge_family(families, family);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn accepting(accept: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+        headers
+    }
+
+    #[test]
+    fn escapes_and_unescapes_label_values() {
+        let value = "C:\\apps \"web\"\nline";
+        let escaped = escape_label_value(value);
+        assert_eq!(escaped, "C:\\\\apps \\\"web\\\"\\nline");
+        assert_eq!(unescape(&escaped), value);
+    }
+
+    /// Encode the given families in the text format
+    fn to_text(families: &[MetricFamily]) -> String {
+        String::from_utf8(Format::Text.encode(families)).unwrap()
+    }
+
+    #[test]
+    fn parses_samples() {
+        let sample = parse_sample(
+            r#"compose_service_up{compose_name="we\"b",service_name="a,b}c"} 1 1700000000000"#,
+        )
+        .expect("sample should parse");
+        assert_eq!(sample.name, "compose_service_up");
+        assert_eq!(
+            sample.labels,
+            [
+                ("compose_name".to_string(), "we\"b".to_string()),
+                ("service_name".to_string(), "a,b}c".to_string())
+            ]
+        );
+        assert_eq!(sample.value, 1.0);
+        assert_eq!(sample.timestamp_ms, Some(1700000000000));
+
+        let sample = parse_sample("compose_apps_nbro_configs 3").expect("sample should parse");
+        assert!(sample.labels.is_empty());
+        assert_eq!(sample.value, 3.0);
+        assert_eq!(sample.timestamp_ms, None);
+    }
+
+    #[test]
+    fn doesnt_parse_comments_or_invalid_lines() {
+        assert!(parse_sample("# HELP compose_service_up Whether the service is up").is_none());
+        assert!(parse_sample("").is_none());
+        assert!(parse_sample(r#"compose_service_up{compose_name="web} 1"#).is_none());
+        assert!(parse_sample("compose_service_up not-a-number").is_none());
+    }
+
+    #[test]
+    fn parses_families() {
+        let metrics = indoc::indoc! {r#"
+            # HELP a First
+            # TYPE a gauge
+            a{x="1"} 1
+            # HELP b Second
+            # TYPE b histogram
+            b_bucket{le="0.5"} 1
+            b_bucket{le="+Inf"} 2
+            # HELP a First
+            # TYPE a gauge
+            a{x="2"} 2 1700000000000
+            b_sum 3
+            b_count 2
+            # HELP c Without samples
+            # TYPE c gauge
+            d_total 4
+            # TYPE e counter
+            e_total{y="a\\b"} 5
+        "#};
+        assert_eq!(
+            to_text(&parse_families(metrics)),
+            indoc::indoc! {r#"
+                # HELP a First
+                # TYPE a gauge
+                a{x="1"} 1
+                a{x="2"} 2 1700000000000
+                # HELP b Second
+                # TYPE b histogram
+                b_bucket{le="0.5"} 1
+                b_bucket{le="+Inf"} 2
+                b_sum 3
+                b_count 2
+                # TYPE d_total gauge
+                d_total 4
+                # TYPE e_total counter
+                e_total{y="a\\b"} 5
+            "#}
+        );
+    }
+
+    #[test]
+    fn adds_missing_labels() {
+        let mut families = parse_families(indoc::indoc! {r#"
+            compose_service_up{compose_name="web"} 1
+            compose_apps_nbro_configs 3
+            compose_apps_federation_up{source_host="db-1"} 1
+        "#});
+        add_missing_labels(
+            &mut families,
+            &[("source_host", "web-1"), ("compose_name", "other")],
+        );
+        assert_eq!(
+            to_text(&families),
+            indoc::indoc! {r#"
+                # TYPE compose_service_up gauge
+                compose_service_up{source_host="web-1",compose_name="web"} 1
+                # TYPE compose_apps_nbro_configs gauge
+                compose_apps_nbro_configs{source_host="web-1",compose_name="other"} 3
+                # TYPE compose_apps_federation_up gauge
+                compose_apps_federation_up{compose_name="other",source_host="db-1"} 1
+            "#}
+        );
+    }
+
+    #[test]
+    fn merges_families() {
+        let mut families = parse_families(indoc::indoc! {r#"
+            # HELP a First
+            a{x="1"} 1
+        "#});
+        merge_families(
+            &mut families,
+            parse_families(indoc::indoc! {r#"
+                # HELP a Other
+                a{x="2"} 2
+                b 3
+            "#}),
+        );
+        assert_eq!(
+            to_text(&families),
+            indoc::indoc! {r#"
+                # HELP a First
+                # TYPE a gauge
+                a{x="1"} 1
+                a{x="2"} 2
+                # TYPE b gauge
+                b 3
+            "#}
+        );
+    }
+
+    #[test]
+    fn negotiates_formats() {
+        assert_eq!(Format::negotiate(&HeaderMap::new()), Format::Text);
+        assert_eq!(
+            Format::negotiate(&accepting("text/plain;version=0.0.4;q=0.5,*/*;q=0.1")),
+            Format::Text
+        );
+        assert_eq!(
+            Format::negotiate(&accepting(
+                "application/openmetrics-text;version=1.0.0;q=0.9,text/plain;version=0.0.4;q=0.5"
+            )),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            Format::negotiate(&accepting(
+                "application/openmetrics-text;version=2.0.0,text/plain;q=0.5"
+            )),
+            Format::Text
+        );
+        assert_eq!(
+            Format::negotiate(&accepting(
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited;q=0.7,text/plain;q=0.3"
+            )),
+            Format::Protobuf
+        );
+        // Ties go to the text format
+        assert_eq!(
+            Format::negotiate(&accepting("application/openmetrics-text,text/plain")),
+            Format::Text
+        );
+    }
+}
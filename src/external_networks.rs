@@ -0,0 +1,42 @@
+//! Existence of the external networks apps use (e.g. a network shared with a
+//! reverse proxy stack), a common silent cause of a stack refusing to start
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::compose_config::ComposeConfig;
+use crate::scrape_error::ScrapeError;
+use crate::{exec_docker_app_cmd, DockerCli};
+
+/// Get the names on the engine of the external networks the given app
+/// declares, sorted
+pub fn external_network_names(compose_config: &ComposeConfig) -> Vec<&str> {
+    let mut names: Vec<&str> = compose_config
+        .networks
+        .iter()
+        .filter(|(_, network)| network.external)
+        .map(|(key, network)| network.name.as_deref().unwrap_or(key))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Read the names of all networks on the engine, in the context of the app
+/// with the given compose file
+pub async fn read_network_names(
+    docker: &DockerCli,
+    config_path: &Path,
+) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+    let output = exec_docker_app_cmd(
+        docker,
+        config_path,
+        &["network", "ls", "--format", "{{.Name}}"],
+    )
+    .await
+    .map_err(|err| ScrapeError::context(err, "Failed to execute `docker network ls`"))?;
+    Ok(String::from_utf8_lossy(&output)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect())
+}
@@ -0,0 +1,37 @@
+//! Static labels added to every series the exporter emits, e.g. the host or
+//! environment, so they don't need relabeling rules on every scrape job
+
+use prometheus::proto::MetricFamily;
+
+use crate::{app_labels, exposition};
+
+/// Parse an extra label given as `<name>=<value>`
+pub fn parse(extra_label: &str) -> Result<(String, String), String> {
+    let Some((name, value)) = extra_label.split_once('=') else {
+        return Err(format!(
+            "Invalid extra label {:?}, expected <name>=<value>",
+            extra_label
+        ));
+    };
+    if !app_labels::is_valid_label_name(name) {
+        return Err(format!(
+            "Invalid extra label {:?}: {:?} can't be used as a label name",
+            extra_label, name
+        ));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Add the given labels to every sample of the given metric families. Samples
+/// that already have a label keep their value, so the labels of apps (e.g.
+/// from app_labels rules) override the extra labels.
+pub fn apply(families: &mut [MetricFamily], extra_labels: &[(String, String)]) {
+    if extra_labels.is_empty() {
+        return;
+    }
+    let extra_labels: Vec<(&str, &str)> = extra_labels
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    exposition::add_missing_labels(families, &extra_labels);
+}
@@ -0,0 +1,160 @@
+//! Scraping other compose-apps-exporter instances and re-exposing their
+//! metrics with a `source_host` label, so a single Prometheus target can cover
+//! a small fleet (e.g. behind one jump host)
+
+use hyper::{header, Body, Client, Request, Uri};
+use prometheus::proto::MetricFamily;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::exposition;
+use crate::registry::Samples;
+
+fn default_timeout_seconds() -> f64 {
+    5.0
+}
+
+/// Exporter instance to scrape, as configured
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FederationTargetConfig {
+    /// Value of the `source_host` label of the instance's metrics
+    pub source_host: String,
+    /// Metrics URL of the instance (http:// only), e.g.
+    /// 'http://10.0.0.2:9179/metrics'
+    pub url: String,
+    /// Bearer token to scrape the instance with, e.g. that of a tenant
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: f64,
+}
+
+#[derive(Clone)]
+pub struct FederationTarget {
+    source_host: String,
+    uri: Uri,
+    bearer_token: Option<String>,
+    timeout: Duration,
+}
+
+impl TryFrom<FederationTargetConfig> for FederationTarget {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(config: FederationTargetConfig) -> Result<Self, Self::Error> {
+        let invalid = |reason: String| {
+            format!(
+                "Invalid federation target {:?}: {}",
+                config.source_host, reason
+            )
+        };
+        let uri = Uri::try_from(config.url.as_str())
+            .map_err(|err| invalid(format!("invalid URL {:?}: {}", config.url, err)))?;
+        if uri.scheme_str() != Some("http") {
+            return Err(
+                invalid(format!("unsupported URL {:?}, must be http://", config.url)).into(),
+            );
+        }
+        let timeout = Duration::try_from_secs_f64(config.timeout_seconds)
+            .map_err(|err| invalid(format!("invalid timeout: {}", err)))?;
+        Ok(FederationTarget {
+            source_host: config.source_host,
+            uri,
+            bearer_token: config.bearer_token,
+            timeout,
+        })
+    }
+}
+
+impl FederationTarget {
+    /// Scrape the instance, getting its metrics in the text format
+    async fn scrape(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut request = Request::get(self.uri.clone())
+            .header(header::ACCEPT, "text/plain; version=0.0.4")
+            .header(
+                header::USER_AGENT,
+                concat!("compose-apps-exporter/", env!("CARGO_PKG_VERSION")),
+            );
+        if let Some(bearer_token) = &self.bearer_token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", bearer_token));
+        }
+        let request = request.body(Body::empty())?;
+        let response = Client::new().request(request).await?;
+        if !response.status().is_success() {
+            return Err(format!("responded with {}", response.status()).into());
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        Ok(String::from_utf8(body.to_vec())?)
+    }
+}
+
+/// Outcome of scraping an instance: its metrics in the text format, if
+/// scraping it succeeded
+pub struct Scrape {
+    source_host: String,
+    metrics: Option<String>,
+}
+
+/// Scrape the given instances concurrently
+pub async fn scrape(targets: &[FederationTarget]) -> Vec<Scrape> {
+    let handles: Vec<_> = targets
+        .iter()
+        .cloned()
+        .map(|target| {
+            tokio::spawn(async move {
+                let scraped = tokio::time::timeout(target.timeout, target.scrape())
+                    .await
+                    .map_err(|_| "timed out".into())
+                    .and_then(|scraped| scraped);
+                (target, scraped)
+            })
+        })
+        .collect();
+    let mut scrapes = vec![];
+    for handle in handles {
+        let (target, scraped) = handle.await.expect("federation scrape shouldn't panic");
+        let metrics = match scraped {
+            Ok(scraped) => Some(scraped),
+            Err(e) => {
+                eprintln!(
+                    "Error scraping federation target {}: {}",
+                    target.source_host, e
+                );
+                None
+            }
+        };
+        scrapes.push(Scrape {
+            source_host: target.source_host,
+            metrics,
+        });
+    }
+    scrapes
+}
+
+/// Add whether scraping each instance succeeded to the given samples, to be
+/// named like the exporter's own metrics
+pub fn add_up_metrics(samples: &mut Samples, scrapes: &[Scrape]) {
+    for scrape in scrapes {
+        samples.add(
+            "compose_apps_federation_up",
+            &[("source_host", &scrape.source_host)],
+            scrape.metrics.is_some() as u8,
+        );
+    }
+}
+
+/// Get the metric families of the scraped instances, labelled with their
+/// `source_host` unless they already have one (e.g. because an instance
+/// federates others itself). They're named by the instances themselves, so
+/// they're to be merged after naming the exporter's own metrics.
+pub fn federated_families(scrapes: &[Scrape]) -> Vec<MetricFamily> {
+    let mut federated_families = vec![];
+    for scrape in scrapes {
+        let Some(scraped) = &scrape.metrics else {
+            continue;
+        };
+        let mut families = exposition::parse_families(scraped);
+        exposition::add_missing_labels(&mut families, &[("source_host", &scrape.source_host)]);
+        exposition::merge_families(&mut federated_families, families);
+    }
+    federated_families
+}
@@ -0,0 +1,175 @@
+//! Determining the real client address of requests passing through trusted
+//! reverse proxies, from the `Forwarded` (RFC 7239) or `X-Forwarded-For`
+//! headers
+
+use hyper::{header, HeaderMap};
+use ipnet::IpNet;
+use std::{net::IpAddr, str::FromStr};
+
+/// Parse a trusted proxy, either as a CIDR range or as a single address
+pub fn parse_trusted_proxy(trusted_proxy: &str) -> Result<IpNet, String> {
+    IpNet::from_str(trusted_proxy)
+        .or_else(|_| IpAddr::from_str(trusted_proxy).map(IpNet::from))
+        .map_err(|_| format!("Invalid trusted proxy {:?}", trusted_proxy))
+}
+
+/// Get the address of the client that sent a request, given the address of the
+/// peer it was received from.
+///
+/// Forwarded-for addresses are only honored if the peer is a trusted proxy, in
+/// which case the chain of forwarded-for addresses is walked from the nearest
+/// hop outwards until the first address that isn't a trusted proxy.
+pub fn client_addr(peer_addr: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |addr: &IpAddr| trusted_proxies.iter().any(|net| net.contains(addr));
+    if !is_trusted(&peer_addr) {
+        return peer_addr;
+    }
+    let forwarded_for = if headers.contains_key(header::FORWARDED) {
+        forwarded_header_addrs(headers)
+    } else {
+        x_forwarded_for_header_addrs(headers)
+    };
+    let mut client_addr = peer_addr;
+    for addr in forwarded_for.into_iter().rev() {
+        client_addr = addr;
+        if !is_trusted(&addr) {
+            break;
+        }
+    }
+    client_addr
+}
+
+/// Get the addresses of the `X-Forwarded-For` headers, e.g.
+/// `X-Forwarded-For: 203.0.113.195, 2001:db8::1`
+fn x_forwarded_for_header_addrs(headers: &HeaderMap) -> Vec<IpAddr> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|addr| IpAddr::from_str(addr.trim()).ok())
+        .collect()
+}
+
+/// Get the `for=` addresses of the `Forwarded` headers, e.g.
+/// `Forwarded: for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`
+fn forwarded_header_addrs(headers: &HeaderMap) -> Vec<IpAddr> {
+    headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for")
+                    .then(|| parse_forwarded_node(value))
+                    .flatten()
+            })
+        })
+        .collect()
+}
+
+/// Parse a `Forwarded` node, e.g. `192.0.2.60`, `"192.0.2.60:8080"` or
+/// `"[2001:db8::1]:4711"`. Obfuscated identifiers and `unknown` are ignored.
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim_matches('"');
+    if let Some(bracketed) = node.strip_prefix('[') {
+        return IpAddr::from_str(bracketed.split(']').next()?).ok();
+    }
+    IpAddr::from_str(node)
+        .ok()
+        .or_else(|| IpAddr::from_str(node.rsplit_once(':')?.0).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn trusted(proxies: &[&str]) -> Vec<IpNet> {
+        proxies
+            .iter()
+            .map(|proxy| parse_trusted_proxy(proxy).expect("trusted proxy should parse"))
+            .collect()
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_trusted_proxies() {
+        assert_eq!(
+            parse_trusted_proxy("10.0.0.0/8"),
+            Ok("10.0.0.0/8".parse().unwrap())
+        );
+        assert_eq!(
+            parse_trusted_proxy("10.0.0.1"),
+            Ok("10.0.0.1/32".parse().unwrap())
+        );
+        assert_eq!(parse_trusted_proxy("::1"), Ok("::1/128".parse().unwrap()));
+        assert!(parse_trusted_proxy("proxy.local").is_err());
+    }
+
+    #[test]
+    fn parses_forwarded_nodes() {
+        assert_eq!(parse_forwarded_node("192.0.2.60"), Some(ip("192.0.2.60")));
+        assert_eq!(
+            parse_forwarded_node("\"192.0.2.60:8080\""),
+            Some(ip("192.0.2.60"))
+        );
+        assert_eq!(
+            parse_forwarded_node("\"[2001:db8::1]:4711\""),
+            Some(ip("2001:db8::1"))
+        );
+        assert_eq!(
+            parse_forwarded_node("\"[2001:db8::1]\""),
+            Some(ip("2001:db8::1"))
+        );
+        assert_eq!(parse_forwarded_node("unknown"), None);
+        assert_eq!(parse_forwarded_node("_hidden"), None);
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("203.0.113.195"));
+        assert_eq!(
+            client_addr(ip("192.0.2.1"), &headers, &trusted(&["10.0.0.0/8"])),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn walks_x_forwarded_for_chain() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("198.51.100.7, 203.0.113.195, 10.0.0.2"),
+        );
+        assert_eq!(
+            client_addr(ip("10.0.0.1"), &headers, &trusted(&["10.0.0.0/8"])),
+            ip("203.0.113.195")
+        );
+        // Everything trusted: the outermost address is the client
+        assert_eq!(
+            client_addr(ip("10.0.0.1"), &headers, &trusted(&["0.0.0.0/0"])),
+            ip("198.51.100.7")
+        );
+    }
+
+    #[test]
+    fn prefers_forwarded_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("198.51.100.7"));
+        headers.insert(
+            header::FORWARDED,
+            HeaderValue::from_static("for=192.0.2.60;proto=http, For=\"[2001:db8::1]:4711\""),
+        );
+        assert_eq!(
+            client_addr(ip("10.0.0.1"), &headers, &trusted(&["10.0.0.0/8"])),
+            ip("2001:db8::1")
+        );
+    }
+}
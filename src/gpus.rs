@@ -0,0 +1,162 @@
+//! GPUs reserved by services, and whether their containers actually got them,
+//! for (homelab AI) stacks sharing GPUs
+
+use futures::future::try_join_all;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::compose_config::{ComposeDeviceRequest, ComposeService, DeviceCount};
+use crate::scrape_error::{ErrorCode, ScrapeError};
+use crate::{exec_docker_app_cmd, DockerCli};
+
+/// Capability of GPU device requests
+static GPU_CAPABILITY: &str = "gpu";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedContainer {
+    /// e.g. '/my-container'
+    name: String,
+    host_config: InspectedHostConfig,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedHostConfig {
+    #[serde(default)]
+    device_requests: Option<Vec<InspectedDeviceRequest>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedDeviceRequest {
+    /// Sets of capabilities, any of which the device must have all of
+    #[serde(default)]
+    capabilities: Option<Vec<Vec<String>>>,
+}
+
+/// Number of devices requested, -1 meaning all of them (as docker does)
+fn device_count(device_request: &ComposeDeviceRequest) -> i64 {
+    if !device_request.device_ids.is_empty() {
+        return device_request.device_ids.len() as i64;
+    }
+    match device_request.count {
+        Some(DeviceCount::Count(count)) => count,
+        // All devices if neither the count nor the IDs are given
+        Some(DeviceCount::All(_)) | None => -1,
+    }
+}
+
+/// Get the number of GPUs the given service reserves, through
+/// `deploy.resources.reservations.devices` or `gpus`, by driver ('' if left to
+/// docker). -1 means all GPUs.
+pub fn reserved_gpus(service: &ComposeService) -> BTreeMap<String, i64> {
+    let reserved_devices = service
+        .deploy
+        .as_ref()
+        .and_then(|deploy| deploy.resources.as_ref())
+        .and_then(|resources| resources.reservations.as_ref())
+        .map(|reservations| reservations.devices.as_slice())
+        .unwrap_or_default();
+    let gpu_requests = reserved_devices
+        .iter()
+        .filter(|device_request| {
+            device_request
+                .capabilities
+                .iter()
+                .any(|capability| capability == GPU_CAPABILITY)
+        })
+        .chain(&service.gpus);
+    let mut reserved_gpus = BTreeMap::new();
+    for gpu_request in gpu_requests {
+        let driver = gpu_request.driver.clone().unwrap_or_default();
+        let count = device_count(gpu_request);
+        reserved_gpus
+            .entry(driver)
+            .and_modify(|total: &mut i64| {
+                *total = if *total < 0 || count < 0 {
+                    -1
+                } else {
+                    *total + count
+                }
+            })
+            .or_insert(count);
+    }
+    reserved_gpus
+}
+
+/// Whether any of the given device requests, by their sets of capabilities,
+/// is for GPUs
+fn requests_gpus<'a>(mut capabilities: impl Iterator<Item = &'a Vec<String>>) -> bool {
+    capabilities.any(|capabilities| capabilities.iter().any(|c| c == GPU_CAPABILITY))
+}
+
+/// Read whether the given containers were created with access to GPUs, from
+/// the engine API if enabled, or else using a single (batched) `docker inspect`
+/// call in the context of the app with the given compose file
+pub async fn read_gpus_attached(
+    docker: &DockerCli,
+    config_path: &Path,
+    container_names: &[&str],
+) -> Result<HashMap<String, bool>, Box<dyn std::error::Error>> {
+    if container_names.is_empty() {
+        return Ok(HashMap::new());
+    }
+    if let Some(api) = &docker.api {
+        let inspected_containers = try_join_all(container_names.iter().map(|name| async move {
+            api.inspect_container(name)
+                .await
+                .map_err(ScrapeError::from_boxed)
+        }))
+        .await
+        .map_err(|err| ScrapeError::context(err.into(), "Failed to inspect the containers"))?;
+        return Ok(container_names
+            .iter()
+            .zip(inspected_containers)
+            .map(|(name, inspected_container)| {
+                let device_requests = inspected_container
+                    .host_config
+                    .and_then(|host_config| host_config.device_requests)
+                    .unwrap_or_default();
+                let gpus_attached = requests_gpus(
+                    device_requests
+                        .iter()
+                        .flat_map(|device_request| device_request.capabilities.iter().flatten()),
+                );
+                (name.to_string(), gpus_attached)
+            })
+            .collect());
+    }
+    let mut inspect_args = vec!["inspect"];
+    inspect_args.extend(container_names);
+    let inspected_containers: Vec<InspectedContainer> = serde_json::from_slice(
+        &exec_docker_app_cmd(docker, config_path, &inspect_args)
+            .await
+            .map_err(|err| ScrapeError::context(err, "Failed to execute `docker inspect`"))?,
+    )
+    .map_err(|err| {
+        ScrapeError::new(
+            ErrorCode::DockerOutputInvalid,
+            format!("Failed to parse `docker inspect` output: {}", err),
+        )
+    })?;
+    Ok(inspected_containers
+        .into_iter()
+        .map(|inspected_container| {
+            let device_requests = inspected_container
+                .host_config
+                .device_requests
+                .unwrap_or_default();
+            let gpus_attached = requests_gpus(
+                device_requests
+                    .iter()
+                    .flat_map(|device_request| device_request.capabilities.iter().flatten()),
+            );
+            (
+                inspected_container.name.trim_start_matches('/').to_string(),
+                gpus_attached,
+            )
+        })
+        .collect())
+}
@@ -0,0 +1,166 @@
+//! Grace period for services that are down or still starting, e.g. during a
+//! routine `docker compose up -d` rollout or right after a host reboot, so
+//! they don't flip alert-relevant series before they had a chance to come up
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::STATE_NOT_UP;
+
+/// States services pass through while being (re)deployed
+static UNSETTLED_STATES: [&str; 3] = [STATE_NOT_UP, "created", "removing"];
+
+#[derive(Default)]
+struct ServiceGrace {
+    /// Last state and health seen while the service was settled
+    last_settled: Option<(String, String)>,
+    /// Since when the service has been unsettled
+    unsettled_since: Option<Instant>,
+}
+
+pub struct GracePeriods {
+    started_at: Instant,
+    /// By compose and service name
+    services: Mutex<HashMap<(String, String), ServiceGrace>>,
+}
+
+/// What to report for a service
+pub struct Reported {
+    /// State and health, or none if the service is in its grace period and
+    /// hasn't been seen up before
+    pub state: Option<(String, String)>,
+    pub in_grace_period: bool,
+}
+
+impl GracePeriods {
+    pub fn new() -> Self {
+        GracePeriods {
+            started_at: Instant::now(),
+            services: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get what to report for a service currently in the given state and
+    /// health. A service is unsettled while it's down, being deployed or
+    /// starting, and in its grace period while it has been unsettled for less
+    /// than `grace_period`, or less than `grace_period` after the exporter
+    /// started. During it, the last settled state and health are reported (or
+    /// nothing if the service hasn't been seen settled yet), and the current
+    /// ones after it.
+    pub fn reported_state(
+        &self,
+        compose_name: &str,
+        service_name: &str,
+        state: &str,
+        health: &str,
+        grace_period: Duration,
+    ) -> Reported {
+        let mut services = self
+            .services
+            .lock()
+            .expect("grace periods lock shouldn't be poisoned");
+        let key = (compose_name.to_string(), service_name.to_string());
+        let service = services.entry(key).or_default();
+        let current = (state.to_string(), health.to_string());
+        if !UNSETTLED_STATES.contains(&state) && health != "starting" {
+            service.last_settled = Some(current.clone());
+            service.unsettled_since = None;
+            return Reported {
+                state: Some(current),
+                in_grace_period: false,
+            };
+        }
+        let unsettled_since = *service.unsettled_since.get_or_insert_with(Instant::now);
+        let in_grace_period =
+            unsettled_since.elapsed() < grace_period || self.started_at.elapsed() < grace_period;
+        Reported {
+            state: if in_grace_period {
+                service.last_settled.clone()
+            } else {
+                Some(current)
+            },
+            in_grace_period,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+    /// Grace periods of an exporter that started long ago
+    fn long_running() -> GracePeriods {
+        GracePeriods {
+            started_at: Instant::now() - 2 * GRACE_PERIOD,
+            services: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn state(reported: &Reported) -> Option<(&str, &str)> {
+        reported
+            .state
+            .as_ref()
+            .map(|(state, health)| (state.as_str(), health.as_str()))
+    }
+
+    #[test]
+    fn reports_settled_services() {
+        let grace_periods = GracePeriods::new();
+        let reported =
+            grace_periods.reported_state("web", "app", "running", "healthy", GRACE_PERIOD);
+        assert_eq!(state(&reported), Some(("running", "healthy")));
+        assert!(!reported.in_grace_period);
+    }
+
+    #[test]
+    fn reports_last_settled_state_during_grace_period() {
+        let grace_periods = long_running();
+        grace_periods.reported_state("web", "app", "running", "healthy", GRACE_PERIOD);
+        for (unsettled_state, health) in
+            [("created", ""), ("running", "starting"), (STATE_NOT_UP, "")]
+        {
+            let reported =
+                grace_periods.reported_state("web", "app", unsettled_state, health, GRACE_PERIOD);
+            assert_eq!(state(&reported), Some(("running", "healthy")));
+            assert!(reported.in_grace_period);
+        }
+    }
+
+    #[test]
+    fn reports_nothing_for_services_not_seen_settled() {
+        let grace_periods = long_running();
+        let reported = grace_periods.reported_state("web", "app", "created", "", GRACE_PERIOD);
+        assert_eq!(state(&reported), None);
+        assert!(reported.in_grace_period);
+    }
+
+    #[test]
+    fn reports_current_state_after_grace_period() {
+        let grace_periods = long_running();
+        grace_periods.reported_state("web", "app", "running", "healthy", GRACE_PERIOD);
+        grace_periods.reported_state("web", "app", STATE_NOT_UP, "", GRACE_PERIOD);
+        grace_periods
+            .services
+            .lock()
+            .unwrap()
+            .get_mut(&("web".to_string(), "app".to_string()))
+            .expect("service should be tracked")
+            .unsettled_since = Some(Instant::now() - 2 * GRACE_PERIOD);
+        let reported = grace_periods.reported_state("web", "app", STATE_NOT_UP, "", GRACE_PERIOD);
+        assert_eq!(state(&reported), Some((STATE_NOT_UP, "")));
+        assert!(!reported.in_grace_period);
+    }
+
+    #[test]
+    fn has_grace_period_after_exporter_start() {
+        let grace_periods = GracePeriods::new();
+        let reported = grace_periods.reported_state("web", "app", STATE_NOT_UP, "", GRACE_PERIOD);
+        assert!(reported.in_grace_period);
+        let reported = grace_periods.reported_state("web", "db", STATE_NOT_UP, "", Duration::ZERO);
+        assert_eq!(state(&reported), Some((STATE_NOT_UP, "")));
+        assert!(!reported.in_grace_period);
+    }
+}
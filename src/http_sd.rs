@@ -0,0 +1,42 @@
+//! Prometheus HTTP service discovery of the apps, served on `/sd`: one target
+//! group per app, scraped on its own by selecting it with the `app` query
+//! parameter, so Prometheus picks up newly deployed apps automatically
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A target group in the HTTP SD format, see
+/// <https://prometheus.io/docs/prometheus/latest/http_sd/>
+#[derive(Serialize)]
+pub struct TargetGroup {
+    targets: Vec<String>,
+    labels: BTreeMap<String, String>,
+}
+
+impl TargetGroup {
+    /// Target group scraping the app with the given compose name (and grouping
+    /// labels) from the exporter at the given address. The app's labels are
+    /// only passed as `__meta_compose_*` labels for relabeling, as the scraped
+    /// series carry them already (and would otherwise have them renamed to
+    /// `exported_*`).
+    pub fn of_app(
+        address: &str,
+        scheme: &str,
+        metrics_path: &str,
+        compose_name: &str,
+        app_labels: Vec<(String, String)>,
+    ) -> Self {
+        let mut labels: BTreeMap<String, String> = app_labels
+            .into_iter()
+            .map(|(name, value)| (format!("__meta_compose_label_{}", name), value))
+            .collect();
+        labels.insert("__meta_compose_name".to_string(), compose_name.to_string());
+        labels.insert("__param_app".to_string(), compose_name.to_string());
+        labels.insert("__metrics_path__".to_string(), metrics_path.to_string());
+        labels.insert("__scheme__".to_string(), scheme.to_string());
+        TargetGroup {
+            targets: vec![address.to_string()],
+            labels,
+        }
+    }
+}
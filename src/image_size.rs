@@ -0,0 +1,119 @@
+//! Size of the images services run, to identify the apps whose images dominate
+//! disk usage and to track image size regressions across upgrades. The same
+//! inspection yields the images' platforms, see [`crate::platform`].
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::platform::Platform;
+use crate::scrape_error::{ErrorCode, ScrapeError};
+use crate::{run_docker_cmd, DockerCli};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedImage {
+    id: String,
+    #[serde(default)]
+    repo_tags: Vec<String>,
+    #[serde(default)]
+    repo_digests: Vec<String>,
+    size: u64,
+    #[serde(default)]
+    os: String,
+    #[serde(default)]
+    architecture: String,
+    #[serde(default)]
+    variant: Option<String>,
+}
+
+/// Details of an image relevant to the exporter
+pub struct ImageDetails {
+    pub size: u64,
+    pub platform: Platform,
+    /// e.g. 'nginx@sha256:...', empty for images that weren't pulled
+    pub repo_digests: Vec<String>,
+}
+
+/// Read the details of the given images (as referenced in a compose file), using
+/// a single (batched) `docker image inspect` call in the context of the app
+/// with the given compose file. Images that don't exist locally (e.g. because
+/// they haven't been pulled yet) are left out.
+pub async fn read_images(
+    docker: &DockerCli,
+    config_path: &Path,
+    images: &[&str],
+) -> Result<HashMap<String, ImageDetails>, Box<dyn std::error::Error>> {
+    if images.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let mut command = docker.app_command(config_path)?;
+    command.args(["image", "inspect"]).args(images);
+    // `docker image inspect` exits unsuccessfully if any of the images doesn't
+    // exist, but still prints the ones that do
+    let output = run_docker_cmd(command, docker.command_timeout)
+        .await
+        .map_err(|err| ScrapeError::context(err, "Failed to execute `docker image inspect`"))?;
+    let inspected_images: Vec<InspectedImage> =
+        serde_json::from_slice(&output.stdout).map_err(|err| {
+            if output.status.success() {
+                ScrapeError::new(
+                    ErrorCode::DockerOutputInvalid,
+                    format!("Failed to parse `docker image inspect` output: {}", err),
+                )
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                ScrapeError::new(
+                    ErrorCode::DockerCommandFailed,
+                    format!("`docker image inspect` failed: {}", stderr),
+                )
+                .with_stderr(&stderr)
+            }
+        })?;
+    Ok(images
+        .iter()
+        .filter_map(|image| {
+            let inspected_image = inspected_images
+                .iter()
+                .find(|inspected_image| inspected_image.is_referenced_by(image))?;
+            let details = ImageDetails {
+                size: inspected_image.size,
+                platform: Platform {
+                    os: inspected_image.os.clone(),
+                    architecture: inspected_image.architecture.clone(),
+                    variant: inspected_image.variant.clone(),
+                },
+                repo_digests: inspected_image.repo_digests.clone(),
+            };
+            Some((image.to_string(), details))
+        })
+        .collect())
+}
+
+impl InspectedImage {
+    fn is_referenced_by(&self, reference: &str) -> bool {
+        let reference = normalize_reference(reference);
+        self.id == reference
+            || self.id.strip_prefix("sha256:") == Some(&reference)
+            || self
+                .repo_tags
+                .iter()
+                .chain(&self.repo_digests)
+                .any(|repo_reference| normalize_reference(repo_reference) == reference)
+    }
+}
+
+/// Normalize an image reference the way docker does for display, e.g.
+/// 'docker.io/library/nginx' to 'nginx:latest'
+fn normalize_reference(reference: &str) -> String {
+    let reference = reference
+        .strip_prefix("docker.io/library/")
+        .or_else(|| reference.strip_prefix("docker.io/"))
+        .unwrap_or(reference);
+    let name = reference.rsplit('/').next().unwrap_or(reference);
+    if name.contains(':') || name.contains('@') || reference.starts_with("sha256:") {
+        reference.to_string()
+    } else {
+        format!("{}:latest", reference)
+    }
+}
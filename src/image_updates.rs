@@ -0,0 +1,124 @@
+//! Whether the images services run are stale, by comparing their digests with
+//! the ones their tags currently point to in the registry
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::scrape_error::ScrapeError;
+use crate::{exec_docker_app_cmd, DockerCli};
+
+/// Descriptor of the manifest (or index) a tag points to, as printed by `docker
+/// buildx imagetools inspect --format '{{json .Manifest}}'`
+#[derive(Deserialize)]
+struct ManifestDescriptor {
+    /// e.g. 'sha256:...'
+    digest: String,
+}
+
+struct CheckedDigest {
+    at: Instant,
+    /// `None` if the check failed (e.g. for images that were never pushed)
+    digest: Option<String>,
+}
+
+/// Digests of the images in the registry, by image reference, checked at most
+/// once per interval as registries rate-limit requests
+#[derive(Default)]
+pub struct RegistryDigests(Mutex<HashMap<String, CheckedDigest>>);
+
+impl RegistryDigests {
+    /// Get the digests the given images (as referenced in a compose file)
+    /// currently have in their registries, checking those not checked within
+    /// the given interval in the context of the app with the given compose
+    /// file (so e.g. the registry credentials of the user running its docker
+    /// commands apply). Images whose digest can't be checked are left out.
+    pub async fn get(
+        &self,
+        docker: &DockerCli,
+        config_path: &Path,
+        images: &[&str],
+        check_interval: Duration,
+    ) -> HashMap<String, String> {
+        let mut digests = HashMap::new();
+        for image in images {
+            let checked = self
+                .0
+                .lock()
+                .expect("registry digests lock shouldn't be poisoned")
+                .get(*image)
+                .filter(|checked| checked.at.elapsed() < check_interval)
+                .map(|checked| checked.digest.clone());
+            let digest = match checked {
+                Some(digest) => digest,
+                None => {
+                    let digest = read_registry_digest(docker, config_path, image)
+                        .await
+                        .map_err(|err| {
+                            eprintln!(
+                                "Error checking the registry digest of image {}: {}",
+                                image,
+                                err.to_string().trim_end()
+                            );
+                        })
+                        .ok();
+                    self.0
+                        .lock()
+                        .expect("registry digests lock shouldn't be poisoned")
+                        .insert(
+                            image.to_string(),
+                            CheckedDigest {
+                                at: Instant::now(),
+                                digest: digest.clone(),
+                            },
+                        );
+                    digest
+                }
+            };
+            if let Some(digest) = digest {
+                digests.insert(image.to_string(), digest);
+            }
+        }
+        digests
+    }
+}
+
+async fn read_registry_digest(
+    docker: &DockerCli,
+    config_path: &Path,
+    image: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let output = exec_docker_app_cmd(
+        docker,
+        config_path,
+        &[
+            "buildx",
+            "imagetools",
+            "inspect",
+            "--format",
+            "{{json .Manifest}}",
+            image,
+        ],
+    )
+    .await
+    .map_err(|err| ScrapeError::context(err, "Failed to execute `docker buildx imagetools`"))?;
+    let descriptor: ManifestDescriptor = serde_json::from_slice(&output).map_err(|err| {
+        format!(
+            "Failed to parse `docker buildx imagetools inspect` output: {}",
+            err
+        )
+    })?;
+    Ok(descriptor.digest)
+}
+
+/// Whether an image with the given repo digests (e.g. 'nginx@sha256:...') is
+/// stale, given the digest its tag has in the registry
+pub fn is_update_available(repo_digests: &[String], registry_digest: &str) -> bool {
+    !repo_digests.iter().any(|repo_digest| {
+        repo_digest
+            .rsplit_once('@')
+            .is_some_and(|(_, digest)| digest == registry_digest)
+    })
+}
@@ -0,0 +1,48 @@
+//! Reconciliation of the discovered apps against a declared inventory, to
+//! catch both deleted stacks and unmanaged ones deployed on the host
+
+use std::path::Path;
+
+use crate::registry::Samples;
+
+/// A discovered app, with its compose name if its config resolved
+pub struct DiscoveredApp<'a> {
+    pub config_path: &'a Path,
+    pub compose_name: Option<&'a str>,
+}
+
+/// Whether the given inventory entry, a compose name or a path (to the compose
+/// file or its directory), refers to the given app
+fn refers_to(entry: &str, app: &DiscoveredApp) -> bool {
+    if !entry.contains('/') {
+        return app.compose_name == Some(entry);
+    }
+    let entry = Path::new(entry);
+    app.config_path == entry || app.config_path.parent() == Some(entry)
+}
+
+/// Add the apps missing from and unexpected by the inventory to the given
+/// samples
+pub fn add_metrics(samples: &mut Samples, inventory: &[String], apps: &[DiscoveredApp]) {
+    for entry in inventory {
+        let missing = !apps.iter().any(|app| refers_to(entry, app));
+        samples.add(
+            "compose_app_expected_missing",
+            &[("app", entry)],
+            missing as u8,
+        );
+    }
+    for app in apps {
+        if inventory.iter().any(|entry| refers_to(entry, app)) {
+            continue;
+        }
+        samples.add(
+            "compose_app_unexpected",
+            &[
+                ("compose_name", app.compose_name.unwrap_or_default()),
+                ("config_path", &app.config_path.display().to_string()),
+            ],
+            1,
+        );
+    }
+}
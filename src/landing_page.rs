@@ -0,0 +1,77 @@
+//! The HTML page served on `/`, like other Prometheus exporters do, linking to
+//! the metrics and the other endpoints
+
+use indoc::indoc;
+
+use crate::{config_paths_from_globs, Exporter};
+
+/// Escape text to include in HTML
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render the landing page, with the exporter's version, the configured globs
+/// and directories and the number of apps they currently resolve to
+pub fn render(exporter: &Exporter) -> String {
+    let config = exporter.config();
+    let discovered_apps = match config_paths_from_globs(
+        &config.compose_configs_glob,
+        &config.compose_configs_dir,
+        &config.discovery,
+    ) {
+        Ok(config_paths) => config_paths.len().to_string(),
+        Err(e) => format!("unknown ({})", escape_html(&e.to_string())),
+    };
+    let mut sources: Vec<String> = config
+        .compose_configs_glob
+        .iter()
+        .map(|glob| format!("<li><code>{}</code></li>", escape_html(glob)))
+        .collect();
+    sources.extend(config.compose_configs_dir.iter().map(|dir| {
+        format!(
+            "<li><code>{}</code> (scanned recursively)</li>",
+            escape_html(&dir.display().to_string())
+        )
+    }));
+    format!(
+        indoc! {r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+            <meta charset="utf-8">
+            <title>Compose Apps Exporter</title>
+            </head>
+            <body>
+            <h1>Compose Apps Exporter</h1>
+            <p>Version {}</p>
+            <ul>
+            <li><a href="{}">Metrics</a></li>
+            <li><a href="/-/healthy">Health</a> and <a href="/-/ready">readiness</a></li>
+            <li><a href="/api/v1/openapi.json">OpenAPI document of the JSON API</a></li>
+            <li><a href="/version">Version information</a></li>
+            </ul>
+            <h2>Compose files</h2>
+            <ul>
+            {}
+            </ul>
+            <p>Discovered apps: {}</p>
+            </body>
+            </html>
+        "#},
+        env!("CARGO_PKG_VERSION"),
+        escape_html(&config.metrics_path),
+        sources.join("\n"),
+        discovered_apps
+    )
+}
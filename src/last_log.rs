@@ -0,0 +1,59 @@
+//! Time of the most recent log line of containers, to catch services that are
+//! running but wedged (and have stopped logging)
+
+use chrono::DateTime;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::scrape_error::{ErrorCode, ScrapeError};
+use crate::{run_docker_cmd, DockerCli};
+
+/// Read the timestamp (in seconds since the epoch) of the most recent log line
+/// of the given containers, in the context of the app with the given compose
+/// file. Containers without logs (or with a logging driver that can't be read
+/// back) are left out.
+pub async fn read_last_log_timestamps(
+    docker: &DockerCli,
+    config_path: &Path,
+    container_names: &[&str],
+) -> Result<HashMap<String, f64>, Box<dyn std::error::Error>> {
+    let mut timestamps = HashMap::new();
+    for container_name in container_names {
+        let mut command = docker.app_command(config_path)?;
+        command.args(["logs", "--tail", "1", "--timestamps", container_name]);
+        let output = run_docker_cmd(command, docker.command_timeout)
+            .await
+            .map_err(|err| ScrapeError::context(err, "Failed to execute `docker logs`"))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // e.g. 'configured logging driver does not support reading'
+            if stderr.contains("does not support reading") {
+                continue;
+            }
+            return Err(ScrapeError::new(
+                ErrorCode::DockerCommandFailed,
+                format!("`docker logs` failed: {}", stderr),
+            )
+            .with_stderr(&stderr)
+            .into());
+        }
+        // The last line is printed to stdout or stderr, depending on the
+        // stream it was logged to
+        let timestamp = [&output.stdout, &output.stderr]
+            .into_iter()
+            .filter_map(|stream| line_timestamp(&String::from_utf8_lossy(stream)))
+            .reduce(f64::max);
+        if let Some(timestamp) = timestamp {
+            timestamps.insert(container_name.to_string(), timestamp);
+        }
+    }
+    Ok(timestamps)
+}
+
+/// Parse the timestamp `docker logs --timestamps` prefixes a line with, e.g.
+/// '2024-05-01T12:00:00.123456789Z GET / 200'
+fn line_timestamp(line: &str) -> Option<f64> {
+    let timestamp = line.split_whitespace().next()?;
+    let timestamp = DateTime::parse_from_rfc3339(timestamp).ok()?;
+    Some(timestamp.timestamp_millis() as f64 / 1000.0)
+}
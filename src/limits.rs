@@ -0,0 +1,56 @@
+//! Ulimits and restart limits of services, so fleets can be audited for e.g.
+//! databases without a raised `nofile` limit
+
+use crate::add_service_metric;
+use crate::compose_config::ComposeService;
+use crate::registry::Samples;
+
+/// Maximum number of times the service is restarted after failing, if limited
+/// (with `restart: on-failure:<max retries>` or a deploy restart policy)
+fn max_restart_retries(service: &ComposeService) -> Option<u64> {
+    let restart_retries = service
+        .restart
+        .as_deref()
+        .and_then(|restart| restart.strip_prefix("on-failure:"))
+        .and_then(|max_retries| max_retries.parse().ok());
+    restart_retries.or_else(|| {
+        service
+            .deploy
+            .as_ref()?
+            .restart_policy
+            .as_ref()?
+            .max_attempts
+    })
+}
+
+/// Add the ulimits and restart limit of a service to the samples
+pub fn add_metrics(
+    samples: &mut Samples,
+    app_labels: &[(&str, &str)],
+    service_name: &str,
+    service: &ComposeService,
+) {
+    for (ulimit, limits) in &service.ulimits {
+        let (soft, hard) = limits.limits();
+        for (limit, value) in [("soft", soft), ("hard", hard)] {
+            add_service_metric(
+                samples,
+                app_labels,
+                service_name,
+                "ulimit",
+                &[("ulimit", ulimit), ("limit", limit)],
+                value,
+            );
+        }
+    }
+    if let Some(max_retries) = max_restart_retries(service) {
+        add_service_metric(
+            samples,
+            app_labels,
+            service_name,
+            "restart_max_retries",
+            &[],
+            max_retries,
+        );
+    }
+}
@@ -1,19 +1,139 @@
-use clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser};
+#[cfg(all(feature = "tokio-console", not(tokio_unstable)))]
+compile_error!(
+    "The tokio-console feature requires building with RUSTFLAGS=\"--cfg tokio_unstable\""
+);
+
+mod alertmanager;
+#[cfg(feature = "jemalloc")]
+mod allocator;
+mod app_labels;
+mod app_state;
+mod apps;
+mod auth;
+mod availability;
+mod bind_mounts;
+mod cancellation;
+mod compat;
+mod compose_config;
+mod compression;
+mod concurrency;
+mod config_drift;
+mod config_reload;
+mod container_details;
+mod cors;
+mod debounce;
+mod debug_state;
+mod dependencies;
+mod discovery;
+mod downtime;
+mod engine_api;
+mod expected_state;
+mod exposition;
+mod external_networks;
+mod extra_labels;
+mod federation;
+mod forwarded;
+mod gpus;
+mod grace_period;
+mod http_sd;
+mod image_size;
+mod image_updates;
+mod inventory;
+mod landing_page;
+mod last_log;
+mod limits;
+mod maintenance;
+mod metric_naming;
+mod metrics_schema;
+mod name_conflicts;
+mod openapi;
+mod openmetrics;
+mod overlapping_scrapes;
+mod platform;
+mod protobuf;
+mod proxy_protocol;
+mod registry;
+mod replicas;
+mod run_as_user;
+mod scrape_error;
+mod secrets;
+mod security;
+mod selection;
+mod service_info;
+mod stats;
+mod synthetic_checks;
+mod telemetry;
+mod tenants;
+mod tls;
+mod uptime_history;
+mod volume_usage;
+mod watch;
+mod web;
+mod zabbix;
+
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use directories::ProjectDirs;
 use figment::{
     providers::{Env, Format, Serialized, Yaml},
     Figment,
 };
 use hyper::http::HeaderValue;
-use hyper::service::{make_service_fn, service_fn};
-use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{header, Body, Method, Request, Response, StatusCode, Uri};
 use indoc::indoc;
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
-use std::{collections::HashMap, fmt::Debug, net::IpAddr};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{convert::Infallible, str::FromStr};
+use std::{fmt::Debug, net::IpAddr};
 use std::{net::SocketAddr, path::Path};
 
+use alertmanager::Alerts;
+use app_labels::{AppLabels, AppLabelsRule};
+use app_state::{AppError, AppState, ServiceState};
+use apps::{AppConfig, Apps};
+use auth::ScrapeAuth;
+use availability::Availability;
+use bind_mounts::BindMountUsage;
+use compat::Compat;
+use compose_config::ComposeConfig;
+use config_drift::CONFIG_HASH_LABEL;
+use config_reload::ConfigReloads;
+use container_details::ContainerDetailsCache;
+use cors::Cors;
+use debounce::{DebounceConfig, Debouncer};
+use debug_state::DebugState;
+use discovery::{DiscoveryOptions, SymlinkPolicy};
+use downtime::{DowntimeWindow, DowntimeWindowConfig};
+use engine_api::{DockerBackend, EngineApi};
+use expected_state::ExpectedStateRule;
+use federation::{FederationTarget, FederationTargetConfig};
+use grace_period::GracePeriods;
+use image_size::ImageDetails;
+use image_updates::RegistryDigests;
+use maintenance::{Maintenance, MaintenanceTarget};
+use metric_naming::MetricNaming;
+use overlapping_scrapes::{
+    Admission, Collection, CollectionResult, InFlightCollections, OverlapPolicy,
+};
+use platform::{EnginePlatform, Platform};
+use registry::{SampleValue, Samples};
+use run_as_user::{RunAsUser, RunAsUserRule};
+use scrape_error::{ErrorCode, ScrapeError};
+use selection::Selection;
+use stats::ContainerStats;
+use synthetic_checks::{SyntheticCheck, SyntheticCheckConfig};
+use tenants::{Tenant, TenantConfig, Tenants};
+use tls::TlsOptions;
+use uptime_history::UptimeHistory;
+use web::WebConfig;
+use zabbix::ZabbixItems;
+
 static ENV_PREFIX: &str = "COMPOSE_APPS_EXPORTER_";
 
 #[cfg(target_os = "macos")]
@@ -23,209 +143,1669 @@ static SYSTEM_CONFIG_FILE_PATH: &str = "/etc/";
 #[cfg(target_os = "windows")]
 static SYSTEM_CONFIG_FILE_PATH: &str = "C:\\ProgramData\\";
 
+#[cfg(not(target_os = "windows"))]
+const DEFAULT_COMPOSE_CONFIGS_GLOB: &str = "/etc/compose-apps/*";
+#[cfg(target_os = "windows")]
+const DEFAULT_COMPOSE_CONFIGS_GLOB: &str = "C:\\ProgramData\\compose-apps\\*";
+
+static JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Time clients get to send their PROXY protocol header after connecting
+static PROXY_PROTOCOL_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+static TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// URL schemes of docker daemon endpoints understood by the docker CLI
+static DOCKER_HOST_SCHEMES: [&str; 5] = ["unix", "tcp", "ssh", "fd", "npipe"];
+
 /// Prometheus metrics exporter for docker compose apps.
 #[derive(Parser, Deserialize, Serialize, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Config {
-    /// Glob pattern for docker-compose.yml files or directories containing them
-    #[arg(short, long, default_value = "/etc/compose-apps/*")]
+    /// Glob pattern for compose files or directories containing them (as
+    /// compose.yaml, compose.yml, docker-compose.yaml or docker-compose.yml)
+    #[arg(short, long, default_value = DEFAULT_COMPOSE_CONFIGS_GLOB)]
     compose_configs_glob: Vec<String>,
+    /// Directory to scan recursively for compose files, down to
+    /// --discovery-max-depth (5 by default)
+    #[arg(long)]
+    #[serde(default)]
+    compose_configs_dir: Vec<std::path::PathBuf>,
+    /// Glob pattern for paths matched by the compose configs globs to skip,
+    /// along with everything below them (e.g. '/etc/compose-apps/_disabled/*')
+    #[arg(long)]
+    #[serde(default)]
+    compose_configs_exclude_glob: Vec<String>,
+    /// Maximum number of path components the globs may match below their
+    /// fixed part, e.g. 3 for `/srv/**/compose.yaml` to match up to
+    /// `/srv/a/b/compose.yaml`
+    #[arg(long)]
+    discovery_max_depth: Option<usize>,
+    /// Whether the globs match symlinks and descend into symlinked directories
+    #[arg(long, value_enum, default_value = "follow")]
+    #[serde(default)]
+    discovery_symlinks: SymlinkPolicy,
+    /// Names of directories for the globs not to descend into (patterns, e.g.
+    /// '.git' or 'backup*')
+    #[arg(long)]
+    #[serde(default)]
+    discovery_exclude_dirs: Vec<String>,
+    /// Also collect the running compose projects listed by `docker compose
+    /// ls`, even if their compose files don't match the globs (e.g. apps
+    /// started ad-hoc from home directories)
+    #[arg(long)]
+    discover_running: bool,
     /// Port to listen on
     #[arg(short, long, default_value = "9179")]
     port: u16,
     /// Address to listen on
     #[arg(short, long, default_value = "127.0.0.1")]
     address: String,
+    /// Path under which to expose the metrics
+    #[arg(long, default_value = "/metrics")]
+    metrics_path: String,
+    /// Compress metrics responses of at least this size with gzip, for clients
+    /// that accept it
+    #[arg(long, default_value = "4096")]
+    gzip_min_size_bytes: usize,
+    /// Docker daemon endpoint (e.g. 'unix:///run/docker.sock', or
+    /// 'npipe:////./pipe/docker_engine' on Windows), defaults to the docker
+    /// CLI's own default
+    #[arg(long, conflicts_with = "docker_socket")]
+    docker_host: Option<String>,
+    /// Path of the docker daemon's unix socket, shorthand for a 'unix://'
+    /// docker host. If neither this nor the docker host is set, the rootless
+    /// docker socket ($XDG_RUNTIME_DIR/docker.sock) is used if it exists.
+    #[arg(long)]
+    docker_socket: Option<String>,
+    /// Command to prefix docker invocations with (e.g. 'sudo -n' or 'doas'),
+    /// so the exporter can run as a user that isn't in the docker group
+    #[arg(long)]
+    docker_command_prefix: Option<String>,
+    /// Time after which to kill a docker command (e.g. when the docker daemon
+    /// is wedged), failing the collection of its app. 0 to never kill them.
+    #[arg(long, default_value = "10")]
+    docker_command_timeout_seconds: f64,
+    /// Run each app's docker commands as the user owning its compose file
+    /// (Linux only, requires running as root)
+    #[arg(long)]
+    run_as_config_owner: bool,
+    /// Users to run the docker commands of specific apps as, by compose file
+    /// path glob (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    run_as_user: Vec<RunAsUserRule>,
+    /// How to list the containers of apps: from the docker engine API, or with
+    /// `docker compose ps`. Defaults to the API, unless a docker command prefix
+    /// or run-as-user is set (which only apply to the CLI) or the docker host
+    /// is only reachable through the CLI (e.g. 'ssh://'). Either way, the
+    /// compose files are parsed with the docker CLI, which is thus required.
+    #[arg(long, value_enum)]
+    docker_backend: Option<DockerBackend>,
+    /// Addresses or CIDR ranges of reverse proxies whose Forwarded and
+    /// X-Forwarded-For headers are trusted to contain the real client address
+    #[arg(long)]
+    trusted_proxies: Vec<String>,
+    /// Require connections to start with a PROXY protocol (v1 or v2) header,
+    /// as sent by e.g. HAProxy or Traefik in TCP mode, and take the client
+    /// address from it
+    #[arg(long)]
+    proxy_protocol: bool,
+    /// Web configuration file in the schema of the Prometheus exporter toolkit,
+    /// with TLS, basic auth and HTTP server settings
+    #[arg(long = "web.config.file")]
+    web_config_file: Option<std::path::PathBuf>,
+    /// PEM-encoded certificate (chain) to serve HTTPS with, reloaded when the
+    /// file changes. Requires the key file.
+    #[arg(long, requires = "tls_key_file")]
+    tls_cert_file: Option<std::path::PathBuf>,
+    /// PEM-encoded private key of the TLS certificate
+    #[arg(long, requires = "tls_cert_file")]
+    tls_key_file: Option<std::path::PathBuf>,
+    /// Origins allowed to query the exporter from the browser (CORS), or '*'
+    /// for any
+    #[arg(long)]
+    cors_allowed_origins: Vec<String>,
+    /// Methods allowed in cross-origin requests
+    #[arg(long, default_values = ["GET", "HEAD"])]
+    cors_allowed_methods: Vec<String>,
+    /// Users allowed to scrape the metrics with basic auth, as <user>:<bcrypt
+    /// hash of the password>
+    #[arg(long)]
+    #[serde(default)]
+    basic_auth_users: Vec<String>,
+    /// File containing a bearer token allowed to scrape the metrics
+    #[arg(long)]
+    bearer_token_file: Option<std::path::PathBuf>,
+    /// File containing the bearer token required to access the /debug/state
+    /// endpoint, which is disabled if not set
+    #[arg(long)]
+    debug_token_file: Option<std::path::PathBuf>,
+    /// Tenants who can each see only their own apps, with their bearer token
+    /// (config file only). If set, the metrics endpoints and the JSON API
+    /// require the token of a tenant.
+    #[arg(skip)]
+    #[serde(default)]
+    tenants: Vec<TenantConfig>,
+    /// Include the error code, failing app and an excerpt of the docker
+    /// command's stderr in error responses, instead of a generic message
+    #[arg(long)]
+    debug_errors: bool,
+    /// HTTP/TCP checks the exporter runs itself, for services without a
+    /// healthcheck (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    synthetic_checks: Vec<SyntheticCheckConfig>,
+    /// Apps expected on the host, by compose name or path (of the compose file
+    /// or its directory), to report missing and unexpected apps
+    #[arg(long)]
+    #[serde(default)]
+    inventory: Vec<String>,
+    /// Other compose-apps-exporter instances to scrape and re-expose the
+    /// metrics of, with a source_host label (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    federation: Vec<FederationTargetConfig>,
+    /// State services are expected to be in, if not running (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    expected_states: Vec<ExpectedStateRule>,
+    /// File containing the bearer token required to put apps in maintenance
+    /// through the /maintenance endpoint, which is disabled if not set
+    #[arg(long)]
+    maintenance_token_file: Option<std::path::PathBuf>,
+    /// Keep reporting the last state and health seen outside of maintenance
+    /// for services in maintenance
+    #[arg(long)]
+    maintenance_hold_last_state: bool,
+    /// Recurring windows during which apps or services are allowed to be
+    /// down (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    downtime_windows: Vec<DowntimeWindowConfig>,
+    /// Named groups of apps, by compose file globs, each served under
+    /// <metrics path>/<group name> (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    groups: HashMap<String, Vec<String>>,
+    /// Apps to collect besides the ones the globs match, each with its compose
+    /// files, project name, working directory, env file and static labels
+    /// (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    apps: Vec<AppConfig>,
+    /// Environment and tier labels to assign to apps, by compose name and/or
+    /// compose file path glob (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    app_labels: Vec<AppLabelsRule>,
+    /// Regexes whose named capture groups, applied to the compose file path,
+    /// become labels of the app's metrics (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    path_label_rules: Vec<String>,
+    /// Labels to add to every series, as <name>=<value> (e.g.
+    /// 'environment=prod'). App labels of the same name override them.
+    #[arg(long)]
+    #[serde(default)]
+    extra_labels: Vec<String>,
+    /// Measure the disk usage of the host paths bind-mounted into services
+    #[arg(long)]
+    bind_mount_usage: bool,
+    /// How many directories deep to descend into bind-mounted host paths when
+    /// measuring their disk usage
+    #[arg(long, default_value = "16")]
+    bind_mount_usage_max_depth: usize,
+    /// Time after which to stop measuring the disk usage of a bind-mounted
+    /// host path, reporting what was measured up to then
+    #[arg(long, default_value = "5")]
+    bind_mount_usage_timeout_seconds: u64,
+    /// Report the disk usage of the named volumes apps declare (using `docker
+    /// system df`, which can be slow with many volumes)
+    #[arg(long)]
+    volume_usage: bool,
+    /// Report the size of the images services run
+    #[arg(long)]
+    image_size: bool,
+    /// Report the platform of the images services run, and whether they run
+    /// emulated on the docker engine's architecture
+    #[arg(long)]
+    image_platform: bool,
+    /// Report whether the images services run are stale, by comparing their
+    /// digests with the ones their tags have in the registry (using `docker
+    /// buildx imagetools inspect`)
+    #[arg(long)]
+    image_update_check: bool,
+    /// How often to check the registry for the digest of each image, as
+    /// registries rate-limit requests
+    #[arg(long, default_value = "3600")]
+    image_update_check_interval_seconds: u64,
+    /// Report the time of the most recent log line of each container (using
+    /// one `docker logs` call per container)
+    #[arg(long)]
+    last_log_timestamp: bool,
+    /// Report the CPU, memory, network and block I/O usage of services (using
+    /// one `docker stats` call per app, which takes a couple of seconds)
+    #[arg(long)]
+    enable_stats: bool,
+    /// Report whether services run with the config their compose file
+    /// currently renders to, i.e. whether it was edited without running
+    /// `docker compose up` (using one extra `docker compose config` call per
+    /// app)
+    #[arg(long)]
+    config_drift: bool,
+    /// Environment variables (e.g. VERSION or GIT_SHA) whose values to add to
+    /// compose_service_info as labels, named after the lowercased variable
+    /// with an 'env_' prefix
+    #[arg(long)]
+    #[serde(default)]
+    service_info_environment: Vec<String>,
+    /// What to do with a scrape while a collection of the same metrics is
+    /// already in flight: share its result (coalesce), wait for it to finish
+    /// and collect anew, or respond with 503 Service Unavailable (reject)
+    #[arg(long, value_enum, default_value = "coalesce")]
+    overlapping_scrapes: OverlapPolicy,
+    /// Share the result of collections in flight for less than this long,
+    /// regardless of `overlapping_scrapes`
+    #[arg(long, default_value = "0")]
+    overlapping_scrapes_fresh_seconds: f64,
+    /// Serve the result of the last collection to scrapes arriving less than
+    /// this long after it finished, instead of collecting anew (0 to always
+    /// collect)
+    #[arg(long, default_value = "0")]
+    min_scrape_interval_seconds: f64,
+    /// Collect all apps in the background this often, serving scrapes of all
+    /// apps the result of the last collection instead of collecting on every
+    /// scrape (0 to collect on scrape)
+    #[arg(long, default_value = "0")]
+    collect_interval_seconds: f64,
+    /// Time to leave of the scrape timeout Prometheus sends (in the
+    /// X-Prometheus-Scrape-Timeout-Seconds header) for serving the metrics:
+    /// apps not collected by then are reported as timed out
+    #[arg(long, default_value = "0.5")]
+    scrape_timeout_offset_seconds: f64,
+    /// Maximum number of apps to collect at the same time
+    #[arg(long, default_value = "8")]
+    max_concurrent_apps: usize,
+    /// Time a service may be down or starting (e.g. during a rollout, or
+    /// right after the exporter started) before its state and health series
+    /// reflect it
+    #[arg(long, default_value = "0")]
+    grace_period_seconds: u64,
+    /// Number of consecutive collections a new service state or health must be
+    /// observed in before it's exported, per metric (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    debounce: DebounceConfig,
+    /// Windows (in minutes) over which to export the fraction of time each
+    /// service was running and healthy, e.g. 5 for
+    /// compose_service_available_ratio_5m
+    #[arg(long)]
+    #[serde(default)]
+    availability_windows_minutes: Vec<u64>,
+    /// File to keep a history of service availability transitions in, to
+    /// serve uptime percentages over the last 24h, 7d and 30d on
+    /// /api/v1/uptime
+    #[arg(long)]
+    uptime_history_file: Option<std::path::PathBuf>,
+    /// Also export the uptime percentages from the uptime history as metrics
+    #[arg(long)]
+    uptime_history_metrics: bool,
+    /// Only start listening once the warm-up collection has finished, rather
+    /// than reporting not ready on /-/ready until then
+    #[arg(long)]
+    warm_up_before_listen: bool,
+    /// Metric names to use: the legacy ones, ones following the Prometheus
+    /// naming conventions, or both (to migrate dashboards and alerts)
+    #[arg(long, value_enum, default_value = "legacy")]
+    #[serde(default)]
+    metric_naming: MetricNaming,
+    /// Prefix of the names of all metrics instead of 'compose' (e.g.
+    /// 'acme_compose' for 'acme_compose_service_health')
+    #[arg(long, default_value = metric_naming::DEFAULT_PREFIX)]
+    metric_prefix: String,
+    /// Base URL of an Alertmanager to send alerts for services that are down
+    /// or unhealthy to, e.g. http://localhost:9093
+    #[arg(long)]
+    alertmanager_url: Option<String>,
+    /// How often to collect all apps and (re)send alerts to the Alertmanager
+    #[arg(long, default_value = "60")]
+    alertmanager_interval_seconds: u64,
+    /// Labels to add to the alerts sent to the Alertmanager (config file only)
+    #[arg(skip)]
+    #[serde(default)]
+    alertmanager_labels: HashMap<String, String>,
+    /// Annotations to add to the alerts sent to the Alertmanager (config file
+    /// only)
+    #[arg(skip)]
+    #[serde(default)]
+    alertmanager_annotations: HashMap<String, String>,
+    /// Zabbix server or proxy (`host` or `host:port`) to push the state and
+    /// health of services to, with the Zabbix sender protocol
+    #[arg(long)]
+    zabbix_server: Option<String>,
+    /// Host to push the items as, as configured in Zabbix (defaults to the
+    /// exporter's host name)
+    #[arg(long)]
+    zabbix_host: Option<String>,
+    /// Key of the pushed items, with `{metric}` (state or health),
+    /// `{compose_name}` and `{service_name}` replaced
+    #[arg(
+        long,
+        default_value = "compose.service.{metric}[{compose_name},{service_name}]"
+    )]
+    zabbix_key_template: String,
+    /// How often to collect all apps and push the state and health of services
+    /// to Zabbix
+    #[arg(long, default_value = "60")]
+    zabbix_interval_seconds: u64,
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<ExporterCommand>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ExporterCommand {
+    /// Print every metric the current configuration can emit (name, type, help
+    /// and labels), then exit
+    MetricsSchema {
+        #[arg(long, value_enum, default_value = "json")]
+        format: SchemaFormat,
+    },
+    /// Show a live table of the services of all apps, with their state,
+    /// health and uptime, instead of serving metrics
+    Watch {
+        /// Time between refreshes
+        #[arg(long, default_value = "5")]
+        interval_seconds: u64,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum SchemaFormat {
+    Json,
+    Yaml,
 }
 
 struct ParsedConfig {
     pub compose_configs_glob: Vec<String>,
+    pub compose_configs_dir: Vec<std::path::PathBuf>,
+    pub discovery: DiscoveryOptions,
+    pub discover_running: bool,
     pub port: u16,
     pub address: IpAddr,
+    pub metrics_path: String,
+    pub gzip_min_size_bytes: usize,
+    pub docker_host: Option<String>,
+    pub docker_command_prefix: Vec<String>,
+    pub docker_command_timeout: Option<Duration>,
+    pub run_as_user: RunAsUser,
+    pub docker_backend: DockerBackend,
+    pub trusted_proxies: Vec<IpNet>,
+    pub proxy_protocol: bool,
+    pub tls: Option<TlsOptions>,
+    pub web: WebConfig,
+    pub cors: Cors,
+    pub scrape_auth: ScrapeAuth,
+    pub debug_token: Option<String>,
+    pub tenants: Tenants,
+    pub debug_errors: bool,
+    pub synthetic_checks: Vec<SyntheticCheck>,
+    pub federation: Vec<FederationTarget>,
+    pub inventory: Vec<String>,
+    pub expected_states: Vec<ExpectedStateRule>,
+    pub maintenance_token: Option<String>,
+    pub maintenance_hold_last_state: bool,
+    pub downtime_windows: Vec<DowntimeWindow>,
+    pub groups: HashMap<String, Vec<String>>,
+    pub apps: Apps,
+    pub app_labels: AppLabels,
+    pub extra_labels: Vec<(String, String)>,
+    pub bind_mount_usage: bool,
+    pub bind_mount_usage_max_depth: usize,
+    pub bind_mount_usage_timeout: Duration,
+    pub volume_usage: bool,
+    pub image_size: bool,
+    pub image_platform: bool,
+    pub image_update_check: bool,
+    pub image_update_check_interval: Duration,
+    pub last_log_timestamp: bool,
+    pub enable_stats: bool,
+    pub config_drift: bool,
+    pub service_info_environment: Vec<String>,
+    pub overlapping_scrapes: OverlapPolicy,
+    pub overlapping_scrapes_fresh_for: Duration,
+    pub min_scrape_interval: Duration,
+    pub collect_interval: Duration,
+    pub scrape_timeout_offset: Duration,
+    pub max_concurrent_apps: usize,
+    pub grace_period: Duration,
+    pub debounce: DebounceConfig,
+    pub availability_windows: Vec<Duration>,
+    pub uptime_history_file: Option<std::path::PathBuf>,
+    pub uptime_history_metrics: bool,
+    pub warm_up_before_listen: bool,
+    pub metric_naming: MetricNaming,
+    pub metric_prefix: String,
+    pub alertmanager_url: Option<Uri>,
+    pub alertmanager_interval: Duration,
+    pub alertmanager_labels: HashMap<String, String>,
+    pub alertmanager_annotations: HashMap<String, String>,
+    pub zabbix_server: Option<String>,
+    pub zabbix_host: String,
+    pub zabbix_key_template: String,
+    pub zabbix_interval: Duration,
+    pub command: Option<ExporterCommand>,
+    /// Hash of the configuration, see [`config_reload::config_hash`]
+    pub hash: u64,
 }
 
 impl TryFrom<Config> for ParsedConfig {
     type Error = Box<dyn std::error::Error>;
 
     fn try_from(config: Config) -> Result<Self, Self::Error> {
+        let hash = config_reload::config_hash(&serde_json::to_value(&config)?);
         let address = IpAddr::from_str(&config.address)?;
+        if !config.metrics_path.starts_with('/')
+            || HeaderValue::from_str(&config.metrics_path).is_err()
+        {
+            return Err(format!(
+                "Invalid metrics path {:?}, must start with '/'",
+                config.metrics_path
+            )
+            .into());
+        }
+        if !metric_naming::is_valid_prefix(&config.metric_prefix) {
+            return Err(format!(
+                "Invalid metric prefix {:?}, must be a valid metric name",
+                config.metric_prefix
+            )
+            .into());
+        }
+        if (config.debounce.state > 1 || config.debounce.health > 1)
+            && config.collect_interval_seconds <= 0.0
+        {
+            return Err(
+                "debounce requires collect_interval_seconds, as state changes are only debounced across background collections"
+                    .into(),
+            );
+        }
+        if let Some(group_name) = config.groups.keys().find(|group_name| {
+            group_name.is_empty()
+                || !group_name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        }) {
+            return Err(format!(
+                "Invalid group name {:?}, may only contain letters, digits, '-' and '_'",
+                group_name
+            )
+            .into());
+        }
+        if config.availability_windows_minutes.contains(&0) {
+            return Err("Availability windows must be at least 1 minute long".into());
+        }
+        let availability_windows = config
+            .availability_windows_minutes
+            .iter()
+            .map(|minutes| Duration::from_secs(minutes * 60))
+            .collect();
+        let alertmanager_url = config
+            .alertmanager_url
+            .as_deref()
+            .map(|url| match Uri::from_str(url) {
+                Ok(uri) if uri.scheme_str() == Some("http") => Ok(uri),
+                _ => Err(format!(
+                    "Invalid Alertmanager URL {:?}, must be an http:// URL",
+                    url
+                )),
+            })
+            .transpose()?;
+        if config.alertmanager_interval_seconds == 0 {
+            return Err("The Alertmanager interval must be at least 1 second".into());
+        }
+        if config.max_concurrent_apps == 0 {
+            return Err("The maximum number of concurrent apps must be at least 1".into());
+        }
+        if config.zabbix_interval_seconds == 0 {
+            return Err("The Zabbix interval must be at least 1 second".into());
+        }
+        let zabbix_host = match (config.zabbix_host, &config.zabbix_server) {
+            (Some(zabbix_host), _) => zabbix_host,
+            (None, None) => String::new(),
+            (None, Some(_)) => {
+                host_name().ok_or("The host name is unknown, zabbix_host must be set")?
+            }
+        };
+        let docker_host = match (config.docker_host, config.docker_socket) {
+            (Some(_), Some(_)) => {
+                return Err("Only one of docker_host and docker_socket can be set".into())
+            }
+            (Some(docker_host), None) => Some(validate_docker_host(docker_host)?),
+            (None, Some(docker_socket)) => Some(format!("unix://{}", docker_socket)),
+            (None, None) => rootless_docker_socket_path()
+                .map(|socket_path| format!("unix://{}", socket_path.display())),
+        };
+        let docker_command_prefix: Vec<String> = config
+            .docker_command_prefix
+            .map(|prefix| prefix.split_whitespace().map(String::from).collect())
+            .unwrap_or_default();
+        let run_as_user = RunAsUser::new(config.run_as_user, config.run_as_config_owner)?;
+        // e.g. ssh:// hosts
+        let cli_only_docker_host = docker_host.as_deref().is_some_and(|docker_host| {
+            !["unix://", "tcp://", "npipe://"]
+                .iter()
+                .any(|scheme| docker_host.starts_with(scheme))
+        });
+        let cli_required = !docker_command_prefix.is_empty() || run_as_user.is_enabled();
+        let docker_backend = match config.docker_backend {
+            Some(DockerBackend::Api) if cli_required => {
+                return Err(
+                    "The docker command prefix and run-as-user require the cli docker backend"
+                        .into(),
+                )
+            }
+            Some(DockerBackend::Api) if cli_only_docker_host => {
+                return Err(format!(
+                    "The docker host {} requires the cli docker backend",
+                    docker_host.unwrap_or_default()
+                )
+                .into())
+            }
+            Some(docker_backend) => docker_backend,
+            None if cli_required || cli_only_docker_host => DockerBackend::Cli,
+            None => DockerBackend::Api,
+        };
+        let mut web = match &config.web_config_file {
+            Some(_)
+                if config.tls_cert_file.is_some()
+                    || config.tls_key_file.is_some()
+                    || !config.basic_auth_users.is_empty() =>
+            {
+                return Err(
+                    "tls_cert_file, tls_key_file and basic_auth_users can't be combined with web_config_file, which replaces them"
+                        .into(),
+                )
+            }
+            Some(web_config_file) => WebConfig::load(web_config_file)?,
+            None => WebConfig::default(),
+        };
+        let basic_auth_users = if config.web_config_file.is_some() {
+            std::mem::take(&mut web.basic_auth_users)
+        } else {
+            config
+                .basic_auth_users
+                .iter()
+                .map(|user| auth::parse_basic_auth_user(user))
+                .collect::<Result<_, _>>()?
+        };
         Ok(ParsedConfig {
             compose_configs_glob: config.compose_configs_glob,
+            compose_configs_dir: config.compose_configs_dir,
+            discovery: DiscoveryOptions {
+                max_depth: config.discovery_max_depth,
+                symlinks: config.discovery_symlinks,
+                exclude_dirs: config
+                    .discovery_exclude_dirs
+                    .iter()
+                    .map(|pattern| {
+                        glob::Pattern::new(pattern).map_err(|err| {
+                            format!("Invalid excluded directory pattern {:?}: {}", pattern, err)
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+                exclude_globs: config
+                    .compose_configs_exclude_glob
+                    .iter()
+                    .map(|glob| {
+                        glob::Pattern::new(glob)
+                            .map_err(|err| format!("Invalid exclude glob {:?}: {}", glob, err))
+                    })
+                    .collect::<Result<_, _>>()?,
+            },
+            discover_running: config.discover_running,
             port: config.port,
             address,
+            metrics_path: config.metrics_path,
+            gzip_min_size_bytes: config.gzip_min_size_bytes,
+            docker_host,
+            docker_command_prefix,
+            docker_command_timeout: Duration::try_from_secs_f64(
+                config.docker_command_timeout_seconds,
+            )
+            .map(|timeout| Some(timeout).filter(|timeout| !timeout.is_zero()))
+            .map_err(|err| format!("Invalid docker_command_timeout_seconds: {}", err))?,
+            run_as_user,
+            docker_backend,
+            trusted_proxies: config
+                .trusted_proxies
+                .iter()
+                .map(|trusted_proxy| forwarded::parse_trusted_proxy(trusted_proxy))
+                .collect::<Result<_, _>>()?,
+            proxy_protocol: config.proxy_protocol,
+            tls: match (config.tls_cert_file, config.tls_key_file) {
+                (Some(cert_file), Some(key_file)) => Some(TlsOptions::new(cert_file, key_file)),
+                (None, None) => web.tls.take(),
+                _ => return Err("tls_cert_file and tls_key_file must be set together".into()),
+            },
+            web,
+            cors: Cors::new(config.cors_allowed_origins, &config.cors_allowed_methods)?,
+            scrape_auth: match ScrapeAuth::new(
+                basic_auth_users,
+                config.bearer_token_file.as_deref(),
+            )? {
+                scrape_auth if scrape_auth.is_enabled() && !config.tenants.is_empty() => {
+                    return Err(
+                        "basic_auth_users and bearer_token_file can't be combined with tenants, whose tokens already protect the metrics"
+                            .into(),
+                    )
+                }
+                scrape_auth => scrape_auth,
+            },
+            debug_token: config
+                .debug_token_file
+                .as_deref()
+                .map(|path| auth::read_token_file(path, "debug"))
+                .transpose()?,
+            tenants: Tenants::new(config.tenants)?,
+            debug_errors: config.debug_errors,
+            synthetic_checks: config
+                .synthetic_checks
+                .into_iter()
+                .map(SyntheticCheck::try_from)
+                .collect::<Result<_, _>>()?,
+            inventory: config.inventory,
+            federation: config
+                .federation
+                .into_iter()
+                .map(FederationTarget::try_from)
+                .collect::<Result<_, _>>()?,
+            expected_states: config.expected_states,
+            maintenance_token: config
+                .maintenance_token_file
+                .as_deref()
+                .map(|path| auth::read_token_file(path, "maintenance"))
+                .transpose()?,
+            maintenance_hold_last_state: config.maintenance_hold_last_state,
+            downtime_windows: config
+                .downtime_windows
+                .into_iter()
+                .map(DowntimeWindow::try_from)
+                .collect::<Result<_, _>>()?,
+            groups: config.groups,
+            app_labels: AppLabels::new(config.app_labels, config.path_label_rules, &config.apps)?,
+            apps: Apps::new(config.apps)?,
+            extra_labels: config
+                .extra_labels
+                .iter()
+                .map(|extra_label| extra_labels::parse(extra_label))
+                .collect::<Result<_, _>>()?,
+            bind_mount_usage: config.bind_mount_usage,
+            bind_mount_usage_max_depth: config.bind_mount_usage_max_depth,
+            bind_mount_usage_timeout: Duration::from_secs(config.bind_mount_usage_timeout_seconds),
+            volume_usage: config.volume_usage,
+            image_size: config.image_size,
+            image_platform: config.image_platform,
+            image_update_check: config.image_update_check,
+            image_update_check_interval: Duration::from_secs(
+                config.image_update_check_interval_seconds,
+            ),
+            last_log_timestamp: config.last_log_timestamp,
+            enable_stats: config.enable_stats,
+            config_drift: config.config_drift,
+            service_info_environment: match config
+                .service_info_environment
+                .iter()
+                .find(|variable| !service_info::is_valid_environment_label(variable))
+            {
+                Some(variable) => {
+                    return Err(format!(
+                        "Invalid service_info_environment variable {:?}: only letters, digits and underscores are allowed",
+                        variable
+                    )
+                    .into())
+                }
+                None => config.service_info_environment,
+            },
+            overlapping_scrapes: config.overlapping_scrapes,
+            overlapping_scrapes_fresh_for: Duration::try_from_secs_f64(
+                config.overlapping_scrapes_fresh_seconds,
+            )
+            .map_err(|err| format!("Invalid overlapping_scrapes_fresh_seconds: {}", err))?,
+            min_scrape_interval: Duration::try_from_secs_f64(config.min_scrape_interval_seconds)
+                .map_err(|err| format!("Invalid min_scrape_interval_seconds: {}", err))?,
+            collect_interval: Duration::try_from_secs_f64(config.collect_interval_seconds)
+                .map_err(|err| format!("Invalid collect_interval_seconds: {}", err))?,
+            scrape_timeout_offset: Duration::try_from_secs_f64(
+                config.scrape_timeout_offset_seconds,
+            )
+            .map_err(|err| format!("Invalid scrape_timeout_offset_seconds: {}", err))?,
+            max_concurrent_apps: config.max_concurrent_apps,
+            grace_period: Duration::from_secs(config.grace_period_seconds),
+            debounce: config.debounce,
+            availability_windows,
+            uptime_history_file: config.uptime_history_file,
+            uptime_history_metrics: config.uptime_history_metrics,
+            warm_up_before_listen: config.warm_up_before_listen,
+            metric_naming: config.metric_naming,
+            metric_prefix: config.metric_prefix,
+            alertmanager_url,
+            alertmanager_interval: Duration::from_secs(config.alertmanager_interval_seconds),
+            alertmanager_labels: config.alertmanager_labels,
+            alertmanager_annotations: config.alertmanager_annotations,
+            zabbix_server: config.zabbix_server,
+            zabbix_host,
+            zabbix_key_template: config.zabbix_key_template,
+            zabbix_interval: Duration::from_secs(config.zabbix_interval_seconds),
+            // Subcommands are CLI-only, see `get_config`
+            command: None,
+            hash,
         })
     }
 }
 
-#[derive(Deserialize)]
-struct ComposeService {
-    container_name: String,
+fn validate_docker_host(docker_host: String) -> Result<String, Box<dyn std::error::Error>> {
+    let scheme = docker_host.split_once("://").map(|(scheme, _)| scheme);
+    if !scheme.is_some_and(|scheme| DOCKER_HOST_SCHEMES.contains(&scheme)) {
+        return Err(format!(
+            "Invalid docker host {:?}, expected one of the schemes: {}",
+            docker_host,
+            DOCKER_HOST_SCHEMES.join(", ")
+        )
+        .into());
+    }
+    if scheme == Some("npipe") && !cfg!(target_os = "windows") {
+        return Err("Named pipe (npipe://) docker hosts are only supported on Windows".into());
+    }
+    Ok(docker_host)
 }
 
-#[derive(Deserialize)]
-struct ComposeConfig {
-    name: String,
-    services: HashMap<String, ComposeService>,
+/// Get the socket path of a rootless docker daemon run by the current user, if
+/// there is one and the `DOCKER_HOST` environment variable doesn't already
+/// point the docker CLI elsewhere
+fn rootless_docker_socket_path() -> Option<std::path::PathBuf> {
+    if std::env::var_os("DOCKER_HOST").is_some() {
+        return None;
+    }
+    let socket_path = Path::new(&std::env::var_os("XDG_RUNTIME_DIR")?).join("docker.sock");
+    socket_path.exists().then_some(socket_path)
+}
+
+/// State shared by all requests
+struct Exporter {
+    /// Swapped out as a whole when the config is reloaded
+    config: RwLock<Arc<ParsedConfig>>,
+    config_reloads: Mutex<ConfigReloads>,
+    in_flight_collections: InFlightCollections,
+    docker: DockerCli,
+    started_at: Instant,
+    /// Whether the warm-up collection has finished
+    ready: AtomicBool,
+    /// Whether any collection has succeeded since the exporter started
+    collected_successfully: AtomicBool,
+    last_scrape: Mutex<Option<ScrapeRecord>>,
+    /// Outcome of the last collection of the background collection task
+    last_background_collection: Mutex<Option<ScrapeRecord>>,
+    maintenance: Maintenance,
+    grace_periods: GracePeriods,
+    debouncer: Debouncer,
+    availability: Availability,
+    uptime_history: Option<UptimeHistory>,
+    /// Config problems last logged, by compose file
+    logged_config_problems: Mutex<HashMap<std::path::PathBuf, Vec<String>>>,
+    /// Name conflicts between apps last logged
+    logged_name_conflicts: Mutex<Vec<String>>,
+    /// Collection errors last logged, by compose file of the failing app
+    logged_app_errors: Mutex<HashMap<std::path::PathBuf, String>>,
+    alerts: Alerts,
+    zabbix_items: ZabbixItems,
+    /// Compose names of the apps collected, by compose file, to scope the JSON
+    /// API to tenants
+    compose_names: Mutex<HashMap<std::path::PathBuf, String>>,
+    engine_platform: EnginePlatform,
+    registry_digests: RegistryDigests,
+    /// Details of containers from their last inspection, by container ID
+    container_details: ContainerDetailsCache<InspectedContainer>,
+}
+
+impl Exporter {
+    /// Get the currently applied config
+    fn config(&self) -> Arc<ParsedConfig> {
+        self.config
+            .read()
+            .expect("config lock shouldn't be poisoned")
+            .clone()
+    }
+
+    /// Reload the config from the config files, environment and CLI args,
+    /// keeping the current one if the new one is invalid
+    fn reload_config(&self) {
+        let successful = match get_config() {
+            Ok(config) => {
+                let current_config = self.config();
+                // Read once at startup, e.g. to set up the listener or the
+                // docker CLI
+                let restart_required: Vec<&str> = [
+                    ("address", config.address != current_config.address),
+                    ("port", config.port != current_config.port),
+                    (
+                        "proxy_protocol",
+                        config.proxy_protocol != current_config.proxy_protocol,
+                    ),
+                    ("TLS", config.tls != current_config.tls),
+                    ("HTTP/2", config.web.http2 != current_config.web.http2),
+                    (
+                        "docker_host",
+                        config.docker_host != current_config.docker_host,
+                    ),
+                    (
+                        "docker_command_prefix",
+                        config.docker_command_prefix != current_config.docker_command_prefix,
+                    ),
+                    (
+                        "docker_command_timeout_seconds",
+                        config.docker_command_timeout != current_config.docker_command_timeout,
+                    ),
+                    (
+                        "docker_backend",
+                        config.docker_backend != current_config.docker_backend,
+                    ),
+                    (
+                        "run_as_user",
+                        config.run_as_user != current_config.run_as_user,
+                    ),
+                    (
+                        "maintenance_hold_last_state",
+                        config.maintenance_hold_last_state
+                            != current_config.maintenance_hold_last_state,
+                    ),
+                    (
+                        "uptime_history_file",
+                        config.uptime_history_file != current_config.uptime_history_file,
+                    ),
+                ]
+                .into_iter()
+                .filter(|(_, changed)| *changed)
+                .map(|(setting, _)| setting)
+                .collect();
+                if !restart_required.is_empty() {
+                    eprintln!(
+                        "Changes to {} take effect after a restart",
+                        restart_required.join(", ")
+                    );
+                }
+                *self
+                    .config
+                    .write()
+                    .expect("config lock shouldn't be poisoned") = Arc::new(config);
+                println!("Reloaded config");
+                true
+            }
+            Err(e) => {
+                eprintln!("Error reloading config, keeping the current one: \n{}", e);
+                false
+            }
+        };
+        self.config_reloads
+            .lock()
+            .expect("config reloads lock shouldn't be poisoned")
+            .record(successful);
+    }
+}
+
+/// Outcome of a metrics scrape
+struct ScrapeRecord {
+    at: SystemTime,
+    duration: Duration,
+    error: Option<ScrapeError>,
+}
+
+/// Response of the `/version` endpoint
+#[derive(Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    git_revision: &'static str,
+    /// RFC 3339 date-time
+    build_date: &'static str,
+    /// Enabled cargo features
+    features: Vec<&'static str>,
+}
+
+impl VersionInfo {
+    fn of_this_build() -> Self {
+        VersionInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_revision: env!("BUILD_GIT_REVISION"),
+            build_date: env!("BUILD_DATE"),
+            features: env!("BUILD_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "PascalCase")]
 struct Container {
+    /// Missing from the `docker compose ps` output on some versions
+    #[serde(default, rename = "ID")]
+    id: Option<String>,
     name: String,
+    /// Name of the compose service the container belongs to, missing from the
+    /// `docker compose ps` output on some versions
+    #[serde(default)]
+    service: String,
+    /// Number of the container within its service (e.g. 2 for the second
+    /// replica), only known after inspecting it
+    #[serde(skip)]
+    number: Option<u32>,
     /// One of: created, restarting, running, removing, paused, exited, or dead
     state: String,
-    // /// e.g. 'Up x minutes (healthy)'
-    // status: String,
-    health: String,
+    /// e.g. 'Up x minutes (healthy)', missing from the `docker compose ps`
+    /// output on some versions
+    #[serde(default)]
+    status: Option<String>,
+    /// Missing from the `docker compose ps` output on some compose/docker
+    /// versions, see [`fill_missing_container_details`]
+    #[serde(default)]
+    health: Option<String>,
+    /// Exit code of the container's last run (0 while it's running)
+    #[serde(default)]
+    exit_code: Option<i64>,
+    /// Number of times the docker engine restarted the container (by its
+    /// restart policy), only known after inspecting it
+    #[serde(skip)]
+    restart_count: Option<i64>,
+    /// Whether the container's last run was killed for running out of memory,
+    /// only known after inspecting it
+    #[serde(skip)]
+    oom_killed: Option<bool>,
+    /// When the container was created, in seconds since the epoch, only known
+    /// after inspecting it
+    #[serde(skip)]
+    created_at: Option<f64>,
+    /// When the container's last run started, in seconds since the epoch,
+    /// only known after inspecting it (and if it ever started)
+    #[serde(skip)]
+    started_at: Option<f64>,
+    /// ID of the image the container runs (e.g. 'sha256:...'), only known
+    /// after inspecting it
+    #[serde(skip)]
+    image_id: Option<String>,
+    /// Version of docker compose that created the container, only known after
+    /// inspecting it
+    #[serde(skip)]
+    compose_version: Option<String>,
+    /// Hash of the service config the container was created with, only known
+    /// after inspecting it
+    #[serde(skip)]
+    config_hash: Option<String>,
+}
+
+/// Label docker compose sets on the containers it creates to its own version
+static COMPOSE_VERSION_LABEL: &str = "com.docker.compose.version";
+/// Label docker compose sets on the containers it creates to the number of the
+/// container within its service
+static CONTAINER_NUMBER_LABEL: &str = "com.docker.compose.container-number";
+
+/// Parse a time as the docker engine reports it, e.g.
+/// '2024-05-01T12:00:00.123456789Z', to seconds since the epoch. The zero time
+/// the engine reports for e.g. containers that never started is `None`.
+fn parse_engine_time(time: &str) -> Option<f64> {
+    let time = chrono::DateTime::parse_from_rfc3339(time).ok()?;
+    if time.timestamp() <= 0 {
+        return None;
+    }
+    Some(time.timestamp_millis() as f64 / 1000.0)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedContainer {
+    id: String,
+    /// e.g. '/my-container'
+    name: String,
+    restart_count: i64,
+    created: String,
+    image: String,
+    config: InspectedContainerConfig,
+    state: InspectedContainerState,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedContainerConfig {
+    labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedContainerState {
+    /// Absent if the container has no healthcheck
+    health: Option<InspectedContainerHealth>,
+    #[serde(rename = "OOMKilled")]
+    oom_killed: bool,
+    exit_code: i64,
+    started_at: String,
+    #[serde(default)]
+    finished_at: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedContainerHealth {
+    /// One of: starting, healthy, or unhealthy
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ComposeProject {
+    name: String,
+    /// Number of containers per state, e.g. 'running(2), exited(1)'
+    #[serde(default)]
+    status: String,
+    /// Comma-separated list of the config files the project was started from
+    config_files: String,
+}
+
+impl ComposeProject {
+    /// Whether any of the project's containers is running
+    fn is_running(&self) -> bool {
+        self.status.contains("running")
+    }
+
+    fn config_file_paths(&self) -> impl Iterator<Item = &Path> {
+        self.config_files
+            .split(',')
+            .filter(|path| !path.is_empty())
+            .map(Path::new)
+    }
 }
 
 fn config_paths_from_globs(
     config_path_globs: &[String],
+    scan_dirs: &[std::path::PathBuf],
+    options: &DiscoveryOptions,
 ) -> Result<Vec<std::path::PathBuf>, Box<dyn std::error::Error>> {
-    let paths: Vec<_> = config_path_globs
-        .iter()
-        .map(|glob| glob::glob(glob).map_err(|err| format!("Invalid glob: {}", err)))
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .flatten()
-        .collect::<Result<Vec<_>, _>>()?;
-    let config_file_paths = paths
-        .iter()
-        .map(|path| {
-            if path.is_dir() {
-                path.join("docker-compose.yml")
-            } else if path.is_file() {
-                path.clone()
-            } else {
-                panic!("Invalid path: {}", path.display());
-            }
+    Ok(discover_config_paths(config_path_globs, scan_dirs, options)?.config_paths)
+}
+
+/// Outcome of expanding the compose configs globs
+struct Discovery {
+    config_paths: Vec<std::path::PathBuf>,
+    /// Number of paths each glob matched (or compose files each scanned
+    /// directory contains), in order
+    glob_matches: Vec<(String, usize)>,
+    /// Time taken to expand the globs and resolve the matched paths
+    duration: Duration,
+}
+
+/// Expand the given globs and scan the given directories for compose files
+fn discover_config_paths(
+    config_path_globs: &[String],
+    scan_dirs: &[std::path::PathBuf],
+    options: &DiscoveryOptions,
+) -> Result<Discovery, Box<dyn std::error::Error>> {
+    let started_at = Instant::now();
+    let mut config_paths = vec![];
+    let mut glob_matches = vec![];
+    for glob in config_path_globs {
+        let paths: Vec<_> = discovery::expand(glob, options)
+            .map_err(|err| ScrapeError::new(ErrorCode::DiscoveryFailed, err))?
+            .into_iter()
+            // Override files are passed along with the file they override
+            .filter(|path| !(path.is_file() && discovery::is_override_file(path)))
+            .filter_map(|path| {
+                if path.is_dir() {
+                    Some(discovery::compose_file_in(&path))
+                } else if path.is_file() {
+                    Some(path)
+                } else {
+                    // e.g. a dangling symlink, or a path removed since the glob
+                    // was expanded
+                    eprintln!(
+                        "Skipping {}, which is neither a file nor a directory",
+                        path.display()
+                    );
+                    None
+                }
+            })
+            .collect();
+        glob_matches.push((glob.clone(), paths.len()));
+        config_paths.extend(paths);
+    }
+    for scan_dir in scan_dirs {
+        let paths = discovery::scan(scan_dir, options)
+            .map_err(|err| ScrapeError::new(ErrorCode::DiscoveryFailed, err))?;
+        glob_matches.push((scan_dir.display().to_string(), paths.len()));
+        config_paths.extend(paths);
+    }
+    Ok(Discovery {
+        config_paths,
+        glob_matches,
+        duration: started_at.elapsed(),
+    })
+}
+
+fn add_discovery_metrics(samples: &mut Samples, discovery: &Discovery) {
+    samples.add(
+        "compose_apps_discovery_duration_seconds",
+        &[],
+        discovery.duration.as_secs_f64(),
+    );
+    for (glob, nbro_matches) in &discovery.glob_matches {
+        samples.add("compose_apps_glob_matches", &[("glob", glob)], nbro_matches);
+    }
+}
+
+/// How to invoke the docker CLI, and the capabilities of the installed version
+struct DockerCli {
+    /// Set when the containers of apps are read from the engine API rather
+    /// than with the CLI
+    api: Option<EngineApi>,
+    /// Docker daemon endpoint, passed as `--host` (rather than `DOCKER_HOST`,
+    /// which e.g. sudo wouldn't pass on)
+    host: Option<String>,
+    /// Program and arguments to run docker through, e.g. `["sudo", "-n"]`
+    command_prefix: Vec<String>,
+    /// Time after which to kill docker commands, see [`run_docker_cmd`]
+    command_timeout: Option<Duration>,
+    run_as_user: RunAsUser,
+    compat: Compat,
+}
+
+impl DockerCli {
+    async fn detect(config: &ParsedConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let host = config.docker_host.clone();
+        let command_prefix = config.docker_command_prefix.clone();
+        let command_timeout = config.docker_command_timeout;
+        let compat = Compat::detect(
+            || docker_command(host.as_deref(), &command_prefix),
+            command_timeout,
+        )
+        .await?;
+        let api =
+            match config.docker_backend {
+                DockerBackend::Api => Some(EngineApi::connect(host.as_deref()).map_err(|err| {
+                    format!("Failed to connect to the docker engine API: {}", err)
+                })?),
+                DockerBackend::Cli => None,
+            };
+        Ok(DockerCli {
+            api,
+            host,
+            command_prefix,
+            command_timeout,
+            run_as_user: config.run_as_user.clone(),
+            compat,
         })
-        .collect();
-    return Ok(config_file_paths);
+    }
+
+    fn command(&self) -> std::process::Command {
+        docker_command(self.host.as_deref(), &self.command_prefix)
+    }
+
+    /// Check that the docker daemon is reachable, without running any compose
+    /// commands
+    async fn ping(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match &self.api {
+            Some(api) => api.ping().await,
+            None => {
+                exec_docker_global_cmd(self, &["version", "--format", "{{.Server.Version}}"])
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Get a docker command to run in the context of the app with the given
+    /// compose file (e.g. as the user owning it)
+    fn app_command(
+        &self,
+        config_path: &Path,
+    ) -> Result<std::process::Command, Box<dyn std::error::Error>> {
+        let mut command = self.command();
+        self.run_as_user
+            .apply(&mut command, config_path, self.host.is_some())
+            .map_err(|err| ScrapeError::new(ErrorCode::RunAsUserFailed, err.to_string()))?;
+        Ok(command)
+    }
+}
+
+fn docker_command(host: Option<&str>, command_prefix: &[String]) -> std::process::Command {
+    let mut command = match command_prefix.split_first() {
+        Some((program, args)) => {
+            let mut command = std::process::Command::new(program);
+            command.args(args).arg("docker");
+            command
+        }
+        None => std::process::Command::new("docker"),
+    };
+    if let Some(host) = host {
+        command.arg("--host").arg(host);
+    }
+    command
 }
 
-fn exec_docker_compose_cmd(
+/// Build a docker compose command for the app with the given compose file,
+/// passing the other compose files and options of the app if it's declared in
+/// the config file, or else the compose file's override files
+fn docker_compose_command(
+    docker: &DockerCli,
     config_path: impl AsRef<std::path::Path>,
+    app: Option<&AppConfig>,
     args: &[&str],
-) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let mut command = std::process::Command::new("docker");
+) -> Result<std::process::Command, Box<dyn std::error::Error>> {
+    let mut command = docker.app_command(config_path.as_ref())?;
     command.arg("compose");
     command.arg("-f").arg(config_path.as_ref());
+    match app {
+        Some(app) => {
+            command.args(app.compose_args());
+        }
+        None => {
+            for override_file in discovery::override_files(config_path.as_ref()) {
+                command.arg("-f").arg(override_file);
+            }
+        }
+    }
     command.args(args);
-    let args_str: Vec<_> = command
-        .get_args()
-        .map(|arg| arg.to_string_lossy())
-        .collect();
-    let cmd_str = format!("docker {}", args_str.join(" "));
-    let output = command.output().map_err(|err| {
-        format!(
-            "Failed to execute `{}` (is docker installed?): {}",
-            cmd_str, err
-        )
-    })?;
+    Ok(command)
+}
+
+async fn exec_docker_compose_cmd(
+    docker: &DockerCli,
+    config_path: impl AsRef<std::path::Path>,
+    app: Option<&AppConfig>,
+    args: &[&str],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let command = docker_compose_command(docker, config_path, app, args)?;
+    exec_docker_cmd(command, docker.command_timeout).await
+}
+
+/// Execute a docker command in the context of the app with the given compose
+/// file, see [`DockerCli::app_command`]
+async fn exec_docker_app_cmd(
+    docker: &DockerCli,
+    config_path: impl AsRef<std::path::Path>,
+    args: &[&str],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut command = docker.app_command(config_path.as_ref())?;
+    command.args(args);
+    exec_docker_cmd(command, docker.command_timeout).await
+}
+
+/// Execute a docker command not bound to any particular compose config (e.g.
+/// `docker compose ls`)
+async fn exec_docker_global_cmd(
+    docker: &DockerCli,
+    args: &[&str],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut command = docker.command();
+    command.args(args);
+    exec_docker_cmd(command, docker.command_timeout).await
+}
+
+pub(crate) async fn exec_docker_cmd(
+    command: std::process::Command,
+    timeout: Option<Duration>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    Ok(exec_docker_cmd_output(command, timeout).await?.stdout)
+}
+
+/// Execute a docker command, failing if it exits unsuccessfully. Returns its
+/// full output, e.g. to inspect warnings printed to stderr.
+async fn exec_docker_cmd_output(
+    command: std::process::Command,
+    timeout: Option<Duration>,
+) -> Result<std::process::Output, Box<dyn std::error::Error>> {
+    let cmd_str = command_to_string(&command);
+    let output = run_docker_cmd(command, timeout).await?;
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!(
-            "`{}` failed with status code {}: {}",
-            cmd_str,
-            output
-                .status
-                .code()
-                .map(|code| code.to_string())
-                .unwrap_or_else(|| "unknown".to_string()),
-            stderr
+        return Err(ScrapeError::new(
+            ErrorCode::DockerCommandFailed,
+            format!(
+                "`{}` failed with status code {}: {}",
+                cmd_str,
+                output
+                    .status
+                    .code()
+                    .map(|code| code.to_string())
+                    .unwrap_or_else(|| "unknown".to_string()),
+                stderr
+            ),
         )
+        .with_stderr(&stderr)
         .into());
     }
-    Ok(output.stdout)
+    Ok(output)
+}
+
+/// Execute a docker command, without checking its exit status (for commands
+/// whose output is still useful when they fail, e.g. `docker image inspect` of
+/// some images that don't exist), killing it if it runs longer than the
+/// timeout or the returned future is dropped
+pub(crate) async fn run_docker_cmd(
+    command: std::process::Command,
+    timeout: Option<Duration>,
+) -> Result<std::process::Output, Box<dyn std::error::Error>> {
+    let cmd_str = command_to_string(&command);
+    let kind = telemetry::command_kind(
+        &command.get_program().to_string_lossy(),
+        &command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>(),
+    );
+    let started_at = Instant::now();
+    // Commands killed because their collection was cancelled (or its deadline
+    // passed) are dropped here, neither failing nor finishing
+    let output = cancellation::output(command, timeout).await;
+    telemetry::record_command(
+        &kind,
+        started_at.elapsed(),
+        output
+            .as_ref()
+            .map_or(true, |output| !output.status.success()),
+    );
+    output.map_err(|err| {
+        if err.kind() == std::io::ErrorKind::TimedOut {
+            return ScrapeError::new(
+                ErrorCode::DockerCommandTimedOut,
+                format!("`{}` hung and was {}", cmd_str, err),
+            )
+            .into();
+        }
+        ScrapeError::new(
+            ErrorCode::DockerUnavailable,
+            format!(
+                "Failed to execute `{}` (is docker installed?): {}",
+                cmd_str, err
+            ),
+        )
+        .into()
+    })
+}
+
+fn command_to_string(command: &std::process::Command) -> String {
+    let args_str: Vec<_> = command
+        .get_args()
+        .map(|arg| arg.to_string_lossy())
+        .collect();
+    format!(
+        "{} {}",
+        command.get_program().to_string_lossy(),
+        args_str.join(" ")
+    )
+}
+
+/// Get the name compose gives a project by default, i.e. the name of the
+/// directory containing its config file, normalized
+fn default_compose_project_name(config_path: impl AsRef<std::path::Path>) -> String {
+    let config_path = config_path.as_ref();
+    let config_path =
+        std::fs::canonicalize(config_path).unwrap_or_else(|_| config_path.to_path_buf());
+    config_path
+        .parent()
+        .and_then(|dir| dir.file_name())
+        .map(|dir_name| dir_name.to_string_lossy())
+        .unwrap_or_default()
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .collect()
 }
 
-fn read_compose_config(
+async fn read_compose_config(
     config_path: impl AsRef<std::path::Path>,
+    app: Option<&AppConfig>,
+    docker: &DockerCli,
 ) -> Result<ComposeConfig, Box<dyn std::error::Error>> {
-    let config = serde_yaml::from_slice(
-        &exec_docker_compose_cmd(&config_path, &["config"]).map_err(|err| {
+    let command =
+        docker_compose_command(docker, &config_path, app, &["config", "--format", "json"])?;
+    let output = exec_docker_cmd_output(command, docker.command_timeout)
+        .await
+        .map_err(|err| {
+            ScrapeError::context(
+                err,
+                format!(
+                    "Failed to execute `docker compose config` for {}",
+                    config_path.as_ref().display()
+                ),
+            )
+        })?;
+    let mut config: ComposeConfig = serde_json::from_slice(&output.stdout).map_err(|err| {
+        ScrapeError::new(
+            ErrorCode::DockerOutputInvalid,
             format!(
-                "Failed to execute `docker compose config` for {}: {}",
+                "Failed to parse `docker compose config` output for {}: {}",
                 config_path.as_ref().display(),
                 err
-            )
-        })?,
-    )
-    .map_err(|err| {
-        format!(
-            "Failed to parse `docker compose config` output for {}: {}",
-            config_path.as_ref().display(),
-            err
+            ),
         )
     })?;
+    if !docker.compat.config_includes_name() {
+        config.name = match app.and_then(|app| app.name.clone()) {
+            Some(name) => name,
+            None => default_compose_project_name(&config_path),
+        };
+    }
+    // e.g. 'WARN[0000] The "TAG" variable is not set. Defaulting to a blank
+    // string.', or 'level=warning msg=...' on older versions
+    config.warnings = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter(|line| line.to_lowercase().contains("warn"))
+        .map(|line| line.trim().to_string())
+        .collect();
     Ok(config)
 }
 
-fn read_running_compose_containers(
+async fn read_running_compose_containers(
     config_path: impl AsRef<std::path::Path>,
+    app: Option<&AppConfig>,
+    compose_name: &str,
+    docker: &DockerCli,
 ) -> Result<Vec<Container>, Box<dyn std::error::Error>> {
-    let running_containers: Vec<Container> = serde_json::from_slice(
-        &exec_docker_compose_cmd(&config_path, &["ps", "--format", "json"]).map_err(|err| {
-            format!(
-                "Failed to execute `docker compose ps` for {}: {}",
-                config_path.as_ref().display(),
-                err
+    if let Some(api) = &docker.api {
+        return api
+            .read_compose_containers(compose_name)
+            .await
+            .map_err(|err| {
+                ScrapeError::context(
+                    err,
+                    format!(
+                        "Failed to read the containers of {}",
+                        config_path.as_ref().display()
+                    ),
+                )
+                .into()
+            });
+    }
+    let running_containers = docker
+        .compat
+        .parse_ps_output(
+            &exec_docker_compose_cmd(docker, &config_path, app, &["ps", "--format", "json"])
+                .await
+                .map_err(|err| {
+                    ScrapeError::context(
+                        err,
+                        format!(
+                            "Failed to execute `docker compose ps` for {}",
+                            config_path.as_ref().display()
+                        ),
+                    )
+                })?,
+        )
+        .map_err(|err| {
+            ScrapeError::new(
+                ErrorCode::DockerOutputInvalid,
+                format!(
+                    "Failed to parse `docker compose ps` output for {}: {}",
+                    config_path.as_ref().display(),
+                    err
+                ),
             )
-        })?,
+        })?;
+    Ok(running_containers)
+}
+
+/// Fill in the details of containers that `docker compose ps` didn't report
+/// (e.g. their health on some versions) or can't (e.g. their restart count),
+/// from the cache or else using a single (batched) `docker inspect` call.
+/// Containers removed in the meantime keep the details `docker compose ps`
+/// reported.
+async fn fill_missing_container_details(
+    docker: &DockerCli,
+    config_path: impl AsRef<std::path::Path>,
+    containers: &mut [Container],
+    cache: &ContainerDetailsCache<InspectedContainer>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut inspect_args = vec!["inspect".to_string()];
+    for container in containers
+        .iter_mut()
+        .filter(|container| container.health.is_none() || container.restart_count.is_none())
+    {
+        // The health changes without the container restarting, so it's only
+        // cached if `docker compose ps` reports it
+        let cached = match (&container.id, &container.status) {
+            (Some(id), Some(status)) if container.health.is_some() => cache.get(id, status),
+            _ => None,
+        };
+        match cached {
+            Some(inspected_container) => fill_container_details(container, inspected_container),
+            None => inspect_args.push(container.id.clone().unwrap_or(container.name.clone())),
+        }
+    }
+    if inspect_args.len() == 1 {
+        return Ok(());
+    }
+    let mut command = docker.app_command(config_path.as_ref())?;
+    command.args(&inspect_args);
+    // `docker inspect` exits unsuccessfully if any of the containers doesn't
+    // exist (anymore), but still prints the ones that do
+    let output = run_docker_cmd(command, docker.command_timeout)
+        .await
+        .map_err(|err| ScrapeError::context(err, "Failed to execute `docker inspect`"))?;
+    let inspected_containers: Vec<InspectedContainer> = serde_json::from_slice(&output.stdout)
+        .map_err(|err| {
+            if output.status.success() {
+                ScrapeError::new(
+                    ErrorCode::DockerOutputInvalid,
+                    format!("Failed to parse `docker inspect` output: {}", err),
+                )
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                ScrapeError::new(
+                    ErrorCode::DockerCommandFailed,
+                    format!("`docker inspect` failed: {}", stderr),
+                )
+                .with_stderr(&stderr)
+            }
+        })?;
+    for inspected_container in inspected_containers {
+        let name = inspected_container.name.trim_start_matches('/');
+        let Some(container) = containers.iter_mut().find(|container| {
+            container
+                .id
+                .as_deref()
+                .map_or(container.name == name, |id| {
+                    inspected_container.id.starts_with(id)
+                })
+        }) else {
+            continue;
+        };
+        if let Some(id) = &container.id {
+            cache.insert(
+                id.clone(),
+                parse_engine_time(&inspected_container.state.started_at),
+                parse_engine_time(&inspected_container.state.finished_at),
+                inspected_container.clone(),
+            );
+        }
+        fill_container_details(container, inspected_container);
+    }
+    Ok(())
+}
+
+/// Fill in the details of the given container from its inspection
+fn fill_container_details(container: &mut Container, inspected_container: InspectedContainer) {
+    let state = inspected_container.state;
+    if container.health.is_none() {
+        container.health = Some(state.health.map(|health| health.status).unwrap_or_default());
+    }
+    container.exit_code.get_or_insert(state.exit_code);
+    container.restart_count = Some(inspected_container.restart_count);
+    container.oom_killed = Some(state.oom_killed);
+    container.created_at = parse_engine_time(&inspected_container.created);
+    container.started_at = parse_engine_time(&state.started_at);
+    container.image_id = Some(inspected_container.image);
+    let mut labels = inspected_container.config.labels.unwrap_or_default();
+    container.compose_version = labels.remove(COMPOSE_VERSION_LABEL);
+    container.config_hash = labels.remove(CONFIG_HASH_LABEL);
+    container.number = labels
+        .get(CONTAINER_NUMBER_LABEL)
+        .and_then(|number| number.parse().ok());
+}
+
+/// Read all compose projects known to the docker engine, including stopped ones
+async fn read_engine_compose_projects(
+    docker: &DockerCli,
+) -> Result<Vec<ComposeProject>, Box<dyn std::error::Error>> {
+    if let Some(api) = &docker.api {
+        return api.read_compose_projects().await.map_err(|err| {
+            ScrapeError::context(err, "Failed to read the compose projects").into()
+        });
+    }
+    let projects: Vec<ComposeProject> = serde_json::from_slice(
+        &exec_docker_global_cmd(docker, &["compose", "ls", "--all", "--format", "json"])
+            .await
+            .map_err(|err| ScrapeError::context(err, "Failed to execute `docker compose ls`"))?,
     )
     .map_err(|err| {
-        format!(
-            "Failed to parse `docker compose ps` output for {}: {}",
-            config_path.as_ref().display(),
-            err
+        ScrapeError::new(
+            ErrorCode::DockerOutputInvalid,
+            format!("Failed to parse `docker compose ls` output: {}", err),
         )
     })?;
-    Ok(running_containers)
+    Ok(projects)
 }
 
-fn service_metric_to_string(
-    compose_name: &str,
+/// Add a sample of a service metric, `app_labels` being the labels shared by
+/// all metrics of the service's app (its compose name and grouping labels)
+fn add_service_metric(
+    samples: &mut Samples,
+    app_labels: &[(&str, &str)],
     service_name: &str,
     metric_name: &str,
     extra_labels: &[(&str, &str)],
-    value: u8,
-) -> String {
-    let mut labels = vec![
-        ("compose_name", compose_name),
-        ("service_name", service_name),
-    ];
+    value: impl SampleValue,
+) {
+    let mut labels = app_labels.to_vec();
+    labels.push(("service_name", service_name));
     labels.extend(extra_labels);
-    let labels_str = labels
-        .iter()
-        .map(|(key, value)| format!("{}=\"{}\"", key, value))
-        .collect::<Vec<_>>()
-        .join(",");
-    format!(
-        "compose_service_{}{{{}}} {}",
-        metric_name, labels_str, value
-    )
+    samples.add(&format!("compose_service_{}", metric_name), &labels, value);
 }
 
-fn service_state_metric_to_strings(
-    compose_name: &str,
+fn add_service_state_metrics(
+    samples: &mut Samples,
+    app_labels: &[(&str, &str)],
     service_name: &str,
     metric_name: &str,
     possible_values: &[&str],
     value: &str,
-) -> Vec<String> {
-    let mut metrics = vec![];
+) {
     for possible_value in possible_values {
-        let value = if value == *possible_value { 1 } else { 0 };
-        metrics.push(service_metric_to_string(
-            compose_name,
+        add_service_metric(
+            samples,
+            app_labels,
             service_name,
             metric_name,
             &[("state", possible_value)],
-            value,
-        ));
+            (value == *possible_value) as u8,
+        );
     }
-    metrics
 }
 
 static STATE_NOT_UP: &str = "not_up";
@@ -243,127 +1823,2130 @@ static POSSIBLE_STATES_STATE: [&str; 8] = [
 static POSSIBLE_STATES_HEALTH: [&str; 5] =
     [STATE_NOT_UP, "no_check", "starting", "healthy", "unhealthy"];
 
+/// What the docker engine reports about an app, besides its containers
+struct EngineState<'a> {
+    /// Sizes of all volumes on the engine, by name
+    volume_sizes: Option<&'a HashMap<String, u64>>,
+    /// Sizes of the images the app's services run, by reference
+    images: Option<HashMap<String, ImageDetails>>,
+    /// Digests the images the app's services run have in their registries, by
+    /// reference
+    registry_digests: HashMap<String, String>,
+    engine_platform: Option<Platform>,
+    /// Whether the app's containers that reserve GPUs got access to them, by
+    /// container name
+    gpus_attached: HashMap<String, bool>,
+    /// By container name
+    last_log_timestamps: HashMap<String, f64>,
+    /// Resource usage of the app's running containers, by container name
+    container_stats: HashMap<String, ContainerStats>,
+    /// Hashes of the current configs of the app's services, by service name
+    service_config_hashes: HashMap<String, String>,
+    /// Names of all networks on the engine, if the app uses external ones
+    network_names: Option<HashSet<String>>,
+    /// Disk usage of the host paths bind-mounted into the app's services, by
+    /// path
+    bind_mount_usages: HashMap<String, BindMountUsage>,
+}
+
 /// Convert the given compose config and list of running containers to a
-/// multiline string of metrics
+/// multiline string of metrics, rendered through a registry, and the state of
+/// the app's services, sorted by name. Only the background collection of all
+/// apps observes state transitions for debouncing, so how often apps are
+/// scraped doesn't matter; other collections report the states it debounced.
 fn config_and_containers_to_metrics(
+    config_path: &Path,
     compose_config: &ComposeConfig,
     running_containers: Vec<Container>,
-) -> String {
-    let service_names = compose_config.services.keys();
-    let metrics = service_names.flat_map(|service_name| {
-        let container_name = &compose_config.services[service_name].container_name;
-        let container = running_containers
+    engine_state: &EngineState,
+    exporter: &Exporter,
+    background: bool,
+) -> (Samples, Vec<ServiceState>) {
+    let config = exporter.config();
+    let grouping_labels = config
+        .app_labels
+        .of(Some(&compose_config.name), config_path);
+    let mut app_labels = vec![("compose_name", compose_config.name.as_str())];
+    app_labels.extend(
+        grouping_labels
             .iter()
-            .find(|container| container.name == *container_name);
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    );
+    let app_labels = &app_labels;
+    let mut samples = Samples::default();
+    let mut service_states = vec![];
+    for (service_name, service) in &compose_config.services {
+        let compose_name = &compose_config.name;
+        let service_containers =
+            replicas::service_containers(&running_containers, compose_name, service_name, service);
+        // The per-service metrics are of the first container, the only one
+        // unless the service is scaled
+        let container = service_containers.first().map(|(_, container)| *container);
         let state = container.map_or(STATE_NOT_UP, |c| &c.state);
-        let health = match container.map(|c| c.health.as_str()) {
+        let health = match container.map(|c| c.health.as_deref().unwrap_or_default()) {
             None => STATE_NOT_UP,
             Some("") => STATE_HEALTH_NO_CHECK,
             Some(health) => health,
         };
-        let compose_name = &compose_config.name;
-        let mut metrics = service_state_metric_to_strings(
+        let available =
+            state == "running" && (health == "healthy" || health == STATE_HEALTH_NO_CHECK);
+        if let Some(uptime_history) = &exporter.uptime_history {
+            uptime_history.observe(compose_name, service_name, available);
+        }
+        let available_ratios = if config.availability_windows.is_empty() {
+            vec![]
+        } else {
+            exporter.availability.observe(
+                compose_name,
+                service_name,
+                available,
+                &config.availability_windows,
+            )
+        };
+        let in_maintenance =
+            exporter
+                .maintenance
+                .is_in_maintenance(compose_name, service_name, &service.labels);
+        let (state, health) = exporter.maintenance.reported_state(
             compose_name,
             service_name,
-            "health",
-            &POSSIBLE_STATES_HEALTH,
+            in_maintenance,
+            state,
             health,
         );
-        metrics.append(&mut service_state_metric_to_strings(
+        let reported = exporter.grace_periods.reported_state(
             compose_name,
             service_name,
-            "state",
-            &POSSIBLE_STATES_STATE,
-            state,
-        ));
-        metrics
-    });
-    return metrics.collect::<Vec<String>>().join("\n");
-}
-
-/// Get all metrics as for given docker compose config path as a multi-line
-/// string
-fn get_metrics_for_config_path(
-    config_path: impl AsRef<std::path::Path> + Debug,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let config = read_compose_config(config_path.as_ref())?;
-    let running_containers = read_running_compose_containers(config_path.as_ref())?;
-    Ok(config_and_containers_to_metrics(
-        &config,
-        running_containers,
-    ))
-}
-
-/// Get all metrics as for given docker compose config paths as a multi-line
-/// string
-fn get_metrics_for_configs_paths(
-    config_paths: Vec<impl AsRef<std::path::Path> + Debug>,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let config_metrics_comment = indoc! {"
-        # HELP compose_service_up Whether the docker compose services's status is 'Up' (as opposed to e.g. 'Restarting')
-        # TYPE compose_service_up gauge
-        # HELP compose_service_health Whether the docker compose services's health is 'healthy'
-        # TYPE compose_service_health gauge
-    "};
-    let nbro_config_paths = config_paths.len();
-    let config_metrics = config_paths
-        .iter()
-        .map(|config_path| get_metrics_for_config_path(config_path))
-        .collect::<Result<Vec<String>, Box<dyn std::error::Error>>>()
-        .map_err(|err| {
-            format!(
-                "Failed to get metrics for config paths {:?}: {}",
-                config_paths, err
+            &state,
+            &health,
+            config.grace_period,
+        );
+        let reported_state = reported.state.map(|(state, health)| {
+            let debounce = &config.debounce;
+            let debounced = |metric_name, value: &str, required| {
+                if background {
+                    exporter.debouncer.observe(
+                        metric_name,
+                        compose_name,
+                        service_name,
+                        value,
+                        required,
+                    )
+                } else {
+                    exporter
+                        .debouncer
+                        .exported(metric_name, compose_name, service_name)
+                        .unwrap_or_else(|| value.to_string())
+                }
+            };
+            (
+                debounced("state", &state, debounce.state),
+                debounced("health", &health, debounce.health),
             )
-        })?
-        .join("\n");
-    let nbro_configs_metric = format!(
-        indoc! {"
-            # HELP compose_apps_nbro_configs Number of docker-compose apps
-            # TYPE compose_apps_nbro_configs gauge
-            compose_apps_nbro_configs {}
-        "},
-        nbro_config_paths
-    );
-    Ok(format!(
-        "{}{}\n{}",
-        config_metrics_comment, config_metrics, nbro_configs_metric
-    ))
-}
-
-/// Convert a list of globs to a list of config paths and use them to get metrics
-/// for each app as a multi-line string
-fn get_metrics_for_config_globs(
-    config_globs: &[String],
-) -> Result<String, Box<dyn std::error::Error>> {
-    let config_paths = config_paths_from_globs(config_globs)?;
-    get_metrics_for_configs_paths(config_paths)
+        });
+        if let Some((state, health)) = &reported_state {
+            add_service_state_metrics(
+                &mut samples,
+                app_labels,
+                service_name,
+                "health",
+                &POSSIBLE_STATES_HEALTH,
+                health,
+            );
+            add_service_state_metrics(
+                &mut samples,
+                app_labels,
+                service_name,
+                "state",
+                &POSSIBLE_STATES_STATE,
+                state,
+            );
+        }
+        let mut service_state = ServiceState {
+            service_name: service_name.clone(),
+            state: reported_state.as_ref().map(|(state, _)| state.clone()),
+            health: reported_state.as_ref().map(|(_, health)| health.clone()),
+            image: service.image.clone(),
+            containers_desired: replicas::desired_containers(service) as u64,
+            containers_running: service_containers
+                .iter()
+                .filter(|(_, container)| container.state == "running")
+                .count() as u64,
+            in_maintenance,
+            ..Default::default()
+        };
+        for (window, ratio) in config.availability_windows.iter().zip(available_ratios) {
+            let window_suffix = availability::window_suffix(*window);
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                &format!("available_ratio_{}", window_suffix),
+                &[],
+                ratio,
+            );
+            service_state.available_ratios.insert(window_suffix, ratio);
+        }
+        let uptimes = exporter
+            .uptime_history
+            .as_ref()
+            .filter(|_| config.uptime_history_metrics)
+            .and_then(|uptime_history| uptime_history.uptime(compose_name, service_name));
+        for ((window_name, _), uptime) in uptime_history::UPTIME_WINDOWS
+            .iter()
+            .zip(uptimes.unwrap_or_default())
+        {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                &format!("uptime_ratio_{}", window_name),
+                &[],
+                uptime,
+            );
+            service_state
+                .uptime_ratios
+                .insert(window_name.to_string(), uptime);
+        }
+        add_service_metric(
+            &mut samples,
+            app_labels,
+            service_name,
+            "in_grace_period",
+            &[],
+            reported.in_grace_period as u8,
+        );
+        add_service_metric(
+            &mut samples,
+            app_labels,
+            service_name,
+            "maintenance",
+            &[],
+            in_maintenance as u8,
+        );
+        let in_downtime =
+            downtime::is_in_downtime(&config.downtime_windows, compose_name, service_name);
+        add_service_metric(
+            &mut samples,
+            app_labels,
+            service_name,
+            "in_downtime",
+            &[],
+            in_downtime as u8,
+        );
+        service_state.in_downtime = in_downtime;
+        service_states.push(service_state);
+        let expected_state = expected_state::expected_state(
+            &config.expected_states,
+            compose_name,
+            service_name,
+            &service.labels,
+        )
+        .unwrap_or_else(|err| {
+            eprintln!(
+                "Invalid {} label of service {} of {}: {}",
+                expected_state::EXPECTED_STATE_LABEL,
+                service_name,
+                compose_name,
+                err
+            );
+            Default::default()
+        });
+        if let Some((state, _)) = &reported_state {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "state_matches_expected",
+                &[("expected", expected_state.as_str())],
+                expected_state.matches(state) as u8,
+            );
+        }
+        if let Some((state, health)) = reported_state
+            .as_ref()
+            .filter(|_| config.zabbix_server.is_some())
+        {
+            exporter
+                .zabbix_items
+                .observe(compose_name, service_name, state, health);
+        }
+        if config.alertmanager_url.is_some() {
+            let problem = match &reported_state {
+                _ if in_maintenance || in_downtime => None,
+                Some((state, _)) if !expected_state.matches(state) => {
+                    Some(alertmanager::Problem::Down)
+                }
+                Some((_, health)) if health == "unhealthy" => {
+                    Some(alertmanager::Problem::Unhealthy)
+                }
+                _ => None,
+            };
+            exporter.alerts.observe(compose_name, service_name, problem);
+        }
+        for (dependency_name, condition, satisfied) in
+            dependencies::dependencies(compose_config, service_name, &running_containers)
+        {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "dependency_satisfied",
+                &[("dependency", dependency_name), ("condition", condition)],
+                satisfied as u8,
+            );
+        }
+        let image = service
+            .image
+            .as_ref()
+            .and_then(|image| Some((image, engine_state.images.as_ref()?.get(image)?)));
+        if let Some((image, details)) = image.filter(|_| config.image_size) {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "image_size_bytes",
+                &[("image", image)],
+                details.size,
+            );
+        }
+        let registry_digest = image.and_then(|(image, _)| engine_state.registry_digests.get(image));
+        if let Some(((image, details), registry_digest)) = image.zip(registry_digest) {
+            // Images that weren't pulled (e.g. built locally) can't be compared
+            if !details.repo_digests.is_empty() {
+                add_service_metric(
+                    &mut samples,
+                    app_labels,
+                    service_name,
+                    "image_update_available",
+                    &[("image", image)],
+                    image_updates::is_update_available(&details.repo_digests, registry_digest)
+                        as u8,
+                );
+            }
+        }
+        if let Some((_, details)) = image.filter(|_| config.image_platform) {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "platform_info",
+                &[("platform", &details.platform.to_string())],
+                1,
+            );
+            if let Some(engine_platform) = &engine_state.engine_platform {
+                add_service_metric(
+                    &mut samples,
+                    app_labels,
+                    service_name,
+                    "emulated",
+                    &[],
+                    details.platform.is_emulated_on(engine_platform) as u8,
+                );
+            }
+        }
+        let reserved_gpus = gpus::reserved_gpus(service);
+        for (driver, count) in &reserved_gpus {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "gpus_reserved",
+                &[("driver", driver)],
+                count,
+            );
+        }
+        let gpus_attached = container
+            .filter(|_| !reserved_gpus.is_empty())
+            .and_then(|container| engine_state.gpus_attached.get(&container.name));
+        if let Some(gpus_attached) = gpus_attached {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "gpus_attached",
+                &[],
+                *gpus_attached as u8,
+            );
+        }
+        service_info::add_metric(
+            &mut samples,
+            app_labels,
+            service_name,
+            service,
+            container,
+            &config.service_info_environment,
+        );
+        security::add_metrics(&mut samples, app_labels, service_name, service);
+        limits::add_metrics(&mut samples, app_labels, service_name, service);
+        replicas::add_metrics(
+            &mut samples,
+            app_labels,
+            service_name,
+            service,
+            &service_containers,
+        );
+        if let Some(config_hash) = engine_state.service_config_hashes.get(service_name) {
+            let container_config_hashes: Vec<&str> = service_containers
+                .iter()
+                .filter_map(|(_, container)| container.config_hash.as_deref())
+                .collect();
+            if !container_config_hashes.is_empty() {
+                add_service_metric(
+                    &mut samples,
+                    app_labels,
+                    service_name,
+                    "config_drift",
+                    &[],
+                    container_config_hashes
+                        .iter()
+                        .any(|container_config_hash| container_config_hash != config_hash)
+                        as u8,
+                );
+            }
+        }
+        if let Some(restart_count) = container.and_then(|container| container.restart_count) {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "restart_count",
+                &[],
+                restart_count,
+            );
+        }
+        if let Some(oom_killed) = container.and_then(|container| container.oom_killed) {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "oomkilled",
+                &[],
+                oom_killed as u8,
+            );
+        }
+        if let Some(created_at) = container.and_then(|container| container.created_at) {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "created_time_seconds",
+                &[],
+                created_at,
+            );
+        }
+        if let Some(started_at) = container.and_then(|container| container.started_at) {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "start_time_seconds",
+                &[],
+                started_at,
+            );
+        }
+        if let Some(exit_code) = container.and_then(|container| container.exit_code) {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "exit_code",
+                &[],
+                exit_code,
+            );
+        }
+        let last_log_timestamp =
+            container.and_then(|container| engine_state.last_log_timestamps.get(&container.name));
+        if let Some(last_log_timestamp) = last_log_timestamp {
+            add_service_metric(
+                &mut samples,
+                app_labels,
+                service_name,
+                "last_log_timestamp_seconds",
+                &[],
+                last_log_timestamp,
+            );
+        }
+        let container_stats =
+            container.and_then(|container| engine_state.container_stats.get(&container.name));
+        if let Some(container_stats) = container_stats {
+            for (metric_name, value) in [
+                ("cpu_usage_ratio", container_stats.cpu_usage_ratio),
+                ("memory_usage_bytes", container_stats.memory_usage_bytes),
+                ("memory_limit_bytes", container_stats.memory_limit_bytes),
+                (
+                    "network_receive_bytes_total",
+                    container_stats.network_receive_bytes,
+                ),
+                (
+                    "network_transmit_bytes_total",
+                    container_stats.network_transmit_bytes,
+                ),
+                ("block_read_bytes_total", container_stats.block_read_bytes),
+                ("block_write_bytes_total", container_stats.block_write_bytes),
+            ] {
+                add_service_metric(
+                    &mut samples,
+                    app_labels,
+                    service_name,
+                    metric_name,
+                    &[],
+                    value,
+                );
+            }
+        }
+        add_bind_mount_metrics(
+            &mut samples,
+            app_labels,
+            service_name,
+            service,
+            &engine_state.bind_mount_usages,
+        );
+    }
+    secrets::add_metrics(&mut samples, app_labels, compose_config);
+    samples.add(
+        "compose_app_config_warnings",
+        app_labels,
+        compose_config.warnings.len(),
+    );
+    samples.add(
+        "compose_app_orphan_containers",
+        app_labels,
+        replicas::orphan_containers(&running_containers, compose_config),
+    );
+    if let Some(network_names) = &engine_state.network_names {
+        for network_name in external_networks::external_network_names(compose_config) {
+            let mut labels = app_labels.clone();
+            labels.push(("network", network_name));
+            samples.add(
+                "compose_app_external_network_missing",
+                &labels,
+                !network_names.contains(network_name) as u8,
+            );
+        }
+    }
+    if let Some(volume_sizes) = engine_state.volume_sizes {
+        for (_, volume_name) in volume_usage::declared_volume_names(compose_config) {
+            if let Some(size) = volume_sizes.get(&volume_name) {
+                let mut labels = app_labels.clone();
+                labels.push(("volume", &volume_name));
+                samples.add("compose_app_volume_bytes", &labels, size);
+            }
+        }
+    }
+    service_states.sort_by(|a, b| a.service_name.cmp(&b.service_name));
+    (samples, service_states)
+}
+
+/// Get the host paths bind-mounted into the given service
+fn bind_mount_sources(service: &compose_config::ComposeService) -> impl Iterator<Item = &str> {
+    service
+        .volumes
+        .iter()
+        .filter(|volume| volume.kind == "bind")
+        .filter_map(|volume| volume.source.as_deref())
+}
+
+/// Measure the disk usage of the host paths bind-mounted into the services of
+/// the given app, by path, on a blocking thread as it walks their directories
+async fn measure_bind_mounts(
+    compose_config: &ComposeConfig,
+    config: &ParsedConfig,
+) -> HashMap<String, BindMountUsage> {
+    let sources: HashSet<String> = compose_config
+        .services
+        .values()
+        .flat_map(bind_mount_sources)
+        .map(String::from)
+        .collect();
+    let max_depth = config.bind_mount_usage_max_depth;
+    let timeout = config.bind_mount_usage_timeout;
+    tokio::task::spawn_blocking(move || {
+        sources
+            .into_iter()
+            .map(|source| {
+                let usage = bind_mounts::measure(Path::new(&source), max_depth, timeout);
+                (source, usage)
+            })
+            .collect()
+    })
+    .await
+    .expect("measuring bind mounts shouldn't panic")
+}
+
+/// Add the disk usage of the host paths bind-mounted into the given service to
+/// the samples, if measured
+fn add_bind_mount_metrics(
+    samples: &mut Samples,
+    app_labels: &[(&str, &str)],
+    service_name: &str,
+    service: &compose_config::ComposeService,
+    bind_mount_usages: &HashMap<String, BindMountUsage>,
+) {
+    for source in bind_mount_sources(service) {
+        let Some(usage) = bind_mount_usages.get(source) else {
+            continue;
+        };
+        add_service_metric(
+            samples,
+            app_labels,
+            service_name,
+            "bind_mount_bytes",
+            &[("path", source)],
+            usage.bytes,
+        );
+        add_service_metric(
+            samples,
+            app_labels,
+            service_name,
+            "bind_mount_usage_truncated",
+            &[("path", source)],
+            usage.truncated as u8,
+        );
+    }
+}
+
+/// Log the config problems (errors or warnings) of the app with the given
+/// compose file, unless they are the same as the last time, so they don't flood
+/// the logs on every scrape
+fn log_changed_config_problems(exporter: &Exporter, config_path: &Path, problems: &[String]) {
+    let mut logged_config_problems = exporter
+        .logged_config_problems
+        .lock()
+        .expect("logged config problems lock shouldn't be poisoned");
+    if logged_config_problems.get(config_path).map(Vec::as_slice) == Some(problems) {
+        return;
+    }
+    for problem in problems {
+        eprintln!("{}", problem);
+    }
+    logged_config_problems.insert(config_path.to_path_buf(), problems.to_vec());
+}
+
+/// Metrics of an app
+struct AppMetrics {
+    metrics: Samples,
+    /// Compose name of the app and container names of its services, if its
+    /// config resolved
+    names: Option<(String, Vec<String>)>,
+    state: AppState,
+}
+
+/// Log the given name conflicts between apps, unless they are the same as the
+/// last time
+fn log_changed_name_conflicts(exporter: &Exporter, warnings: &[String]) {
+    let mut logged_name_conflicts = exporter
+        .logged_name_conflicts
+        .lock()
+        .expect("logged name conflicts lock shouldn't be poisoned");
+    if logged_name_conflicts.as_slice() == warnings {
+        return;
+    }
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    *logged_name_conflicts = warnings.to_vec();
+}
+
+/// Get all metrics as for given docker compose config path
+async fn get_metrics_for_config_path(
+    config_path: impl AsRef<std::path::Path> + Debug,
+    volume_sizes: Option<&HashMap<String, u64>>,
+    exporter: &Exporter,
+    background: bool,
+) -> Result<AppMetrics, Box<dyn std::error::Error>> {
+    let docker = &exporter.docker;
+    let config = exporter.config();
+    let app = config.apps.get(config_path.as_ref());
+    let compose_config = match read_compose_config(config_path.as_ref(), app, docker).await {
+        Ok(compose_config) => compose_config,
+        Err(err) => {
+            // A broken compose file shouldn't fail the whole scrape, only mark
+            // the app's config as invalid
+            let err = ScrapeError::from_boxed(err);
+            let error_class = match err.code {
+                ErrorCode::DockerCommandFailed => {
+                    config_error_class(err.stderr.as_deref().unwrap_or_default())
+                }
+                ErrorCode::DockerOutputInvalid => "unparsable",
+                _ => return Err(err.into()),
+            };
+            log_changed_config_problems(
+                exporter,
+                config_path.as_ref(),
+                &[format!(
+                    "Invalid config {}: {}",
+                    config_path.as_ref().display(),
+                    err.to_string().trim_end()
+                )],
+            );
+            let mut metrics = Samples::default();
+            add_config_valid_metric(
+                &mut metrics,
+                config_path.as_ref(),
+                &config.app_labels.of(None, config_path.as_ref()),
+                error_class,
+            );
+            return Ok(AppMetrics {
+                metrics,
+                names: None,
+                state: AppState {
+                    compose_name: app_compose_name(config_path.as_ref(), exporter),
+                    config_path: config_path.as_ref().display().to_string(),
+                    config_error_class: Some(error_class.to_string()),
+                    error: None,
+                    services: vec![],
+                },
+            });
+        }
+    };
+    exporter
+        .compose_names
+        .lock()
+        .expect("compose names lock shouldn't be poisoned")
+        .insert(
+            config_path.as_ref().to_path_buf(),
+            compose_config.name.clone(),
+        );
+    log_changed_config_problems(
+        exporter,
+        config_path.as_ref(),
+        &compose_config
+            .warnings
+            .iter()
+            .map(|warning| {
+                format!(
+                    "Warning in config {}: {}",
+                    config_path.as_ref().display(),
+                    warning
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    let mut running_containers =
+        read_running_compose_containers(config_path.as_ref(), app, &compose_config.name, docker)
+            .await?;
+    for container in &mut running_containers {
+        let healthcheck_disabled = compose_config
+            .services
+            .iter()
+            .any(|(service_name, service)| {
+                replicas::is_of_service(container, &compose_config.name, service_name, service)
+                    && service.healthcheck_disabled()
+            });
+        if container.health.is_none() && healthcheck_disabled {
+            // No need to inspect the container to know it has no health
+            container.health = Some(String::new());
+        }
+    }
+    fill_missing_container_details(
+        docker,
+        config_path.as_ref(),
+        &mut running_containers,
+        &exporter.container_details,
+    )
+    .await?;
+    let image_names: Vec<&str> = compose_config
+        .services
+        .values()
+        .filter_map(|service| service.image.as_deref())
+        .collect();
+    let images = if config.image_size || config.image_platform || config.image_update_check {
+        Some(image_size::read_images(docker, config_path.as_ref(), &image_names).await?)
+    } else {
+        None
+    };
+    let registry_digests = if config.image_update_check {
+        exporter
+            .registry_digests
+            .get(
+                docker,
+                config_path.as_ref(),
+                &image_names,
+                config.image_update_check_interval,
+            )
+            .await
+    } else {
+        HashMap::new()
+    };
+    let engine_platform = if config.image_platform {
+        Some(exporter.engine_platform.get(docker).await?)
+    } else {
+        None
+    };
+    let gpu_container_names: Vec<&str> = running_containers
+        .iter()
+        .filter(|container| {
+            compose_config
+                .services
+                .iter()
+                .any(|(service_name, service)| {
+                    replicas::is_of_service(container, &compose_config.name, service_name, service)
+                        && !gpus::reserved_gpus(service).is_empty()
+                })
+        })
+        .map(|container| container.name.as_str())
+        .collect();
+    let gpus_attached =
+        gpus::read_gpus_attached(docker, config_path.as_ref(), &gpu_container_names).await?;
+    let last_log_timestamps = if config.last_log_timestamp {
+        let container_names: Vec<&str> = running_containers
+            .iter()
+            .map(|container| container.name.as_str())
+            .collect();
+        last_log::read_last_log_timestamps(docker, config_path.as_ref(), &container_names).await?
+    } else {
+        HashMap::new()
+    };
+    let container_stats = if config.enable_stats {
+        let container_names: Vec<&str> = running_containers
+            .iter()
+            .filter(|container| container.state == "running")
+            .map(|container| container.name.as_str())
+            .collect();
+        stats::read_container_stats(docker, config_path.as_ref(), &container_names).await?
+    } else {
+        HashMap::new()
+    };
+    let service_config_hashes = if config.config_drift {
+        config_drift::read_service_config_hashes(docker, config_path.as_ref(), app).await?
+    } else {
+        HashMap::new()
+    };
+    let network_names = if external_networks::external_network_names(&compose_config).is_empty() {
+        None
+    } else {
+        Some(external_networks::read_network_names(docker, config_path.as_ref()).await?)
+    };
+    let bind_mount_usages = if config.bind_mount_usage {
+        measure_bind_mounts(&compose_config, &config).await
+    } else {
+        HashMap::new()
+    };
+    let engine_state = EngineState {
+        volume_sizes,
+        images,
+        registry_digests,
+        engine_platform,
+        gpus_attached,
+        last_log_timestamps,
+        container_stats,
+        service_config_hashes,
+        network_names,
+        bind_mount_usages,
+    };
+    let (mut metrics, service_states) = config_and_containers_to_metrics(
+        config_path.as_ref(),
+        &compose_config,
+        running_containers,
+        &engine_state,
+        exporter,
+        background,
+    );
+    add_config_valid_metric(
+        &mut metrics,
+        config_path.as_ref(),
+        &config
+            .app_labels
+            .of(Some(&compose_config.name), config_path.as_ref()),
+        "",
+    );
+    let mut container_names: Vec<String> = compose_config
+        .services
+        .values()
+        .filter_map(|service| service.container_name.clone())
+        .collect();
+    container_names.sort();
+    Ok(AppMetrics {
+        metrics,
+        state: AppState {
+            compose_name: compose_config.name.clone(),
+            config_path: config_path.as_ref().display().to_string(),
+            config_error_class: None,
+            error: None,
+            services: service_states,
+        },
+        names: Some((compose_config.name, container_names)),
+    })
+}
+
+/// Classify why `docker compose config` failed, given its stderr
+fn config_error_class(stderr: &str) -> &'static str {
+    let stderr = stderr.to_lowercase();
+    if stderr.contains("yaml:") {
+        "yaml_error"
+    } else if stderr.contains("required variable") || stderr.contains("missing a value") {
+        "missing_env"
+    } else if stderr.contains("unsupported") && stderr.contains("version") {
+        "unsupported_version"
+    } else if stderr.contains("additional property") || stderr.contains("validating") {
+        "invalid_schema"
+    } else if stderr.contains("no such file") || stderr.contains("not found") {
+        "file_not_found"
+    } else {
+        "unknown"
+    }
+}
+
+/// Add the `compose_app_config_valid` metric of the app with the given compose
+/// file, with an empty error class if it's valid
+fn add_config_valid_metric(
+    samples: &mut Samples,
+    config_path: &Path,
+    grouping_labels: &[(String, String)],
+    error_class: &str,
+) {
+    let config_path_str = config_path.display().to_string();
+    let mut labels = vec![("config_path", config_path_str.as_str())];
+    labels.extend(
+        grouping_labels
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    );
+    labels.push(("error_class", error_class));
+    samples.add(
+        "compose_app_config_valid",
+        &labels,
+        error_class.is_empty() as u8,
+    );
+}
+
+/// Compose name of the app with the given compose file, as of the last time its
+/// config resolved
+fn app_compose_name(config_path: &Path, exporter: &Exporter) -> String {
+    exporter
+        .compose_names
+        .lock()
+        .expect("compose names lock shouldn't be poisoned")
+        .get(config_path)
+        .cloned()
+        // The config didn't resolve yet, so guess
+        .unwrap_or_else(|| default_compose_project_name(config_path))
+}
+
+fn add_app_scrape_error_metric(
+    samples: &mut Samples,
+    config_path: &Path,
+    exporter: &Exporter,
+    error_code: Option<ErrorCode>,
+) {
+    let compose_name = app_compose_name(config_path, exporter);
+    let config_path_str = config_path.display().to_string();
+    let grouping_labels = exporter
+        .config()
+        .app_labels
+        .of(Some(&compose_name), config_path);
+    let mut labels = vec![
+        ("compose_name", compose_name.as_str()),
+        ("config_path", config_path_str.as_str()),
+    ];
+    labels.extend(
+        grouping_labels
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    );
+    labels.push((
+        "error_code",
+        error_code.as_ref().map_or("", ErrorCode::as_str),
+    ));
+    samples.add(
+        "compose_app_scrape_error",
+        &labels,
+        error_code.is_some() as u8,
+    );
+}
+
+/// Get the metrics of the app with the given compose file, by the deadline if
+/// any. If collecting them fails, only the app's scrape error metric and its
+/// error are returned, so one broken app doesn't hide all others.
+async fn get_isolated_metrics_for_config_path(
+    config_path: &Path,
+    volume_sizes: Option<&HashMap<String, u64>>,
+    exporter: &Exporter,
+    deadline: Option<Instant>,
+    background: bool,
+) -> AppMetrics {
+    let maybe_app_metrics = cancellation::before_deadline(
+        deadline,
+        get_metrics_for_config_path(config_path, volume_sizes, exporter, background),
+    )
+    .await;
+    let err = match maybe_app_metrics {
+        Ok(mut app_metrics) => {
+            exporter
+                .logged_app_errors
+                .lock()
+                .expect("logged app errors lock shouldn't be poisoned")
+                .remove(config_path);
+            add_app_scrape_error_metric(&mut app_metrics.metrics, config_path, exporter, None);
+            return app_metrics;
+        }
+        Err(err) => ScrapeError::context(
+            err,
+            format!("Failed to get metrics for {}", config_path.display()),
+        )
+        .with_config_path(config_path),
+    };
+    let message = format!("[{}] {}", err.code, err.to_string().trim_end());
+    let mut logged_app_errors = exporter
+        .logged_app_errors
+        .lock()
+        .expect("logged app errors lock shouldn't be poisoned");
+    if logged_app_errors.get(config_path) != Some(&message) {
+        eprintln!("{}", message);
+        logged_app_errors.insert(config_path.to_path_buf(), message);
+    }
+    drop(logged_app_errors);
+    let mut metrics = Samples::default();
+    add_app_scrape_error_metric(&mut metrics, config_path, exporter, Some(err.code));
+    AppMetrics {
+        metrics,
+        names: None,
+        state: AppState {
+            compose_name: app_compose_name(config_path, exporter),
+            config_path: config_path.display().to_string(),
+            config_error_class: None,
+            error: Some(AppError::from(&err)),
+            services: vec![],
+        },
+    }
+}
+
+/// Get all metrics as for given docker compose config paths, and the state of
+/// the apps
+async fn get_metrics_for_configs_paths(
+    config_paths: Vec<impl AsRef<std::path::Path> + Debug + Sync>,
+    exporter: &Exporter,
+    deadline: Option<Instant>,
+    background: bool,
+) -> Result<Collection, Box<dyn std::error::Error>> {
+    let nbro_config_paths = config_paths.len();
+    // Failing to read the volume sizes shouldn't fail the metrics of the apps
+    let mut metrics = Samples::default();
+    let volume_sizes = if exporter.config().volume_usage {
+        match cancellation::before_deadline(
+            deadline,
+            volume_usage::read_volume_sizes(&exporter.docker),
+        )
+        .await
+        {
+            Ok(volume_sizes) => {
+                add_volume_usage_scrape_error_metric(&mut metrics, None);
+                Some(volume_sizes)
+            }
+            Err(err) => {
+                let err = ScrapeError::from_boxed(err);
+                eprintln!("Failed to read the volume sizes: [{}] {}", err.code, err);
+                add_volume_usage_scrape_error_metric(&mut metrics, Some(err.code));
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let app_metrics = concurrency::map(
+        &config_paths,
+        exporter.config().max_concurrent_apps,
+        |config_path| {
+            get_isolated_metrics_for_config_path(
+                config_path.as_ref(),
+                volume_sizes.as_ref(),
+                exporter,
+                deadline,
+                background,
+            )
+        },
+    )
+    .await;
+    let app_names: Vec<_> = config_paths
+        .iter()
+        .zip(&app_metrics)
+        .filter_map(|(config_path, app_metrics)| {
+            let (compose_name, container_names) = app_metrics.names.as_ref()?;
+            Some(name_conflicts::AppNames {
+                config_path: config_path.as_ref(),
+                compose_name,
+                container_names,
+            })
+        })
+        .collect();
+    let name_conflicts = name_conflicts::check(&app_names);
+    log_changed_name_conflicts(exporter, &name_conflicts.warnings);
+    metrics.add("compose_apps_nbro_configs", &[], nbro_config_paths);
+    metrics.extend(name_conflicts.metrics);
+    let mut apps = vec![];
+    for app_metrics in app_metrics {
+        metrics.extend(app_metrics.metrics);
+        apps.push(app_metrics.state);
+    }
+    apps.sort_by(|a, b| (&a.compose_name, &a.config_path).cmp(&(&b.compose_name, &b.config_path)));
+    Ok(Collection { metrics, apps })
+}
+
+/// Add the metric of whether reading the volume sizes failed, with the code of
+/// the error if it did
+fn add_volume_usage_scrape_error_metric(samples: &mut Samples, error_code: Option<ErrorCode>) {
+    samples.add(
+        "compose_apps_volume_usage_scrape_error",
+        &[(
+            "error_code",
+            error_code.as_ref().map_or("", ErrorCode::as_str),
+        )],
+        error_code.is_some() as u8,
+    );
+}
+
+/// Normalize a path so that different spellings of the same file compare equal
+/// (relative vs absolute, symlinks, and on Windows: casing and `\\?\` prefixes)
+fn comparable_path(path: &Path) -> std::path::PathBuf {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if cfg!(target_os = "windows") {
+        let path = path.to_string_lossy();
+        std::path::PathBuf::from(path.trim_start_matches(r"\\?\").to_lowercase())
+    } else {
+        path
+    }
+}
+
+/// Get the config paths of the running compose projects known to the docker
+/// engine that aren't among the given (discovered) config paths, by the first
+/// config file each project was started from, if it still exists
+async fn running_projects_config_paths(
+    docker: &DockerCli,
+    config_paths: &[std::path::PathBuf],
+) -> Result<Vec<std::path::PathBuf>, Box<dyn std::error::Error>> {
+    let mut known_config_paths: HashSet<_> = config_paths
+        .iter()
+        .map(|config_path| comparable_path(config_path))
+        .collect();
+    let mut running_config_paths = vec![];
+    for project in read_engine_compose_projects(docker).await? {
+        if !project.is_running()
+            || project
+                .config_file_paths()
+                .any(|path| known_config_paths.contains(&comparable_path(path)))
+        {
+            continue;
+        }
+        if let Some(path) = project
+            .config_file_paths()
+            .next()
+            .filter(|path| path.is_file())
+        {
+            known_config_paths.insert(comparable_path(path));
+            running_config_paths.push(path.to_path_buf());
+        }
+    }
+    Ok(running_config_paths)
+}
+
+/// Add metrics for the compose projects known to the docker engine whose
+/// config files don't match any of the given (discovered) config paths, i.e.
+/// stacks that were left running after their compose file was moved, deleted or
+/// excluded from the configured globs
+async fn add_zombie_projects_metrics(
+    samples: &mut Samples,
+    docker: &DockerCli,
+    config_paths: &[impl AsRef<std::path::Path>],
+    deadline: Option<Instant>,
+) {
+    let known_config_paths: HashSet<_> = config_paths
+        .iter()
+        .map(|config_path| comparable_path(config_path.as_ref()))
+        .collect();
+    // Failing to list the projects shouldn't fail the metrics of the apps
+    let projects =
+        match cancellation::before_deadline(deadline, read_engine_compose_projects(docker)).await {
+            Ok(projects) => projects,
+            Err(err) => {
+                let err = ScrapeError::from_boxed(err);
+                eprintln!("Failed to look for zombie projects: [{}] {}", err.code, err);
+                add_zombie_projects_scrape_error_metric(samples, Some(err.code));
+                return;
+            }
+        };
+    let zombie_projects: Vec<_> = projects
+        .into_iter()
+        .filter(|project| {
+            !project
+                .config_file_paths()
+                .any(|path| known_config_paths.contains(&comparable_path(path)))
+        })
+        .collect();
+    add_zombie_projects_scrape_error_metric(samples, None);
+    samples.add("compose_apps_zombie_projects", &[], zombie_projects.len());
+    for project in &zombie_projects {
+        samples.add(
+            "compose_apps_zombie_project_info",
+            &[
+                ("compose_name", &project.name),
+                ("config_files", &project.config_files),
+            ],
+            1,
+        );
+    }
+}
+
+/// Add the metric of whether looking for zombie projects failed, with the code
+/// of the error if it did
+fn add_zombie_projects_scrape_error_metric(samples: &mut Samples, error_code: Option<ErrorCode>) {
+    samples.add(
+        "compose_apps_zombie_projects_scrape_error",
+        &[(
+            "error_code",
+            error_code.as_ref().map_or("", ErrorCode::as_str),
+        )],
+        error_code.is_some() as u8,
+    );
+}
+
+/// Compose name of the app with the given compose file as of its last
+/// collection, or else the one it's declared with or would get by default,
+/// which misses names set in the compose file
+fn known_compose_name(exporter: &Exporter, config: &ParsedConfig, config_path: &Path) -> String {
+    let compose_name = exporter
+        .compose_names
+        .lock()
+        .expect("compose names lock shouldn't be poisoned")
+        .get(config_path)
+        .cloned();
+    compose_name.unwrap_or_else(|| {
+        config
+            .apps
+            .iter()
+            .find(|app| app.config_path() == config_path)
+            .and_then(|app| app.name.clone())
+            .unwrap_or_else(|| default_compose_project_name(config_path))
+    })
+}
+
+/// Discover the compose files of the apps of the given target: those matching
+/// its globs, plus for all apps (or a selection of them) the declared apps and
+/// running projects if enabled
+async fn discover_apps(
+    exporter: &Exporter,
+    target: &MetricsTarget,
+) -> Result<Discovery, Box<dyn std::error::Error>> {
+    let docker = &exporter.docker;
+    let config = exporter.config();
+    let group_globs = match target {
+        MetricsTarget::Group(group_globs) => Some(group_globs.clone()),
+        MetricsTarget::All | MetricsTarget::Selected(_) => None,
+    };
+    // Expanding the globs walks the filesystem
+    let discovering_config = config.clone();
+    let mut discovery = tokio::task::spawn_blocking(move || {
+        let config = discovering_config;
+        match group_globs {
+            Some(group_globs) => discover_config_paths(&group_globs, &[], &config.discovery),
+            None => discover_config_paths(
+                &config.compose_configs_glob,
+                &config.compose_configs_dir,
+                &config.discovery,
+            ),
+        }
+        .map_err(ScrapeError::from_boxed)
+    })
+    .await
+    .expect("discovery shouldn't panic")?;
+    let all_apps = matches!(target, MetricsTarget::All | MetricsTarget::Selected(_));
+    if all_apps {
+        let known_config_paths: HashSet<_> = discovery
+            .config_paths
+            .iter()
+            .map(|config_path| comparable_path(config_path))
+            .collect();
+        let app_config_paths: Vec<_> = config
+            .apps
+            .iter()
+            .map(|app| app.config_path().to_path_buf())
+            .filter(|config_path| !known_config_paths.contains(&comparable_path(config_path)))
+            .collect();
+        discovery.config_paths.extend(app_config_paths);
+    }
+    if config.discover_running && all_apps {
+        let running_config_paths =
+            running_projects_config_paths(docker, &discovery.config_paths).await?;
+        discovery.config_paths.extend(running_config_paths);
+    }
+    if let MetricsTarget::Selected(selection) = target {
+        discovery.config_paths.retain(|config_path| {
+            selection.selects(
+                config_path,
+                &known_compose_name(exporter, &config, config_path),
+            )
+        });
+    }
+    Ok(discovery)
+}
+
+/// Convert a list of globs to a list of config paths and use them to get metrics
+/// for each app as a multi-line string
+async fn get_metrics_for_config_globs(
+    exporter: &Exporter,
+    target: &MetricsTarget,
+    tenant: Option<&Tenant>,
+    deadline: Option<Instant>,
+    background: bool,
+) -> Result<Collection, Box<dyn std::error::Error>> {
+    let docker = &exporter.docker;
+    let config = exporter.config();
+    let discovery =
+        cancellation::before_deadline(deadline, discover_apps(exporter, target)).await?;
+    if let Some(tenant) = tenant {
+        // Other tenants' apps and the globs discovering them are none of the
+        // tenant's business
+        let config_paths = discovery
+            .config_paths
+            .into_iter()
+            .filter(|config_path| tenant.owns(config_path))
+            .collect();
+        return get_metrics_for_configs_paths(config_paths, exporter, deadline, background).await;
+    }
+    let mut metrics = Samples::default();
+    // The discovery of all apps is reported with the metrics of all apps
+    if !matches!(target, MetricsTarget::Selected(_)) {
+        add_discovery_metrics(&mut metrics, &discovery);
+    }
+    let config_paths = discovery.config_paths;
+    // Projects not in a group aren't zombies, they're just in another group
+    if let MetricsTarget::All = target {
+        add_zombie_projects_metrics(&mut metrics, docker, &config_paths, deadline).await;
+    }
+    let mut collection =
+        get_metrics_for_configs_paths(config_paths.clone(), exporter, deadline, background).await?;
+    // After collecting, so the compose names of new apps are known
+    if let MetricsTarget::All = target {
+        if !config.inventory.is_empty() {
+            let compose_names = exporter
+                .compose_names
+                .lock()
+                .expect("compose names lock shouldn't be poisoned")
+                .clone();
+            let apps: Vec<_> = config_paths
+                .iter()
+                .map(|config_path| inventory::DiscoveredApp {
+                    config_path,
+                    compose_name: compose_names.get(config_path).map(String::as_str),
+                })
+                .collect();
+            inventory::add_metrics(&mut metrics, &config.inventory, &apps);
+        }
+    }
+    collection.metrics.extend(metrics);
+    Ok(collection)
+}
+
+/// Apps to collect the metrics of
+enum MetricsTarget {
+    /// All apps matching the compose configs globs
+    All,
+    /// The apps of a group, by its globs
+    Group(Vec<String>),
+    /// The apps matching the compose configs globs that the query of the
+    /// scrape selects
+    Selected(Selection),
+}
+
+impl MetricsTarget {
+    /// Get the target served at the given path, if any: all apps at the metrics
+    /// path, and each group at a subpath of it named after the group
+    fn of_path(config: &ParsedConfig, path: &str) -> Option<Self> {
+        if path == config.metrics_path {
+            return Some(MetricsTarget::All);
+        }
+        let group_name = path
+            .strip_prefix(config.metrics_path.trim_end_matches('/'))?
+            .strip_prefix('/')?;
+        config
+            .groups
+            .get(group_name)
+            .map(|group_globs| MetricsTarget::Group(group_globs.clone()))
+    }
+}
+
+/// Collect the metrics of the given target, cancelling the collection if the
+/// returned future is dropped (i.e. when the client disconnects). `background`
+/// is whether this is the periodic collection of all apps (rather than one
+/// for a scrape), see [`config_and_containers_to_metrics`].
+async fn collect_metrics(
+    exporter: &Arc<Exporter>,
+    metrics_target: MetricsTarget,
+    tenant: Option<Tenant>,
+    deadline: Option<Instant>,
+    background: bool,
+) -> CollectionResult {
+    let scrape_started_at = Instant::now();
+    let cancel_on_drop = cancellation::CancelOnDrop::new();
+    let maybe_metrics = get_metrics_for_config_globs(
+        exporter,
+        &metrics_target,
+        tenant.as_ref(),
+        deadline,
+        background,
+    )
+    .await
+    .map_err(ScrapeError::from_boxed);
+    cancel_on_drop.disarm();
+    if maybe_metrics.is_ok() {
+        exporter
+            .collected_successfully
+            .store(true, Ordering::Relaxed);
+    }
+    *exporter
+        .last_scrape
+        .lock()
+        .expect("last scrape lock shouldn't be poisoned") = Some(ScrapeRecord {
+        at: SystemTime::now(),
+        duration: scrape_started_at.elapsed(),
+        error: maybe_metrics.as_ref().err().cloned(),
+    });
+    maybe_metrics
+}
+
+/// Name of the host the exporter runs on, if known
+fn host_name() -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        nix::unistd::gethostname()
+            .ok()
+            .map(|host_name| host_name.to_string_lossy().into_owned())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        std::env::var("COMPUTERNAME")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .ok()
+    }
+}
+
+/// Add the `target_info` metric, carrying the exporter's resource attributes
+/// per the OpenTelemetry conventions for Prometheus
+fn add_target_info_metric(samples: &mut Samples, exporter: &Exporter) {
+    let host_name = host_name();
+    let mut labels = vec![
+        ("service_name", env!("CARGO_PKG_NAME")),
+        ("service_version", env!("CARGO_PKG_VERSION")),
+    ];
+    if let Some(host_name) = &host_name {
+        labels.push(("host_name", host_name));
+    }
+    if let Some(docker_host) = &exporter.docker.host {
+        labels.push(("docker_host", docker_host));
+    }
+    samples.add("target_info", &labels, 1);
+}
+
+/// How long to serve the result of a collection of all apps (not restricted to
+/// a tenant) for: until the next background collection if apps are collected
+/// in the background, otherwise for the minimum scrape interval
+fn min_interval_of_all_apps(config: &ParsedConfig) -> Duration {
+    if config.collect_interval.is_zero() {
+        config.min_scrape_interval
+    } else {
+        Duration::MAX
+    }
+}
+
+/// Run an initial collection of all apps, so the first scrape after a restart
+/// doesn't race a cold start, then report ready
+async fn warm_up(exporter: &Arc<Exporter>) {
+    let metrics_path = exporter.config().metrics_path.clone();
+    // Scrapes arriving in the meantime are handled like any other overlapping
+    // scrape
+    let admission = exporter.in_flight_collections.admit(
+        &metrics_path,
+        OverlapPolicy::Wait,
+        Duration::ZERO,
+        Duration::ZERO,
+    );
+    if let Admission::Lead(leader) = admission {
+        // The first of the background collections
+        let maybe_metrics = collect_metrics(exporter, MetricsTarget::All, None, None, true).await;
+        match &maybe_metrics {
+            Ok(_) => println!("Warm-up collection finished"),
+            Err(e) => eprintln!("Warm-up collection failed: [{}] {}", e.code, e),
+        }
+        leader.finish(&maybe_metrics, min_interval_of_all_apps(&exporter.config()));
+    }
+    exporter.ready.store(true, Ordering::Relaxed);
+}
+
+/// Collect all apps outside of a scrape, sharing the result of a collection of
+/// all apps already in flight
+async fn collect_in_background(exporter: &Arc<Exporter>) -> Option<CollectionResult> {
+    let metrics_path = exporter.config().metrics_path.clone();
+    let admission = exporter.in_flight_collections.admit(
+        &metrics_path,
+        OverlapPolicy::Coalesce,
+        Duration::ZERO,
+        // Background collections keep the observations of services current
+        Duration::ZERO,
+    );
+    match admission {
+        Admission::Lead(leader) => {
+            let maybe_metrics =
+                collect_metrics(exporter, MetricsTarget::All, None, None, true).await;
+            leader.finish(&maybe_metrics, min_interval_of_all_apps(&exporter.config()));
+            Some(maybe_metrics)
+        }
+        Admission::Join(follower) | Admission::Wait(follower) => follower.result().await,
+        Admission::Reject { .. } | Admission::Cached { .. } => None,
+    }
+}
+
+/// Periodically collect all apps, if a collect interval is configured, for
+/// scrapes to be served from
+async fn collect_periodically(exporter: Arc<Exporter>) {
+    loop {
+        let collect_interval = exporter.config().collect_interval;
+        if collect_interval.is_zero() {
+            // Check again later, in case the configuration is reloaded
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        tokio::time::sleep(collect_interval).await;
+        let started_at = Instant::now();
+        let Some(maybe_metrics) = collect_in_background(&exporter).await else {
+            continue;
+        };
+        if let Err(e) = &maybe_metrics {
+            eprintln!("Background collection failed: [{}] {}", e.code, e);
+        }
+        *exporter
+            .last_background_collection
+            .lock()
+            .expect("last background collection lock shouldn't be poisoned") = Some(ScrapeRecord {
+            at: SystemTime::now(),
+            duration: started_at.elapsed(),
+            error: maybe_metrics.err(),
+        });
+    }
+}
+
+/// Periodically collect all apps and push the state and health of services to
+/// the configured Zabbix server, if any
+async fn push_to_zabbix(exporter: Arc<Exporter>) {
+    loop {
+        tokio::time::sleep(exporter.config().zabbix_interval).await;
+        let config = exporter.config();
+        let Some(zabbix_server) = &config.zabbix_server else {
+            continue;
+        };
+        match collect_in_background(&exporter).await {
+            Some(Ok(_)) => {}
+            Some(Err(e)) => {
+                eprintln!("Collection for Zabbix failed: [{}] {}", e.code, e);
+                continue;
+            }
+            None => continue,
+        }
+        let items = exporter.zabbix_items.items(
+            config.zabbix_interval * 2,
+            &config.zabbix_host,
+            &config.zabbix_key_template,
+        );
+        if items.is_empty() {
+            continue;
+        }
+        match zabbix::send(zabbix_server, items).await {
+            Ok(info) if info.contains("failed: 0") => {}
+            // e.g. items that don't exist in Zabbix
+            Ok(info) => eprintln!("Zabbix didn't process all items: {}", info),
+            Err(e) => eprintln!("Error pushing to Zabbix: {}", e),
+        }
+    }
+}
+
+/// Periodically collect all apps and send alerts for the services that are
+/// down or unhealthy to the configured Alertmanager, if any
+async fn send_alerts(exporter: Arc<Exporter>) {
+    loop {
+        tokio::time::sleep(exporter.config().alertmanager_interval).await;
+        let config = exporter.config();
+        let Some(alertmanager_url) = &config.alertmanager_url else {
+            continue;
+        };
+        match collect_in_background(&exporter).await {
+            Some(Ok(_)) => {}
+            Some(Err(e)) => {
+                eprintln!("Collection for alerts failed: [{}] {}", e.code, e);
+                continue;
+            }
+            // Abandoned by the collection it shared, retry next time
+            None => continue,
+        }
+        // Alerts end unless resent, so they resolve if the exporter stops
+        let alerts = exporter.alerts.alerts_to_send(
+            config.alertmanager_interval * 2,
+            config.alertmanager_interval * 4,
+            &alertmanager::AlertTemplate {
+                labels: &config.alertmanager_labels,
+                annotations: &config.alertmanager_annotations,
+            },
+        );
+        if alerts.is_empty() {
+            continue;
+        }
+        if let Err(e) = alertmanager::send(alertmanager_url, &alerts).await {
+            eprintln!("Error sending alerts to the Alertmanager: {}", e);
+        }
+    }
+}
+
+/// How long a rejected scrape should wait before retrying, estimated from the
+/// duration of the last collection
+fn retry_after_seconds(exporter: &Exporter, running_for: Duration) -> u64 {
+    let last_duration = exporter
+        .last_scrape
+        .lock()
+        .expect("last scrape lock shouldn't be poisoned")
+        .as_ref()
+        .map_or(Duration::ZERO, |scrape| scrape.duration);
+    last_duration
+        .saturating_sub(running_for)
+        .as_secs_f64()
+        .ceil()
+        .max(1.0) as u64
+}
+
+/// Deadline for collecting the metrics of a scrape, so they can be served
+/// before the scrape timeout Prometheus sends, less the given offset
+fn scrape_deadline(headers: &hyper::HeaderMap, offset: Duration) -> Option<Instant> {
+    let timeout = headers
+        .get("X-Prometheus-Scrape-Timeout-Seconds")?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())?;
+    Some(Instant::now() + timeout.saturating_sub(offset))
+}
+
+/// Collect the metrics of the given target for a request, sharing the
+/// collection with concurrent requests with the same key as the overlapping
+/// scrapes policy allows, along with how long ago the collection finished
+/// (non-zero if served from an earlier one). `None` if rejected because a
+/// collection is already running, with the response's status and
+/// `Retry-After` header set accordingly.
+async fn collect_shared(
+    exporter: &Arc<Exporter>,
+    collection_key: &str,
+    metrics_target: MetricsTarget,
+    tenant: Option<&Tenant>,
+    min_interval: Duration,
+    deadline: Option<Instant>,
+    response: &mut Response<Body>,
+) -> Option<(CollectionResult, Duration)> {
+    let config = exporter.config();
+    loop {
+        let admission = exporter.in_flight_collections.admit(
+            collection_key,
+            config.overlapping_scrapes,
+            config.overlapping_scrapes_fresh_for,
+            min_interval,
+        );
+        match admission {
+            Admission::Lead(leader) => {
+                let maybe_metrics =
+                    collect_metrics(exporter, metrics_target, tenant.cloned(), deadline, false)
+                        .await;
+                leader.finish(&maybe_metrics, min_interval);
+                return Some((maybe_metrics, Duration::ZERO));
+            }
+            Admission::Join(follower) => {
+                if let Some(maybe_metrics) = follower.result().await {
+                    return Some((maybe_metrics, Duration::ZERO));
+                }
+            }
+            Admission::Wait(follower) => {
+                follower.result().await;
+            }
+            Admission::Reject { running_for } => {
+                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                response.headers_mut().insert(
+                    header::RETRY_AFTER,
+                    HeaderValue::from(retry_after_seconds(exporter, running_for)),
+                );
+                return None;
+            }
+            Admission::Cached { collection, age } => return Some((Ok(collection), age)),
+        }
+    }
 }
 
 async fn handle_request(
-    compose_config_globs: Vec<String>,
+    exporter: Arc<Exporter>,
+    peer_addr: SocketAddr,
     req: Request<Body>,
 ) -> Result<Response<Body>, Infallible> {
+    let config = exporter.config();
+    if let Some(mut response) = config.cors.preflight_response(&req) {
+        config.web.apply(&mut response);
+        return Ok(response);
+    }
     let mut response = Response::new(Body::empty());
+    let client_addr =
+        forwarded::client_addr(peer_addr.ip(), req.headers(), &config.trusted_proxies);
 
+    let (metrics_target, selection_error) = match (
+        MetricsTarget::of_path(&config, req.uri().path()),
+        Selection::from_query(req.uri().query()),
+    ) {
+        (Some(MetricsTarget::All), Ok(Some(selection))) => {
+            (Some(MetricsTarget::Selected(selection)), None)
+        }
+        (Some(MetricsTarget::All), Err(e)) => (Some(MetricsTarget::All), Some(e)),
+        (metrics_target, _) => (metrics_target, None),
+    };
+    let format = exposition::Format::negotiate(req.headers());
+    let metrics_content_type = format.content_type();
+    let tenant = config.tenants.authorize(req.headers());
+    let requires_tenant = metrics_target.is_some()
+        || matches!(req.uri().path(), "/api/v1/uptime" | "/api/v1/apps" | "/sd");
     match (req.method(), req.uri().path()) {
-        (&Method::GET, "/") => {
-            *response.status_mut() = StatusCode::PERMANENT_REDIRECT;
+        _ if requires_tenant && config.tenants.are_enabled() && tenant.is_none() => {
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
             response
                 .headers_mut()
-                .insert(header::LOCATION, HeaderValue::from_static("/metrics"));
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        _ if requires_tenant
+            && config.scrape_auth.is_enabled()
+            && !config.scrape_auth.is_authorized(req.headers()) =>
+        {
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static(config.scrape_auth.challenge()),
+            );
+        }
+        _ if selection_error.is_some() => {
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            *response.body_mut() = Body::from(format!(
+                "{}\n",
+                selection_error.expect("selection error should be set")
+            ));
+        }
+        (&Method::GET, "/") => {
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            // Expanding the globs and scanning the directories touches the
+            // filesystem, so do it off the async threads like the discovery
+            let rendering_exporter = exporter.clone();
+            let page =
+                tokio::task::spawn_blocking(move || landing_page::render(&rendering_exporter))
+                    .await
+                    .expect("rendering the landing page shouldn't panic");
+            *response.body_mut() = Body::from(page);
+        }
+        (&Method::GET | &Method::HEAD, "/-/healthy") => {
+            *response.body_mut() = Body::from("Healthy\n");
+        }
+        (&Method::GET | &Method::HEAD, "/-/ready") => {
+            let not_ready_reason = if !exporter.ready.load(Ordering::Relaxed) {
+                Some("Warming up".to_string())
+            } else if !exporter.collected_successfully.load(Ordering::Relaxed) {
+                Some("No collection has succeeded yet".to_string())
+            } else {
+                exporter
+                    .docker
+                    .ping()
+                    .await
+                    .err()
+                    .map(|err| format!("Docker is unreachable: {}", err))
+            };
+            match not_ready_reason {
+                None => *response.body_mut() = Body::from("Ready\n"),
+                Some(reason) => {
+                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    *response.body_mut() = Body::from(format!("{}\n", reason));
+                }
+            }
         }
-        (&Method::GET, "/metrics") => {
-            let maybe_metrics = get_metrics_for_config_globs(&compose_config_globs);
+        (&Method::GET, "/api/v1/uptime") if exporter.uptime_history.is_some() => {
+            let uptime_history = exporter
+                .uptime_history
+                .as_ref()
+                .expect("uptime history should be enabled");
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(JSON_CONTENT_TYPE),
+            );
+            let mut uptimes = uptime_history.uptimes();
+            if let Some(tenant) = tenant {
+                let compose_names = exporter
+                    .compose_names
+                    .lock()
+                    .expect("compose names lock shouldn't be poisoned");
+                let tenant_compose_names: HashSet<&String> = compose_names
+                    .iter()
+                    .filter(|(config_path, _)| tenant.owns(config_path))
+                    .map(|(_, compose_name)| compose_name)
+                    .collect();
+                uptimes.retain(|uptime| tenant_compose_names.contains(&uptime.compose_name));
+            }
+            *response.body_mut() = Body::from(
+                serde_json::to_string(&uptimes).expect("uptimes should serialize to JSON"),
+            );
+        }
+        (&Method::GET, "/api/v1/apps") => {
+            // Share collections with scrapes of all apps
+            let collection_key = match tenant {
+                Some(tenant) => format!("{} (tenant {})", config.metrics_path, tenant.name),
+                None => config.metrics_path.clone(),
+            };
+            let min_interval = if tenant.is_none() {
+                min_interval_of_all_apps(&config)
+            } else {
+                config.min_scrape_interval
+            };
+            let maybe_metrics = collect_shared(
+                &exporter,
+                &collection_key,
+                MetricsTarget::All,
+                tenant,
+                min_interval,
+                None,
+                &mut response,
+            )
+            .await;
             *response.body_mut() = match maybe_metrics {
-                Ok(mut metrics) => {
-                    metrics.push('\n');
-                    Body::from(metrics)
+                None => Body::from("A collection is already running, try again later"),
+                Some((Ok(collection), _)) => {
+                    response.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(JSON_CONTENT_TYPE),
+                    );
+                    Body::from(
+                        serde_json::to_string(&collection.apps)
+                            .expect("app states should serialize to JSON"),
+                    )
+                }
+                Some((Err(e), _)) => {
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    eprintln!(
+                        "Error while handling {} request from {}: [{}] {}",
+                        req.uri().path(),
+                        client_addr,
+                        e.code,
+                        e
+                    );
+                    Body::from(format!(
+                        "Internal server error ({}). Check logs for details.",
+                        e.code
+                    ))
+                }
+            };
+        }
+        (&Method::GET, "/sd") => {
+            // Scraped at the address Prometheus reached the exporter at
+            let address = req
+                .headers()
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .map_or_else(
+                    || SocketAddr::new(config.address, config.port).to_string(),
+                    String::from,
+                );
+            let scheme = if config.tls.is_some() {
+                "https"
+            } else {
+                "http"
+            };
+            let maybe_discovery = discover_apps(&exporter, &MetricsTarget::All)
+                .await
+                .map_err(|err| err.to_string());
+            match maybe_discovery {
+                Ok(discovery) => {
+                    let target_groups: Vec<_> = discovery
+                        .config_paths
+                        .iter()
+                        .filter(|config_path| tenant.is_none_or(|tenant| tenant.owns(config_path)))
+                        .map(|config_path| {
+                            let compose_name = known_compose_name(&exporter, &config, config_path);
+                            let app_labels = config.app_labels.of(Some(&compose_name), config_path);
+                            http_sd::TargetGroup::of_app(
+                                &address,
+                                scheme,
+                                &config.metrics_path,
+                                &compose_name,
+                                app_labels,
+                            )
+                        })
+                        .collect();
+                    response.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(JSON_CONTENT_TYPE),
+                    );
+                    *response.body_mut() = Body::from(
+                        serde_json::to_string(&target_groups)
+                            .expect("target groups should serialize to JSON"),
+                    );
                 }
                 Err(e) => {
                     *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-                    eprintln!("Error while handling /metrics request: {}", e);
-                    Body::from("Internal server error. Check logs for details.")
+                    eprintln!(
+                        "Error while handling {} request from {}: {}",
+                        req.uri().path(),
+                        client_addr,
+                        e
+                    );
+                    *response.body_mut() =
+                        Body::from("Internal server error. Check logs for details.");
+                }
+            }
+        }
+        (&Method::GET, "/api/v1/openapi.json") => {
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(JSON_CONTENT_TYPE),
+            );
+            *response.body_mut() = Body::from(
+                serde_json::to_string(&openapi::document(&exporter))
+                    .expect("OpenAPI document should serialize to JSON"),
+            );
+        }
+        (&Method::GET, "/version") => {
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(JSON_CONTENT_TYPE),
+            );
+            *response.body_mut() = Body::from(
+                serde_json::to_string(&VersionInfo::of_this_build())
+                    .expect("version info should serialize to JSON"),
+            );
+        }
+        (&Method::GET, "/debug/state") if config.debug_token.is_some() => {
+            let debug_token = config.debug_token.as_deref().unwrap_or_default();
+            if auth::is_bearer_authorized(req.headers(), debug_token) {
+                response.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(JSON_CONTENT_TYPE),
+                );
+                *response.body_mut() = Body::from(
+                    serde_json::to_string_pretty(&DebugState::of(&exporter))
+                        .expect("debug state should serialize to JSON"),
+                );
+            } else {
+                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+        }
+        (&Method::GET, "/maintenance") if config.maintenance_token.is_some() => {
+            let maintenance_token = config.maintenance_token.as_deref().unwrap_or_default();
+            if auth::is_bearer_authorized(req.headers(), maintenance_token) {
+                response.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(JSON_CONTENT_TYPE),
+                );
+                *response.body_mut() = Body::from(
+                    serde_json::to_string(&exporter.maintenance.targets())
+                        .expect("maintenance targets should serialize to JSON"),
+                );
+            } else {
+                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+        }
+        (&Method::PUT | &Method::DELETE, path)
+            if config.maintenance_token.is_some() && path.starts_with("/maintenance/") =>
+        {
+            let maintenance_token = config.maintenance_token.as_deref().unwrap_or_default();
+            let target = MaintenanceTarget::from_path(&path["/maintenance/".len()..]);
+            if !auth::is_bearer_authorized(req.headers(), maintenance_token) {
+                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            } else if let Some(target) = target {
+                let in_maintenance = req.method() == Method::PUT;
+                println!(
+                    "{} {} maintenance (requested by {})",
+                    if in_maintenance { "Starting" } else { "Ending" },
+                    match &target.service_name {
+                        Some(service_name) =>
+                            format!("service {} of {}", service_name, target.compose_name),
+                        None => target.compose_name.clone(),
+                    },
+                    client_addr
+                );
+                exporter.maintenance.set(target, in_maintenance);
+                *response.status_mut() = StatusCode::NO_CONTENT;
+            } else {
+                *response.status_mut() = StatusCode::NOT_FOUND;
+            }
+        }
+        (&Method::HEAD, _) if metrics_target.is_some() => {
+            // Answer (load balancer) health checks without running a collection
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(metrics_content_type),
+            );
+        }
+        (&Method::GET, _) if metrics_target.is_some() => {
+            let metrics_target = metrics_target.expect("metrics target should be set");
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(metrics_content_type),
+            );
+            let all_apps = matches!(metrics_target, MetricsTarget::All) && tenant.is_none();
+            // Selections of apps differ by query, and tenants see different
+            // apps, so don't share collections between them
+            let collection_key = match &metrics_target {
+                MetricsTarget::Selected(selection) => {
+                    format!("{}?{}", req.uri().path(), selection.key())
+                }
+                _ => req.uri().path().to_string(),
+            };
+            let collection_key = match tenant {
+                Some(tenant) => format!("{} (tenant {})", collection_key, tenant.name),
+                None => collection_key,
+            };
+            // Collected in the background, only the first scrapes (before the
+            // warm-up collection finished) wait for a collection
+            let collected_in_background = all_apps && !config.collect_interval.is_zero();
+            let min_interval = if all_apps {
+                min_interval_of_all_apps(&config)
+            } else {
+                config.min_scrape_interval
+            };
+            let deadline = scrape_deadline(req.headers(), config.scrape_timeout_offset);
+            let maybe_metrics = collect_shared(
+                &exporter,
+                &collection_key,
+                metrics_target,
+                tenant,
+                min_interval,
+                deadline,
+                &mut response,
+            )
+            .await;
+            let collection_age = maybe_metrics
+                .as_ref()
+                .map_or(Duration::ZERO, |(_, age)| *age);
+            let maybe_metrics = maybe_metrics.map(|(maybe_metrics, _)| maybe_metrics);
+            *response.body_mut() = match maybe_metrics {
+                None => Body::from("A collection is already running, try again later"),
+                Some(Ok(collection)) => {
+                    let mut metrics = collection.metrics;
+                    // Checks of apps that weren't collected (e.g. of other
+                    // tenants, groups or selections) don't belong in the scrape
+                    let synthetic_checks: Vec<SyntheticCheck> = if all_apps {
+                        config.synthetic_checks.clone()
+                    } else {
+                        let compose_names: HashSet<String> = collection
+                            .apps
+                            .into_iter()
+                            .map(|app| app.compose_name)
+                            .collect();
+                        config
+                            .synthetic_checks
+                            .iter()
+                            .filter(|check| compose_names.contains(check.compose_name()))
+                            .cloned()
+                            .collect()
+                    };
+                    synthetic_checks::add_metrics(&mut metrics, &synthetic_checks).await;
+                    add_target_info_metric(&mut metrics, &exporter);
+                    telemetry::add_metrics(
+                        &mut metrics,
+                        exporter
+                            .last_scrape
+                            .lock()
+                            .expect("last scrape lock shouldn't be poisoned")
+                            .as_ref()
+                            .map(|scrape| scrape.duration),
+                    );
+                    if !config.min_scrape_interval.is_zero() {
+                        metrics.add(
+                            "compose_apps_exporter_collection_age_seconds",
+                            &[],
+                            collection_age.as_secs_f64(),
+                        );
+                    }
+                    if collected_in_background {
+                        let collected_at = SystemTime::now() - collection_age;
+                        metrics.add(
+                            "compose_apps_last_collect_timestamp_seconds",
+                            &[],
+                            collected_at
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or_default()
+                                .as_secs_f64(),
+                        );
+                    }
+                    exporter
+                        .config_reloads
+                        .lock()
+                        .expect("config reloads lock shouldn't be poisoned")
+                        .add_metrics(&mut metrics, config.hash);
+                    #[cfg(feature = "jemalloc")]
+                    if let Err(e) = allocator::add_metrics(&mut metrics) {
+                        eprintln!("Error reading allocator statistics: {}", e);
+                    }
+                    // Only the metrics of all apps include those of other instances
+                    let federation_scrapes = if all_apps && !config.federation.is_empty() {
+                        federation::scrape(&config.federation).await
+                    } else {
+                        vec![]
+                    };
+                    federation::add_up_metrics(&mut metrics, &federation_scrapes);
+                    let mut families = metric_naming::apply(
+                        metrics.gather(telemetry::collectors()),
+                        config.metric_naming,
+                    );
+                    extra_labels::apply(&mut families, &config.extra_labels);
+                    metric_naming::apply_prefix(&mut families, &config.metric_prefix);
+                    // Named by the other instances already
+                    exposition::merge_families(
+                        &mut families,
+                        federation::federated_families(&federation_scrapes),
+                    );
+                    let body = format.encode(&families);
+                    response
+                        .headers_mut()
+                        .append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+                    if body.len() >= config.gzip_min_size_bytes
+                        && compression::accepts_gzip(req.headers())
+                    {
+                        response
+                            .headers_mut()
+                            .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+                        Body::from(compression::gzip(&body))
+                    } else {
+                        Body::from(body)
+                    }
+                }
+                Some(Err(e)) => {
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    eprintln!(
+                        "Error while handling {} request from {}: [{}] {}",
+                        req.uri().path(),
+                        client_addr,
+                        e.code,
+                        e
+                    );
+                    if config.debug_errors {
+                        response.headers_mut().insert(
+                            header::CONTENT_TYPE,
+                            HeaderValue::from_static(JSON_CONTENT_TYPE),
+                        );
+                        Body::from(
+                            serde_json::to_string(&e)
+                                .expect("scrape error should serialize to JSON"),
+                        )
+                    } else {
+                        Body::from(format!(
+                            "Internal server error ({}). Check logs for details.",
+                            e.code
+                        ))
+                    }
                 }
             };
         }
@@ -371,6 +3954,8 @@ async fn handle_request(
             *response.status_mut() = StatusCode::NOT_FOUND;
         }
     };
+    config.cors.apply(req.headers(), &mut response);
+    config.web.apply(&mut response);
 
     Ok(response)
 }
@@ -399,11 +3984,16 @@ fn get_config() -> Result<ParsedConfig, Box<dyn std::error::Error>> {
             ENV_PREFIX
         ))
         .get_matches();
-    let cli_args = Config::from_arg_matches(&cli_matches)?;
+    let mut cli_args = Config::from_arg_matches(&cli_matches)?;
+    let command = cli_args.command.take();
     let cli_args_without_defaults =
         serde_json::from_value::<Map<String, Value>>(serde_json::to_value(&cli_args)?)?
             .into_iter()
-            .filter(|(k, _)| cli_matches.value_source(k) != Some(ValueSource::DefaultValue))
+            .filter(|(k, _)| {
+                // Fields that can only be set in the config file aren't CLI args
+                cli_matches.ids().any(|id| id == k)
+                    && cli_matches.value_source(k) == Some(ValueSource::CommandLine)
+            })
             .collect::<Map<String, Value>>();
 
     let config: Config = Figment::new()
@@ -415,16 +4005,36 @@ fn get_config() -> Result<ParsedConfig, Box<dyn std::error::Error>> {
         // values)
         .join(Serialized::defaults(cli_args))
         .merge(Serialized::defaults(cli_args_without_defaults))
-        .extract::<Config>()?
-        .into();
+        .extract::<Config>()?;
 
-    let parsed_config: ParsedConfig = config.try_into()?;
+    let mut parsed_config: ParsedConfig = config.try_into()?;
+    parsed_config.command = command;
 
-    return Ok(parsed_config);
+    Ok(parsed_config)
+}
+
+/// Run a subcommand instead of the exporter
+fn run_command(
+    command: &ExporterCommand,
+    config: &ParsedConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        ExporterCommand::MetricsSchema { format } => {
+            let schema = metrics_schema::metrics_schema(config);
+            match format {
+                SchemaFormat::Json => println!("{}", serde_json::to_string_pretty(&schema)?),
+                SchemaFormat::Yaml => print!("{}", serde_yaml::to_string(&schema)?),
+            }
+        }
+        ExporterCommand::Watch { .. } => unreachable!("watch runs with the exporter, see main"),
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() {
+    #[cfg(feature = "tokio-console")]
+    console_subscriber::init();
     let config = match get_config() {
         Ok(config) => config,
         Err(e) => {
@@ -432,25 +4042,200 @@ async fn main() {
             std::process::exit(1);
         }
     };
+    let watch_command = config
+        .command
+        .as_ref()
+        .filter(|command| matches!(command, ExporterCommand::Watch { .. }))
+        .cloned();
+    if let Some(command) = config.command.as_ref().filter(|_| watch_command.is_none()) {
+        if let Err(e) = run_command(command, &config) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let docker = match DockerCli::detect(&config).await {
+        Ok(docker) => docker,
+        Err(e) => {
+            eprintln!("Error detecting docker version: \n{}", e);
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "Detected docker {} with docker compose {}",
+        docker.compat.docker_version, docker.compat.compose_version
+    );
+    if let Some(docker_host) = &docker.host {
+        println!("Using docker host {}", docker_host);
+    }
     let socket_address = SocketAddr::from((config.address, config.port));
-
-    let make_svc = make_service_fn(move |_conn| {
-        let compose_configs_glob = config.compose_configs_glob.clone();
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                handle_request(compose_configs_glob.clone(), req)
-            }))
+    // Not reloadable, as the listener is only set up once
+    let proxy_protocol = config.proxy_protocol;
+    let mut http = Http::new();
+    if !config.web.http2 {
+        http.http1_only(true);
+    }
+    let tls_acceptor = match config.tls.clone().map(tls::ReloadingAcceptor::new) {
+        Some(Ok(tls_acceptor)) => Some(Arc::new(tls_acceptor)),
+        Some(Err(e)) => {
+            eprintln!("Error loading the TLS certificate and key: {}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let maintenance = Maintenance::new(config.maintenance_hold_last_state);
+    let uptime_history = match config
+        .uptime_history_file
+        .as_deref()
+        .map(UptimeHistory::open)
+    {
+        None => None,
+        Some(Ok(uptime_history)) => Some(uptime_history),
+        Some(Err(e)) => {
+            eprintln!("Error opening uptime history: \n{}", e);
+            std::process::exit(1);
         }
+    };
+    let exporter = Arc::new(Exporter {
+        config: RwLock::new(Arc::new(config)),
+        config_reloads: Mutex::new(ConfigReloads::new()),
+        in_flight_collections: InFlightCollections::default(),
+        docker,
+        started_at: Instant::now(),
+        ready: AtomicBool::new(false),
+        collected_successfully: AtomicBool::new(false),
+        last_scrape: Mutex::new(None),
+        last_background_collection: Mutex::new(None),
+        maintenance,
+        grace_periods: GracePeriods::new(),
+        debouncer: Debouncer::default(),
+        availability: Availability::default(),
+        uptime_history,
+        logged_config_problems: Mutex::new(HashMap::new()),
+        logged_app_errors: Mutex::new(HashMap::new()),
+        logged_name_conflicts: Mutex::new(vec![]),
+        alerts: Alerts::default(),
+        zabbix_items: ZabbixItems::default(),
+        compose_names: Mutex::new(HashMap::new()),
+        engine_platform: EnginePlatform::default(),
+        registry_digests: RegistryDigests::default(),
+        container_details: ContainerDetailsCache::default(),
     });
+    if let Some(ExporterCommand::Watch { interval_seconds }) = watch_command {
+        watch::run(exporter, Duration::from_secs(interval_seconds.max(1))).await;
+        return;
+    }
+
+    let warm_up = {
+        let exporter = exporter.clone();
+        tokio::spawn(async move { warm_up(&exporter).await })
+    };
+    if exporter.config().warm_up_before_listen {
+        warm_up.await.expect("warm-up shouldn't panic");
+    }
+    tokio::spawn(collect_periodically(exporter.clone()));
+    tokio::spawn(send_alerts(exporter.clone()));
+    tokio::spawn(push_to_zabbix(exporter.clone()));
 
-    let server = Server::bind(&socket_address).serve(make_svc);
+    let listener = match tokio::net::TcpListener::bind(socket_address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("server error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     println!(
-        "compose-apps-exporter listening on http://{}",
+        "compose-apps-exporter listening on {}://{}",
+        if tls_acceptor.is_some() {
+            "https"
+        } else {
+            "http"
+        },
         socket_address
     );
-    if let Err(e) = server.await {
-        eprintln!("server error: {}", e);
-        std::process::exit(1);
+    #[cfg(unix)]
+    {
+        let exporter = exporter.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => {
+                    eprintln!(
+                        "Error listening for SIGHUP, config reloading disabled: {}",
+                        e
+                    );
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                let exporter = exporter.clone();
+                tokio::task::spawn_blocking(move || exporter.reload_config())
+                    .await
+                    .expect("config reload shouldn't panic");
+            }
+        });
+    }
+    loop {
+        let (mut stream, peer_addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                // e.g. too many open files, back off instead of spinning
+                eprintln!("Error accepting connection: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let exporter = exporter.clone();
+        let tls_acceptor = tls_acceptor.clone();
+        let http = http.clone();
+        tokio::spawn(async move {
+            let peer_addr = if proxy_protocol {
+                let header = tokio::time::timeout(
+                    PROXY_PROTOCOL_HEADER_TIMEOUT,
+                    proxy_protocol::read_header(&mut stream),
+                );
+                match header.await {
+                    Ok(Ok(source_addr)) => source_addr.unwrap_or(peer_addr),
+                    Ok(Err(e)) => {
+                        eprintln!(
+                            "Error reading PROXY protocol header from {}: {}",
+                            peer_addr, e
+                        );
+                        return;
+                    }
+                    Err(_) => {
+                        eprintln!(
+                            "Timed out waiting for PROXY protocol header from {}",
+                            peer_addr
+                        );
+                        return;
+                    }
+                }
+            } else {
+                peer_addr
+            };
+            let service = service_fn(move |req| handle_request(exporter.clone(), peer_addr, req));
+            // Like hyper's `Server`, ignore errors of individual connections
+            // (e.g. clients disconnecting early)
+            let Some(tls_acceptor) = tls_acceptor else {
+                let _ = http.serve_connection(stream, service).await;
+                return;
+            };
+            let handshake = tokio::time::timeout(
+                TLS_HANDSHAKE_TIMEOUT,
+                tls_acceptor.acceptor().accept(stream),
+            );
+            match handshake.await {
+                Ok(Ok(stream)) => {
+                    let _ = http.serve_connection(stream, service).await;
+                }
+                // e.g. plain HTTP requests, or clients not trusting the
+                // certificate
+                Ok(Err(_)) => {}
+                Err(_) => eprintln!("Timed out waiting for TLS handshake from {}", peer_addr),
+            }
+        });
     }
 }
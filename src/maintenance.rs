@@ -0,0 +1,133 @@
+//! Maintenance mode for apps and services, so planned upgrades don't page
+//! anyone
+
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+/// Compose service label marking the service as in maintenance
+pub static MAINTENANCE_LABEL: &str = "net.pfiers.compose-apps-exporter.maintenance";
+
+/// App, or service of an app, put in maintenance through the API
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MaintenanceTarget {
+    pub compose_name: String,
+    /// If not set, all services of the app are in maintenance
+    pub service_name: Option<String>,
+}
+
+impl MaintenanceTarget {
+    /// Parse the target from the path of a maintenance API request, e.g.
+    /// `my-app` or `my-app/my-service`
+    pub fn from_path(path: &str) -> Option<Self> {
+        let mut segments = path.split('/');
+        let compose_name = segments.next().filter(|name| !name.is_empty())?;
+        let service_name = segments.next().filter(|name| !name.is_empty());
+        if segments.next().is_some() {
+            return None;
+        }
+        Some(MaintenanceTarget {
+            compose_name: compose_name.to_string(),
+            service_name: service_name.map(String::from),
+        })
+    }
+}
+
+pub struct Maintenance {
+    targets: Mutex<BTreeSet<MaintenanceTarget>>,
+    /// Whether to report the last state and health seen outside of
+    /// maintenance for services in maintenance
+    hold_last_state: bool,
+    /// Last state and health of services outside of maintenance, by compose
+    /// and service name
+    last_states: Mutex<HashMap<(String, String), (String, String)>>,
+}
+
+impl Maintenance {
+    pub fn new(hold_last_state: bool) -> Self {
+        Maintenance {
+            targets: Mutex::new(BTreeSet::new()),
+            hold_last_state,
+            last_states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Put the given target in maintenance, or take it out of it
+    pub fn set(&self, target: MaintenanceTarget, in_maintenance: bool) {
+        let mut targets = self
+            .targets
+            .lock()
+            .expect("maintenance targets lock shouldn't be poisoned");
+        if in_maintenance {
+            targets.insert(target);
+        } else {
+            targets.remove(&target);
+        }
+    }
+
+    /// Targets put in maintenance through the API
+    pub fn targets(&self) -> Vec<MaintenanceTarget> {
+        self.targets
+            .lock()
+            .expect("maintenance targets lock shouldn't be poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the given service is in maintenance, either through the API
+    /// or its label
+    pub fn is_in_maintenance(
+        &self,
+        compose_name: &str,
+        service_name: &str,
+        labels: &HashMap<String, String>,
+    ) -> bool {
+        let label_set = labels
+            .get(MAINTENANCE_LABEL)
+            .is_some_and(|value| matches!(value.to_lowercase().as_str(), "true" | "1" | "yes"));
+        label_set
+            || self
+                .targets
+                .lock()
+                .expect("maintenance targets lock shouldn't be poisoned")
+                .iter()
+                .any(|target| {
+                    target.compose_name == compose_name
+                        && target
+                            .service_name
+                            .as_ref()
+                            .is_none_or(|target_service| target_service == service_name)
+                })
+    }
+
+    /// Get the state and health to report for a service currently in the
+    /// given state and health: the last ones seen outside of maintenance if
+    /// holding them, and the current ones otherwise
+    pub fn reported_state(
+        &self,
+        compose_name: &str,
+        service_name: &str,
+        in_maintenance: bool,
+        state: &str,
+        health: &str,
+    ) -> (String, String) {
+        if !self.hold_last_state {
+            return (state.to_string(), health.to_string());
+        }
+        let mut last_states = self
+            .last_states
+            .lock()
+            .expect("last states lock shouldn't be poisoned");
+        let key = (compose_name.to_string(), service_name.to_string());
+        match (in_maintenance, last_states.get(&key)) {
+            (true, Some(last_state)) => last_state.clone(),
+            (true, None) => (state.to_string(), health.to_string()),
+            (false, _) => {
+                let current = (state.to_string(), health.to_string());
+                last_states.insert(key, current.clone());
+                current
+            }
+        }
+    }
+}
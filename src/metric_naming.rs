@@ -0,0 +1,285 @@
+//! Metric naming schemes: the legacy names, kept for compatibility, and names
+//! following the Prometheus naming conventions (no windows or abbreviations in
+//! names, consistent suffixes), and the prefix of all names
+
+use clap::ValueEnum;
+use prometheus::proto::MetricFamily;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::exposition;
+use crate::metrics_schema::MetricSchema;
+
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetricNaming {
+    #[default]
+    Legacy,
+    BestPractice,
+    /// Both the legacy and best-practice names, to migrate dashboards and
+    /// alerts from one to the other
+    Both,
+}
+
+/// Best-practice name of a metric whose legacy name differs
+struct Renamed {
+    name: &'static str,
+    /// Label replacing a part of the legacy name
+    label: Option<(&'static str, String)>,
+    /// Help text replacing the legacy one, if that mentions the replaced part
+    help: Option<&'static str>,
+}
+
+/// Get the best-practice name of the metric with the given legacy name, if it
+/// differs
+fn best_practice_name(legacy_name: &str) -> Option<Renamed> {
+    let windowed = |prefix: &str, name: &'static str, help: &'static str| {
+        let window = legacy_name.strip_prefix(prefix)?;
+        Some(Renamed {
+            name,
+            label: Some(("window", window.to_string())),
+            help: Some(help),
+        })
+    };
+    match legacy_name {
+        "compose_apps_nbro_configs" => Some(Renamed {
+            name: "compose_apps_configs",
+            label: None,
+            help: None,
+        }),
+        "compose_service_synthetic_health" => Some(Renamed {
+            name: "compose_service_synthetic_check_success",
+            label: None,
+            help: None,
+        }),
+        _ => windowed(
+            "compose_service_available_ratio_",
+            "compose_service_available_ratio",
+            "Fraction of the window the docker compose service was running and healthy (as far as observed)",
+        )
+        .or_else(|| {
+            windowed(
+                "compose_service_uptime_ratio_",
+                "compose_service_uptime_ratio",
+                "Fraction of the window the docker compose service was running and healthy, from the uptime history",
+            )
+        }),
+    }
+}
+
+/// Apply the given naming scheme to metric families, which use the legacy
+/// names. Several legacy families can map to one best-practice family.
+pub fn apply(families: Vec<MetricFamily>, naming: MetricNaming) -> Vec<MetricFamily> {
+    if naming == MetricNaming::Legacy {
+        return families;
+    }
+    let mut renamed_families = vec![];
+    for family in families {
+        let Some(renamed) = best_practice_name(family.name()) else {
+            exposition::merge_family(&mut renamed_families, family);
+            continue;
+        };
+        let mut renamed_family = family.clone();
+        renamed_family.set_name(renamed.name.to_string());
+        if let Some(help) = renamed.help {
+            renamed_family.set_help(help.to_string());
+        }
+        if let Some((label_name, label_value)) = &renamed.label {
+            exposition::add_missing_labels(
+                std::slice::from_mut(&mut renamed_family),
+                &[(*label_name, label_value.as_str())],
+            );
+        }
+        if naming == MetricNaming::Both {
+            exposition::merge_family(&mut renamed_families, family);
+        }
+        exposition::merge_family(&mut renamed_families, renamed_family);
+    }
+    renamed_families
+}
+
+/// Apply the given naming scheme to a metrics schema
+pub fn apply_to_schema(schema: Vec<MetricSchema>, naming: MetricNaming) -> Vec<MetricSchema> {
+    if naming == MetricNaming::Legacy {
+        return schema;
+    }
+    let mut declared = HashSet::new();
+    let mut renamed_schema = vec![];
+    for metric in schema {
+        let Some(renamed) = best_practice_name(&metric.name) else {
+            renamed_schema.push(metric);
+            continue;
+        };
+        let is_new = declared.insert(renamed.name);
+        let mut labels = metric.labels.clone();
+        if let Some((label_name, _)) = &renamed.label {
+            labels.push(label_name.to_string());
+        }
+        let renamed_metric = MetricSchema {
+            name: renamed.name.to_string(),
+            metric_type: metric.metric_type,
+            help: renamed.help.map_or(metric.help.clone(), String::from),
+            labels,
+        };
+        if naming == MetricNaming::Both {
+            renamed_schema.push(metric);
+        }
+        if is_new {
+            renamed_schema.push(renamed_metric);
+        }
+    }
+    renamed_schema
+}
+
+/// Prefix of the names of the exporter's metrics, e.g. `compose` in
+/// `compose_service_health` and `compose_apps_exporter_build_info`
+pub static DEFAULT_PREFIX: &str = "compose";
+
+/// Whether the given prefix keeps the names of metrics valid
+pub fn is_valid_prefix(prefix: &str) -> bool {
+    let mut chars = prefix.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Name of the metric with the given name under the given prefix instead of the
+/// default one, if it has the default one
+fn prefixed_name(name: &str, prefix: &str) -> Option<String> {
+    let rest = name.strip_prefix(DEFAULT_PREFIX)?.strip_prefix('_')?;
+    Some(format!("{}_{}", prefix, rest))
+}
+
+/// Replace the default prefix of the names of the given metric families with
+/// the given one
+pub fn apply_prefix(families: &mut [MetricFamily], prefix: &str) {
+    if prefix == DEFAULT_PREFIX {
+        return;
+    }
+    for family in families {
+        if let Some(name) = prefixed_name(family.name(), prefix) {
+            family.set_name(name);
+        }
+    }
+}
+
+/// Replace the default prefix of the metrics in a metrics schema with the given
+/// one
+pub fn apply_prefix_to_schema(mut schema: Vec<MetricSchema>, prefix: &str) -> Vec<MetricSchema> {
+    for metric in &mut schema {
+        if let Some(name) = prefixed_name(&metric.name, prefix) {
+            metric.name = name;
+        }
+    }
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    static LEGACY_METRICS: &str = indoc! {r#"
+        # HELP compose_service_available_ratio_1h Fraction of the last hour the docker compose service was running and healthy
+        # TYPE compose_service_available_ratio_1h gauge
+        compose_service_available_ratio_1h{compose_name="web",service_name="app"} 1
+        # HELP compose_service_available_ratio_1d Fraction of the last day the docker compose service was running and healthy
+        # TYPE compose_service_available_ratio_1d gauge
+        compose_service_available_ratio_1d 0.5
+        # HELP compose_apps_nbro_configs Number of compose files
+        # TYPE compose_apps_nbro_configs gauge
+        compose_apps_nbro_configs 2
+        # HELP compose_service_health Health of the service
+        # TYPE compose_service_health gauge
+        compose_service_health{compose_name="web",service_name="app"} 1
+    "#};
+
+    /// Apply the given naming scheme to the legacy metrics, in the text format
+    fn apply_to_legacy_metrics(naming: MetricNaming) -> String {
+        let families = apply(exposition::parse_families(LEGACY_METRICS), naming);
+        String::from_utf8(exposition::Format::Text.encode(&families)).unwrap()
+    }
+
+    #[test]
+    fn keeps_legacy_names() {
+        assert_eq!(
+            apply_to_legacy_metrics(MetricNaming::Legacy),
+            LEGACY_METRICS
+        );
+    }
+
+    #[test]
+    fn renames_to_best_practice_names() {
+        assert_eq!(
+            apply_to_legacy_metrics(MetricNaming::BestPractice),
+            indoc! {r#"
+                # HELP compose_service_available_ratio Fraction of the window the docker compose service was running and healthy (as far as observed)
+                # TYPE compose_service_available_ratio gauge
+                compose_service_available_ratio{window="1h",compose_name="web",service_name="app"} 1
+                compose_service_available_ratio{window="1d"} 0.5
+                # HELP compose_apps_configs Number of compose files
+                # TYPE compose_apps_configs gauge
+                compose_apps_configs 2
+                # HELP compose_service_health Health of the service
+                # TYPE compose_service_health gauge
+                compose_service_health{compose_name="web",service_name="app"} 1
+            "#}
+        );
+    }
+
+    #[test]
+    fn keeps_both_names() {
+        let metrics = apply_to_legacy_metrics(MetricNaming::Both);
+        for line in LEGACY_METRICS.lines() {
+            assert!(metrics.lines().any(|l| l == line), "missing {:?}", line);
+        }
+        assert!(metrics.contains("\ncompose_apps_configs 2\n"));
+        assert_eq!(
+            metrics
+                .matches("# TYPE compose_service_available_ratio gauge")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn validates_prefixes() {
+        assert!(is_valid_prefix("compose"));
+        assert!(is_valid_prefix("_my:compose_2"));
+        assert!(!is_valid_prefix(""));
+        assert!(!is_valid_prefix("2compose"));
+        assert!(!is_valid_prefix("my-compose"));
+    }
+
+    #[test]
+    fn replaces_default_prefix() {
+        let metrics = indoc! {r#"
+            # HELP compose_service_health Health of the service
+            # TYPE compose_service_health gauge
+            compose_service_health{compose_name="web"} 1
+            # TYPE composer_info gauge
+            composer_info 1
+            # TYPE up gauge
+            up 1
+        "#};
+        let prefixed = |prefix| {
+            let mut families = exposition::parse_families(metrics);
+            apply_prefix(&mut families, prefix);
+            String::from_utf8(exposition::Format::Text.encode(&families)).unwrap()
+        };
+        assert_eq!(prefixed(DEFAULT_PREFIX), metrics);
+        assert_eq!(
+            prefixed("docker"),
+            indoc! {r#"
+                # HELP docker_service_health Health of the service
+                # TYPE docker_service_health gauge
+                docker_service_health{compose_name="web"} 1
+                # TYPE composer_info gauge
+                composer_info 1
+                # TYPE up gauge
+                up 1
+            "#}
+        );
+    }
+}
@@ -0,0 +1,781 @@
+//! Catalog of the metrics the exporter can emit, so docs, dashboards and
+//! recording rules can be generated from (and validated against) it
+
+use serde::Serialize;
+
+use crate::{availability, metric_naming, service_info, uptime_history, ParsedConfig};
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricType {
+    Gauge,
+    Counter,
+    Histogram,
+}
+
+/// The labels a metric starts with
+#[derive(Clone, Copy)]
+enum BaseLabels {
+    None,
+    /// The compose name and grouping labels of an app
+    App,
+    /// [`BaseLabels::App`], plus the service name
+    Service,
+    /// The compose file path and grouping labels of an app
+    ConfigPath,
+}
+
+struct Definition {
+    name: &'static str,
+    metric_type: MetricType,
+    help: &'static str,
+    base_labels: BaseLabels,
+    extra_labels: &'static [&'static str],
+    /// Whether the given configuration makes the exporter emit the metric
+    is_enabled: fn(&ParsedConfig) -> bool,
+}
+
+const fn gauge(
+    name: &'static str,
+    help: &'static str,
+    base_labels: BaseLabels,
+    extra_labels: &'static [&'static str],
+    is_enabled: fn(&ParsedConfig) -> bool,
+) -> Definition {
+    Definition {
+        name,
+        metric_type: MetricType::Gauge,
+        help,
+        base_labels,
+        extra_labels,
+        is_enabled,
+    }
+}
+
+const fn counter(
+    name: &'static str,
+    help: &'static str,
+    base_labels: BaseLabels,
+    extra_labels: &'static [&'static str],
+    is_enabled: fn(&ParsedConfig) -> bool,
+) -> Definition {
+    Definition {
+        name,
+        metric_type: MetricType::Counter,
+        help,
+        base_labels,
+        extra_labels,
+        is_enabled,
+    }
+}
+
+const fn histogram(
+    name: &'static str,
+    help: &'static str,
+    extra_labels: &'static [&'static str],
+) -> Definition {
+    Definition {
+        name,
+        metric_type: MetricType::Histogram,
+        help,
+        base_labels: BaseLabels::None,
+        extra_labels,
+        is_enabled: |_| true,
+    }
+}
+
+static DEFINITIONS: &[Definition] = &[
+    gauge(
+        "compose_service_health",
+        "Whether the docker compose service's health is the given state",
+        BaseLabels::Service,
+        &["state"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_state",
+        "Whether the docker compose service's container is in the given state",
+        BaseLabels::Service,
+        &["state"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_in_grace_period",
+        "Whether the docker compose service is down or starting, but within its grace period",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_maintenance",
+        "Whether the docker compose service is in maintenance",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_in_downtime",
+        "Whether the docker compose service is in one of its scheduled downtime windows",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_state_matches_expected",
+        "Whether the docker compose service is in its expected state (running, or stopped for e.g. one-shots)",
+        BaseLabels::Service,
+        &["expected"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_dependency_satisfied",
+        "Whether a service the docker compose service depends on satisfies its depends_on condition",
+        BaseLabels::Service,
+        &["dependency", "condition"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_image_size_bytes",
+        "Size of the image the docker compose service runs",
+        BaseLabels::Service,
+        &["image"],
+        |config| config.image_size,
+    ),
+    gauge(
+        "compose_service_image_update_available",
+        "Whether the image the docker compose service runs differs from the one its tag points to in the registry",
+        BaseLabels::Service,
+        &["image"],
+        |config| config.image_update_check,
+    ),
+    gauge(
+        "compose_service_platform_info",
+        "Platform of the image the docker compose service runs",
+        BaseLabels::Service,
+        &["platform"],
+        |config| config.image_platform,
+    ),
+    gauge(
+        "compose_service_emulated",
+        "Whether the image the docker compose service runs doesn't match the docker engine's platform (so it runs emulated)",
+        BaseLabels::Service,
+        &[],
+        |config| config.image_platform,
+    ),
+    gauge(
+        "compose_service_gpus_reserved",
+        "Number of GPUs the docker compose service reserves (-1 for all of them)",
+        BaseLabels::Service,
+        &["driver"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_gpus_attached",
+        "Whether the docker compose service's container was created with access to GPUs",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_info",
+        "Info about the docker compose service",
+        BaseLabels::Service,
+        &service_info::LABELS,
+        |_| true,
+    ),
+    gauge(
+        "compose_service_privileged",
+        "Whether the docker compose service runs privileged",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_capability_added",
+        "Linux capability added to the docker compose service's container",
+        BaseLabels::Service,
+        &["capability"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_host_network",
+        "Whether the docker compose service uses the host's network namespace",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_host_pid",
+        "Whether the docker compose service uses the host's PID namespace",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_non_root_user",
+        "Whether the docker compose service explicitly runs as a user other than root",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_read_only",
+        "Whether the docker compose service's root file system is read-only",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_restart_count",
+        "Number of times the docker engine restarted the docker compose service's container, by its restart policy",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_oomkilled",
+        "Whether the last run of the docker compose service's container was killed for running out of memory",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_created_time_seconds",
+        "Time the docker compose service's container was created",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_start_time_seconds",
+        "Time the last run of the docker compose service's container started",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_exit_code",
+        "Exit code of the last run of the docker compose service's container (0 while it's running)",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_config_drift",
+        "Whether a container of the docker compose service was created with another config than its compose file currently renders to",
+        BaseLabels::Service,
+        &[],
+        |config| config.config_drift,
+    ),
+    gauge(
+        "compose_service_last_log_timestamp_seconds",
+        "Time of the most recent log line of the docker compose service's container",
+        BaseLabels::Service,
+        &[],
+        |config| config.last_log_timestamp,
+    ),
+    gauge(
+        "compose_service_cpu_usage_ratio",
+        "CPU time the docker compose service's container used per second (e.g. 1.5 for one and a half CPUs)",
+        BaseLabels::Service,
+        &[],
+        |config| config.enable_stats,
+    ),
+    gauge(
+        "compose_service_memory_usage_bytes",
+        "Memory the docker compose service's container uses, excluding the page cache",
+        BaseLabels::Service,
+        &[],
+        |config| config.enable_stats,
+    ),
+    gauge(
+        "compose_service_memory_limit_bytes",
+        "Memory limit of the docker compose service's container (the host's memory if it has none)",
+        BaseLabels::Service,
+        &[],
+        |config| config.enable_stats,
+    ),
+    counter(
+        "compose_service_network_receive_bytes_total",
+        "Bytes the docker compose service's container received over the network",
+        BaseLabels::Service,
+        &[],
+        |config| config.enable_stats,
+    ),
+    counter(
+        "compose_service_network_transmit_bytes_total",
+        "Bytes the docker compose service's container sent over the network",
+        BaseLabels::Service,
+        &[],
+        |config| config.enable_stats,
+    ),
+    counter(
+        "compose_service_block_read_bytes_total",
+        "Bytes the docker compose service's container read from block devices",
+        BaseLabels::Service,
+        &[],
+        |config| config.enable_stats,
+    ),
+    counter(
+        "compose_service_block_write_bytes_total",
+        "Bytes the docker compose service's container wrote to block devices",
+        BaseLabels::Service,
+        &[],
+        |config| config.enable_stats,
+    ),
+    gauge(
+        "compose_service_containers_desired",
+        "Number of containers the docker compose service is configured to run (with deploy.replicas or scale)",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_containers_running",
+        "Number of running containers of the docker compose service",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_containers_healthy",
+        "Number of running and healthy containers of the docker compose service",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_container_state",
+        "Whether a container of the docker compose service is in the given state",
+        BaseLabels::Service,
+        &["container_index", "state"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_ulimit",
+        "Soft or hard ulimit of the docker compose service",
+        BaseLabels::Service,
+        &["ulimit", "limit"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_restart_max_retries",
+        "Maximum number of times the docker compose service is restarted after failing",
+        BaseLabels::Service,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_app_external_network_missing",
+        "Whether an external network the docker compose app uses doesn't exist",
+        BaseLabels::App,
+        &["network"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_secret_info",
+        "Secret the docker compose service uses, and where its value comes from",
+        BaseLabels::Service,
+        &["secret", "source"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_config_info",
+        "Config the docker compose service uses, and where its value comes from",
+        BaseLabels::Service,
+        &["config", "source"],
+        |_| true,
+    ),
+    gauge(
+        "compose_app_secret_file_exists",
+        "Whether the file of a secret of the docker compose app exists",
+        BaseLabels::App,
+        &["secret", "path"],
+        |_| true,
+    ),
+    gauge(
+        "compose_app_config_file_exists",
+        "Whether the file of a config of the docker compose app exists",
+        BaseLabels::App,
+        &["config", "path"],
+        |_| true,
+    ),
+    gauge(
+        "compose_service_bind_mount_bytes",
+        "Total size of the files under a host path bind-mounted into the docker compose service",
+        BaseLabels::Service,
+        &["path"],
+        |config| config.bind_mount_usage,
+    ),
+    gauge(
+        "compose_service_bind_mount_usage_truncated",
+        "Whether measuring the size of the bind-mounted host path was cut short by the depth or time limit",
+        BaseLabels::Service,
+        &["path"],
+        |config| config.bind_mount_usage,
+    ),
+    gauge(
+        "compose_service_synthetic_health",
+        "Whether the exporter's own HTTP/TCP check of the docker compose service succeeded",
+        BaseLabels::None,
+        &["compose_name", "service_name", "check"],
+        |config| !config.synthetic_checks.is_empty(),
+    ),
+    gauge(
+        "compose_app_config_warnings",
+        "Number of warnings `docker compose config` prints for the app (e.g. deprecated keys or unset variables)",
+        BaseLabels::App,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_app_orphan_containers",
+        "Number of running containers of the app that belong to none of the services in its compose file",
+        BaseLabels::App,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_app_volume_bytes",
+        "Disk usage of a named volume the app declares",
+        BaseLabels::App,
+        &["volume"],
+        |config| config.volume_usage,
+    ),
+    gauge(
+        "compose_app_config_valid",
+        "Whether the app's compose file resolves, with the class of error if it doesn't",
+        BaseLabels::ConfigPath,
+        &["error_class"],
+        |_| true,
+    ),
+    gauge(
+        "compose_app_scrape_error",
+        "Whether collecting the app's metrics failed, with the code of the error if it did",
+        BaseLabels::ConfigPath,
+        &["compose_name", "error_code"],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_nbro_configs",
+        "Number of docker-compose apps",
+        BaseLabels::None,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_discovery_duration_seconds",
+        "Time taken to expand the compose configs globs and resolve the matched paths",
+        BaseLabels::None,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_glob_matches",
+        "Number of paths a compose configs glob matched (or compose files a scanned directory contains)",
+        BaseLabels::None,
+        &["glob"],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_zombie_projects",
+        "Number of compose projects known to docker whose config files don't match any configured glob",
+        BaseLabels::None,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_zombie_projects_scrape_error",
+        "Whether looking for zombie projects failed, with the code of the error if it did",
+        BaseLabels::None,
+        &["error_code"],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_zombie_project_info",
+        "Compose projects known to docker whose config files don't match any configured glob",
+        BaseLabels::None,
+        &["compose_name", "config_files"],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_volume_usage_scrape_error",
+        "Whether reading the volume sizes failed, with the code of the error if it did",
+        BaseLabels::None,
+        &["error_code"],
+        |config| config.volume_usage,
+    ),
+    gauge(
+        "compose_apps_container_name_conflict",
+        "Apps declaring a container name that another app also declares",
+        BaseLabels::None,
+        &["container_name", "compose_name", "config_path"],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_compose_name_conflict",
+        "Apps with a compose name that another app also has",
+        BaseLabels::None,
+        &["compose_name", "config_path"],
+        |_| true,
+    ),
+    gauge(
+        "target_info",
+        "Target metadata",
+        BaseLabels::None,
+        &["service_name", "service_version", "host_name", "docker_host"],
+        |_| true,
+    ),
+    counter(
+        "compose_apps_exporter_config_reloads_total",
+        "Number of configuration reloads, by outcome",
+        BaseLabels::None,
+        &["outcome"],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_exporter_build_info",
+        "Version and git commit the exporter was built from",
+        BaseLabels::None,
+        &["version", "commit"],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_exporter_scrape_duration_seconds",
+        "Duration of the last collection of metrics",
+        BaseLabels::None,
+        &[],
+        |_| true,
+    ),
+    histogram(
+        "compose_apps_exporter_docker_command_duration_seconds",
+        "Duration of docker commands and engine API requests, by kind",
+        &["command"],
+    ),
+    counter(
+        "compose_apps_exporter_docker_command_errors_total",
+        "Number of docker commands and engine API requests that failed, by kind",
+        BaseLabels::None,
+        &["command"],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_exporter_config_last_reload_successful",
+        "Whether the last configuration reload succeeded",
+        BaseLabels::None,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_exporter_config_last_reload_success_timestamp_seconds",
+        "Timestamp of the last successful configuration reload (or of the start of the exporter)",
+        BaseLabels::None,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_app_expected_missing",
+        "Whether no compose file was found for an app declared in the inventory",
+        BaseLabels::None,
+        &["app"],
+        |config| !config.inventory.is_empty(),
+    ),
+    gauge(
+        "compose_app_unexpected",
+        "Discovered app not declared in the inventory",
+        BaseLabels::None,
+        &["compose_name", "config_path"],
+        |config| !config.inventory.is_empty(),
+    ),
+    gauge(
+        "compose_apps_federation_up",
+        "Whether scraping the federated compose-apps-exporter instance succeeded",
+        BaseLabels::None,
+        &["source_host"],
+        |config| !config.federation.is_empty(),
+    ),
+    gauge(
+        "compose_apps_exporter_config_hash",
+        "Hash of the currently applied configuration",
+        BaseLabels::None,
+        &[],
+        |_| true,
+    ),
+    gauge(
+        "compose_apps_exporter_collection_age_seconds",
+        "Time since the collection of the served metrics finished (non-zero if served from the last collection because of the minimum scrape interval)",
+        BaseLabels::None,
+        &[],
+        |config| !config.min_scrape_interval.is_zero(),
+    ),
+    gauge(
+        "compose_apps_last_collect_timestamp_seconds",
+        "Time the background collection of the served metrics finished",
+        BaseLabels::None,
+        &[],
+        |config| !config.collect_interval.is_zero(),
+    ),
+    gauge(
+        "compose_apps_exporter_allocator_allocated_bytes",
+        "Bytes allocated by the exporter",
+        BaseLabels::None,
+        &[],
+        |_| cfg!(feature = "jemalloc"),
+    ),
+    gauge(
+        "compose_apps_exporter_allocator_active_bytes",
+        "Bytes in active pages allocated by the exporter",
+        BaseLabels::None,
+        &[],
+        |_| cfg!(feature = "jemalloc"),
+    ),
+    gauge(
+        "compose_apps_exporter_allocator_resident_bytes",
+        "Bytes in physically resident data pages mapped by the allocator",
+        BaseLabels::None,
+        &[],
+        |_| cfg!(feature = "jemalloc"),
+    ),
+    gauge(
+        "compose_apps_exporter_allocator_mapped_bytes",
+        "Bytes in active extents mapped by the allocator",
+        BaseLabels::None,
+        &[],
+        |_| cfg!(feature = "jemalloc"),
+    ),
+    gauge(
+        "compose_apps_exporter_allocator_metadata_bytes",
+        "Bytes dedicated to allocator metadata",
+        BaseLabels::None,
+        &[],
+        |_| cfg!(feature = "jemalloc"),
+    ),
+    gauge(
+        "compose_apps_exporter_allocator_retained_bytes",
+        "Bytes in virtual memory mappings retained by the allocator",
+        BaseLabels::None,
+        &[],
+        |_| cfg!(feature = "jemalloc"),
+    ),
+    gauge(
+        "compose_apps_exporter_allocator_fragmentation_ratio",
+        "Share of the active bytes that isn't allocated",
+        BaseLabels::None,
+        &[],
+        |_| cfg!(feature = "jemalloc"),
+    ),
+];
+
+/// Help text of the availability metric of the window with the given suffix
+fn available_ratio_help(suffix: &str) -> String {
+    format!(
+        "Fraction of the last {} the docker compose service was running and healthy (as far as observed)",
+        suffix
+    )
+}
+
+/// Help text of the uptime history metric of the window with the given name
+fn uptime_ratio_help(window_name: &str) -> String {
+    format!(
+        "Fraction of the last {} the docker compose service was running and healthy, from the uptime history",
+        window_name
+    )
+}
+
+/// Get the help text and type of the metric with the given (legacy) name, if
+/// the exporter emits it
+pub fn describe(name: &str) -> Option<(String, MetricType)> {
+    if let Some(suffix) = name.strip_prefix("compose_service_available_ratio_") {
+        return Some((available_ratio_help(suffix), MetricType::Gauge));
+    }
+    if let Some(window_name) = name.strip_prefix("compose_service_uptime_ratio_") {
+        return Some((uptime_ratio_help(window_name), MetricType::Gauge));
+    }
+    DEFINITIONS
+        .iter()
+        .find(|definition| definition.name == name)
+        .map(|definition| (definition.help.to_string(), definition.metric_type))
+}
+
+#[derive(Serialize, Debug)]
+pub struct MetricSchema {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub metric_type: MetricType,
+    pub help: String,
+    pub labels: Vec<String>,
+}
+
+/// Get the schema of every metric the exporter can emit with the given
+/// configuration, with the labels each metric can have in order
+pub fn metrics_schema(config: &ParsedConfig) -> Vec<MetricSchema> {
+    let grouping_label_names = config.app_labels.label_names();
+    let labels_of = |base_labels: BaseLabels, extra_labels: &[&str]| {
+        let mut labels: Vec<String> = match base_labels {
+            BaseLabels::None => vec![],
+            BaseLabels::App | BaseLabels::Service => vec!["compose_name".to_string()],
+            BaseLabels::ConfigPath => vec!["config_path".to_string()],
+        };
+        if !matches!(base_labels, BaseLabels::None) {
+            labels.extend(grouping_label_names.iter().cloned());
+        }
+        if matches!(base_labels, BaseLabels::Service) {
+            labels.push("service_name".to_string());
+        }
+        labels.extend(extra_labels.iter().map(|label| label.to_string()));
+        for (name, _) in &config.extra_labels {
+            if !labels.contains(name) {
+                labels.push(name.clone());
+            }
+        }
+        labels
+    };
+    let mut schema: Vec<MetricSchema> = DEFINITIONS
+        .iter()
+        .filter(|definition| (definition.is_enabled)(config))
+        .map(|definition| MetricSchema {
+            name: definition.name.to_string(),
+            metric_type: definition.metric_type,
+            help: definition.help.to_string(),
+            labels: labels_of(definition.base_labels, definition.extra_labels),
+        })
+        .collect();
+    // The configured environment variables are labels of the service info
+    if let Some(service_info) = schema
+        .iter_mut()
+        .find(|metric| metric.name == "compose_service_info")
+    {
+        service_info.labels.extend(
+            config
+                .service_info_environment
+                .iter()
+                .map(|variable| service_info::environment_label_name(variable)),
+        );
+    }
+    // One metric per configured availability window
+    schema.extend(config.availability_windows.iter().map(|window| {
+        let suffix = availability::window_suffix(*window);
+        MetricSchema {
+            name: format!("compose_service_available_ratio_{}", suffix),
+            metric_type: MetricType::Gauge,
+            help: available_ratio_help(&suffix),
+            labels: labels_of(BaseLabels::Service, &[]),
+        }
+    }));
+    if config.uptime_history_file.is_some() && config.uptime_history_metrics {
+        schema.extend(
+            uptime_history::UPTIME_WINDOWS
+                .iter()
+                .map(|(window_name, _)| MetricSchema {
+                    name: format!("compose_service_uptime_ratio_{}", window_name),
+                    metric_type: MetricType::Gauge,
+                    help: uptime_ratio_help(window_name),
+                    labels: labels_of(BaseLabels::Service, &[]),
+                }),
+        );
+    }
+    metric_naming::apply_prefix_to_schema(
+        metric_naming::apply_to_schema(schema, config.metric_naming),
+        &config.metric_prefix,
+    )
+}
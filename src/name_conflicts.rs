@@ -0,0 +1,102 @@
+//! Conflicts between the names of different apps: the same `container_name`
+//! declared by several apps, or several apps with the same compose (project)
+//! name, which docker compose then treats as a single project. Both cause
+//! confusing "works on one stack, breaks the other" failures.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::registry::Samples;
+
+/// Names of an app whose config resolved
+pub struct AppNames<'a> {
+    pub config_path: &'a Path,
+    pub compose_name: &'a str,
+    pub container_names: &'a [String],
+}
+
+/// Outcome of checking apps for name conflicts
+pub struct NameConflicts {
+    pub metrics: Samples,
+    /// Human-readable description of each conflict
+    pub warnings: Vec<String>,
+}
+
+/// Group the config paths of the given apps by name, keeping the names shared
+/// by several apps
+fn conflicting<'a>(
+    names: impl Iterator<Item = (&'a str, &'a Path)>,
+) -> BTreeMap<&'a str, Vec<&'a Path>> {
+    let mut config_paths_by_name: BTreeMap<&str, Vec<&Path>> = BTreeMap::new();
+    for (name, config_path) in names {
+        let config_paths = config_paths_by_name.entry(name).or_default();
+        if !config_paths.contains(&config_path) {
+            config_paths.push(config_path);
+        }
+    }
+    config_paths_by_name.retain(|_, config_paths| config_paths.len() > 1);
+    config_paths_by_name
+}
+
+fn paths_to_string(config_paths: &[&Path]) -> String {
+    config_paths
+        .iter()
+        .map(|config_path| config_path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Check the given apps for container and compose names used by more than one
+/// of them
+pub fn check(apps: &[AppNames]) -> NameConflicts {
+    let compose_name_of = |config_path: &Path| {
+        apps.iter()
+            .find(|app| app.config_path == config_path)
+            .map_or("", |app| app.compose_name)
+    };
+    let container_name_conflicts = conflicting(apps.iter().flat_map(|app| {
+        app.container_names
+            .iter()
+            .map(|container_name| (container_name.as_str(), app.config_path))
+    }));
+    let compose_name_conflicts =
+        conflicting(apps.iter().map(|app| (app.compose_name, app.config_path)));
+    let mut warnings = vec![];
+    let mut metrics = Samples::default();
+    for (container_name, config_paths) in &container_name_conflicts {
+        warnings.push(format!(
+            "Container name {} is declared by several apps: {}",
+            container_name,
+            paths_to_string(config_paths)
+        ));
+        for config_path in config_paths {
+            metrics.add(
+                "compose_apps_container_name_conflict",
+                &[
+                    ("container_name", container_name),
+                    ("compose_name", compose_name_of(config_path)),
+                    ("config_path", &config_path.display().to_string()),
+                ],
+                1,
+            );
+        }
+    }
+    for (compose_name, config_paths) in &compose_name_conflicts {
+        warnings.push(format!(
+            "Compose name {} is used by several apps: {}",
+            compose_name,
+            paths_to_string(config_paths)
+        ));
+        for config_path in config_paths {
+            metrics.add(
+                "compose_apps_compose_name_conflict",
+                &[
+                    ("compose_name", compose_name),
+                    ("config_path", &config_path.display().to_string()),
+                ],
+                1,
+            );
+        }
+    }
+    NameConflicts { metrics, warnings }
+}
@@ -0,0 +1,376 @@
+//! OpenAPI document of the exporter's JSON API, served on
+//! `/api/v1/openapi.json` so clients and dashboards can be generated against
+//! it. Only the endpoints enabled by the configuration are described.
+
+use serde_json::{json, Map, Value};
+
+use crate::Exporter;
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn unauthorized_response() -> Value {
+    json!({ "description": "Missing or invalid bearer token" })
+}
+
+fn version_paths(paths: &mut Map<String, Value>) {
+    paths.insert(
+        "/version".to_string(),
+        json!({
+            "get": {
+                "summary": "Version and build information of the exporter",
+                "operationId": "getVersion",
+                "responses": {
+                    "200": json_response("Version information", json!({ "$ref": "#/components/schemas/VersionInfo" })),
+                },
+            },
+        }),
+    );
+}
+
+fn uptime_paths(paths: &mut Map<String, Value>, tenants_enabled: bool) {
+    let mut operation = json!({
+        "summary": "Uptime of every service over the last 24 hours, 7 days and 30 days, from the uptime history",
+        "operationId": "getUptime",
+        "responses": {
+            "200": json_response(
+                "Uptime of every service (of the tenant), sorted by compose and service name",
+                json!({ "type": "array", "items": { "$ref": "#/components/schemas/ServiceUptime" } }),
+            ),
+        },
+    });
+    if tenants_enabled {
+        operation["security"] = json!([{ "tenantToken": [] }]);
+        operation["responses"]["401"] = unauthorized_response();
+    }
+    paths.insert("/api/v1/uptime".to_string(), json!({ "get": operation }));
+}
+
+fn apps_paths(paths: &mut Map<String, Value>, tenants_enabled: bool) {
+    let mut operation = json!({
+        "summary": "State of the apps and their services, from the same collections as the metrics",
+        "operationId": "getApps",
+        "responses": {
+            "200": json_response(
+                "Apps (of the tenant), including those that failed to be collected, sorted by compose name, with their services sorted by name",
+                json!({ "type": "array", "items": { "$ref": "#/components/schemas/AppState" } }),
+            ),
+            "500": { "description": "The collection failed" },
+            "503": { "description": "A collection is already running, see the Retry-After header" },
+        },
+    });
+    if tenants_enabled {
+        operation["security"] = json!([{ "tenantToken": [] }]);
+        operation["responses"]["401"] = unauthorized_response();
+    }
+    paths.insert("/api/v1/apps".to_string(), json!({ "get": operation }));
+}
+
+fn sd_paths(paths: &mut Map<String, Value>, tenants_enabled: bool) {
+    let mut operation = json!({
+        "summary": "Apps as Prometheus HTTP service discovery targets, one target group per app",
+        "operationId": "getServiceDiscovery",
+        "responses": {
+            "200": json_response(
+                "Target groups of the apps (of the tenant)",
+                json!({ "type": "array", "items": { "$ref": "#/components/schemas/TargetGroup" } }),
+            ),
+            "500": { "description": "The discovery failed" },
+        },
+    });
+    if tenants_enabled {
+        operation["security"] = json!([{ "tenantToken": [] }]);
+        operation["responses"]["401"] = unauthorized_response();
+    }
+    paths.insert("/sd".to_string(), json!({ "get": operation }));
+}
+
+fn debug_paths(paths: &mut Map<String, Value>) {
+    paths.insert(
+        "/debug/state".to_string(),
+        json!({
+            "get": {
+                "summary": "Internal state of the exporter, including the outcome of the last collection",
+                "operationId": "getDebugState",
+                "security": [{ "debugToken": [] }],
+                "responses": {
+                    "200": json_response("Internal state", json!({ "$ref": "#/components/schemas/DebugState" })),
+                    "401": unauthorized_response(),
+                },
+            },
+        }),
+    );
+}
+
+fn maintenance_paths(paths: &mut Map<String, Value>) {
+    let compose_name = json!({
+        "name": "compose_name",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" },
+    });
+    let service_name = json!({
+        "name": "service_name",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" },
+    });
+    let set_operations = |parameters: Value, target: &str| {
+        json!({
+            "parameters": parameters,
+            "put": {
+                "summary": format!("Put {} in maintenance", target),
+                "security": [{ "maintenanceToken": [] }],
+                "responses": {
+                    "204": { "description": "In maintenance" },
+                    "401": unauthorized_response(),
+                },
+            },
+            "delete": {
+                "summary": format!("Take {} out of maintenance", target),
+                "security": [{ "maintenanceToken": [] }],
+                "responses": {
+                    "204": { "description": "Out of maintenance" },
+                    "401": unauthorized_response(),
+                },
+            },
+        })
+    };
+    paths.insert(
+        "/maintenance".to_string(),
+        json!({
+            "get": {
+                "summary": "Apps and services put in maintenance through the API",
+                "operationId": "getMaintenance",
+                "security": [{ "maintenanceToken": [] }],
+                "responses": {
+                    "200": json_response(
+                        "Maintenance targets",
+                        json!({ "type": "array", "items": { "$ref": "#/components/schemas/MaintenanceTarget" } }),
+                    ),
+                    "401": unauthorized_response(),
+                },
+            },
+        }),
+    );
+    paths.insert(
+        "/maintenance/{compose_name}".to_string(),
+        set_operations(json!([compose_name.clone()]), "all services of an app"),
+    );
+    paths.insert(
+        "/maintenance/{compose_name}/{service_name}".to_string(),
+        set_operations(json!([compose_name, service_name]), "a service of an app"),
+    );
+}
+
+fn schemas() -> Value {
+    let nullable_string = json!({ "type": ["string", "null"] });
+    let nullable_number = json!({ "type": ["number", "null"] });
+    let scrape_state = json!({
+        "type": ["object", "null"],
+        "properties": {
+            "timestamp_seconds": { "type": "number" },
+            "age_seconds": { "type": "number" },
+            "duration_seconds": { "type": "number" },
+            "error": nullable_string,
+            "error_code": {
+                "oneOf": [{ "$ref": "#/components/schemas/ErrorCode" }, { "type": "null" }],
+            },
+        },
+    });
+    json!({
+        "VersionInfo": {
+            "type": "object",
+            "required": ["version", "git_revision", "build_date", "features"],
+            "properties": {
+                "version": { "type": "string" },
+                "git_revision": { "type": "string" },
+                "build_date": { "type": "string", "format": "date-time" },
+                "features": { "type": "array", "items": { "type": "string" } },
+            },
+        },
+        "ServiceUptime": {
+            "type": "object",
+            "required": ["compose_name", "service_name", "available", "uptime"],
+            "properties": {
+                "compose_name": { "type": "string" },
+                "service_name": { "type": "string" },
+                "available": { "type": "boolean", "description": "Whether the service was running and healthy when last seen" },
+                "uptime": {
+                    "type": "object",
+                    "description": "Fraction of each window the service was available, by window",
+                    "properties": {
+                        "24h": { "type": "number" },
+                        "7d": { "type": "number" },
+                        "30d": { "type": "number" },
+                    },
+                },
+            },
+        },
+        "AppState": {
+            "type": "object",
+            "required": ["compose_name", "config_path", "config_error_class", "error", "services"],
+            "properties": {
+                "compose_name": { "type": "string" },
+                "config_path": { "type": "string" },
+                "config_error_class": nullable_string,
+                "error": {
+                    "oneOf": [{ "$ref": "#/components/schemas/AppError" }, { "type": "null" }],
+                },
+                "services": { "type": "array", "items": { "$ref": "#/components/schemas/ServiceState" } },
+            },
+        },
+        "AppError": {
+            "type": "object",
+            "required": ["code", "message"],
+            "properties": {
+                "code": { "$ref": "#/components/schemas/ErrorCode" },
+                "message": { "type": "string" },
+            },
+        },
+        "ServiceState": {
+            "type": "object",
+            "required": [
+                "service_name",
+                "containers_desired",
+                "containers_running",
+                "in_maintenance",
+                "in_downtime",
+                "available_ratios",
+                "uptime_ratios",
+            ],
+            "properties": {
+                "service_name": { "type": "string" },
+                "state": nullable_string,
+                "health": nullable_string,
+                "image": nullable_string,
+                "containers_desired": { "type": "integer" },
+                "containers_running": { "type": "integer" },
+                "in_maintenance": { "type": "boolean" },
+                "in_downtime": { "type": "boolean" },
+                "available_ratios": { "type": "object", "additionalProperties": { "type": "number" } },
+                "uptime_ratios": { "type": "object", "additionalProperties": { "type": "number" } },
+            },
+        },
+        "TargetGroup": {
+            "type": "object",
+            "required": ["targets", "labels"],
+            "properties": {
+                "targets": { "type": "array", "items": { "type": "string" } },
+                "labels": { "type": "object", "additionalProperties": { "type": "string" } },
+            },
+        },
+        "MaintenanceTarget": {
+            "type": "object",
+            "required": ["compose_name", "service_name"],
+            "properties": {
+                "compose_name": { "type": "string" },
+                "service_name": {
+                    "type": ["string", "null"],
+                    "description": "If null, all services of the app are in maintenance",
+                },
+            },
+        },
+        "ErrorCode": {
+            "type": "string",
+            "enum": [
+                "discovery_failed",
+                "docker_unavailable",
+                "docker_command_failed",
+                "docker_command_timed_out",
+                "docker_output_invalid",
+                "run_as_user_failed",
+                "timed_out",
+                "internal",
+            ],
+        },
+        "DebugState": {
+            "type": "object",
+            "properties": {
+                "version": { "type": "string" },
+                "uptime_seconds": { "type": "number" },
+                "docker": {
+                    "type": "object",
+                    "properties": {
+                        "host": nullable_string,
+                        "command_prefix": { "type": "array", "items": { "type": "string" } },
+                        "docker_version": { "type": "string" },
+                        "compose_version": { "type": "string" },
+                    },
+                },
+                "compose_configs_glob": { "type": "array", "items": { "type": "string" } },
+                "discovered_config_paths": { "type": "array", "items": { "type": "string" } },
+                "discovery_error": nullable_string,
+                "last_scrape": scrape_state,
+                "background_collection": {
+                    "type": "object",
+                    "properties": {
+                        "interval_seconds": nullable_number,
+                        "last_collection": scrape_state,
+                        "overdue": { "type": "boolean" },
+                    },
+                },
+                "in_flight_collections": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "key": { "type": "string" },
+                            "running_for_seconds": { "type": "number" },
+                        },
+                    },
+                },
+                "last_results": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "key": { "type": "string" },
+                            "age_seconds": { "type": "number" },
+                            "keep_for_seconds": nullable_number,
+                            "samples": { "type": "integer" },
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// Get the OpenAPI document of the JSON endpoints currently enabled
+pub fn document(exporter: &Exporter) -> Value {
+    let config = exporter.config();
+    let mut paths = Map::new();
+    version_paths(&mut paths);
+    if exporter.uptime_history.is_some() {
+        uptime_paths(&mut paths, config.tenants.are_enabled());
+    }
+    apps_paths(&mut paths, config.tenants.are_enabled());
+    sd_paths(&mut paths, config.tenants.are_enabled());
+    if config.debug_token.is_some() {
+        debug_paths(&mut paths);
+    }
+    if config.maintenance_token.is_some() {
+        maintenance_paths(&mut paths);
+    }
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "Compose Apps Exporter API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "debugToken": { "type": "http", "scheme": "bearer" },
+                "maintenanceToken": { "type": "http", "scheme": "bearer" },
+                "tenantToken": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
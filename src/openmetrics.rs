@@ -0,0 +1,150 @@
+//! The OpenMetrics text format, negotiated with the `Accept` header (see
+//! [`exposition::Format`]) as an alternative to the classic text format, which
+//! Prometheus 3 and some other tooling prefer
+//!
+//! The metric families are encoded as described in
+//! <https://prometheus.io/docs/specs/om/open_metrics_spec/>.
+
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+
+use crate::exposition;
+
+pub static OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Name and type of a family in the OpenMetrics format. Counter families are
+/// named without the `_total` suffix of their samples; counters whose samples
+/// lack it can't be expressed as counters, so they become `unknown`, like
+/// untyped metrics.
+fn family_name_and_type(name: &str, metric_type: MetricType) -> (&str, &'static str) {
+    match metric_type {
+        MetricType::COUNTER => match name.strip_suffix("_total") {
+            Some(family_name) => (family_name, "counter"),
+            None => (name, "unknown"),
+        },
+        MetricType::GAUGE => (name, "gauge"),
+        MetricType::HISTOGRAM => (name, "histogram"),
+        MetricType::SUMMARY => (name, "summary"),
+        MetricType::UNTYPED => (name, "unknown"),
+    }
+}
+
+/// Format a number the way OpenMetrics spells infinities and NaN
+fn format_number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Append a sample of the given metric, with the given label (e.g. the `le` of
+/// a histogram bucket) after its own ones
+fn push_sample(
+    encoded: &mut String,
+    name: &str,
+    metric: &Metric,
+    extra_label: Option<(&str, &str)>,
+    value: f64,
+) {
+    encoded.push_str(name);
+    let labels: Vec<String> = metric
+        .get_label()
+        .iter()
+        .map(|label| (label.name(), label.value()))
+        .chain(extra_label)
+        .map(|(name, value)| format!("{}=\"{}\"", name, exposition::escape_label_value(value)))
+        .collect();
+    if !labels.is_empty() {
+        encoded.push_str(&format!("{{{}}}", labels.join(",")));
+    }
+    encoded.push_str(&format!(" {}", format_number(value)));
+    // Timestamps are in seconds rather than milliseconds
+    let timestamp_ms = metric.timestamp_ms();
+    if timestamp_ms != 0 {
+        encoded.push_str(&format!(
+            " {}.{:03}",
+            timestamp_ms.div_euclid(1000),
+            timestamp_ms.rem_euclid(1000)
+        ));
+    }
+    encoded.push('\n');
+}
+
+/// Encode metric families in the OpenMetrics format
+pub fn encode(families: &[MetricFamily]) -> String {
+    let mut encoded = String::new();
+    for family in families {
+        let name = family.name();
+        let (family_name, family_type) = family_name_and_type(name, family.get_field_type());
+        if !family.help().is_empty() {
+            encoded.push_str(&format!(
+                "# HELP {} {}\n",
+                family_name,
+                exposition::escape_label_value(family.help())
+            ));
+        }
+        encoded.push_str(&format!("# TYPE {} {}\n", family_name, family_type));
+        for metric in family.get_metric() {
+            match family.get_field_type() {
+                MetricType::COUNTER => push_sample(
+                    &mut encoded,
+                    name,
+                    metric,
+                    None,
+                    metric.get_counter().get_value(),
+                ),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let mut has_inf_bucket = false;
+                    for bucket in histogram.get_bucket() {
+                        has_inf_bucket |= bucket.upper_bound() == f64::INFINITY;
+                        push_sample(
+                            &mut encoded,
+                            &format!("{}_bucket", name),
+                            metric,
+                            Some(("le", &format_number(bucket.upper_bound()))),
+                            bucket.cumulative_count() as f64,
+                        );
+                    }
+                    if !has_inf_bucket {
+                        push_sample(
+                            &mut encoded,
+                            &format!("{}_bucket", name),
+                            metric,
+                            Some(("le", "+Inf")),
+                            histogram.get_sample_count() as f64,
+                        );
+                    }
+                    push_sample(
+                        &mut encoded,
+                        &format!("{}_sum", name),
+                        metric,
+                        None,
+                        histogram.get_sample_sum(),
+                    );
+                    push_sample(
+                        &mut encoded,
+                        &format!("{}_count", name),
+                        metric,
+                        None,
+                        histogram.get_sample_count() as f64,
+                    );
+                }
+                // The exporter doesn't collect or parse summaries and untyped
+                // metrics, whose values are kept like those of gauges
+                MetricType::GAUGE | MetricType::SUMMARY | MetricType::UNTYPED => push_sample(
+                    &mut encoded,
+                    name,
+                    metric,
+                    None,
+                    metric.get_gauge().get_value(),
+                ),
+            }
+        }
+    }
+    encoded.push_str("# EOF\n");
+    encoded
+}
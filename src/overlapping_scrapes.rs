@@ -0,0 +1,426 @@
+//! Handling of scrapes arriving while a collection of the same metrics is
+//! already in flight, so slow collections don't pile more (and more) docker
+//! invocations onto the daemon, and of scrapes arriving more often than the
+//! minimum scrape interval
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::app_state::AppState;
+use crate::registry::Samples;
+use crate::scrape_error::ScrapeError;
+
+/// What a collection produced
+#[derive(Clone, Debug)]
+pub struct Collection {
+    pub metrics: Samples,
+    /// State of the collected apps, sorted by config path
+    pub apps: Vec<AppState>,
+}
+
+pub type CollectionResult = Result<Collection, ScrapeError>;
+
+/// What to do with a scrape when a collection of the same metrics is already
+/// in flight (and isn't fresh enough to share)
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlapPolicy {
+    /// Wait for the collection in flight to finish, then run a new one
+    Wait,
+    /// Share the result of the collection in flight
+    #[default]
+    Coalesce,
+    /// Respond with 503 Service Unavailable and a Retry-After header
+    Reject,
+}
+
+struct InFlight {
+    id: u64,
+    started_at: Instant,
+    result: watch::Receiver<Option<CollectionResult>>,
+}
+
+/// Result of the last successful collection of a metrics path
+struct LastResult {
+    finished_at: Instant,
+    /// How long the result may be served for
+    keep_for: Duration,
+    collection: Collection,
+}
+
+/// Forget the results that may no longer be served, so results of one-off
+/// scrapes (e.g. of selections of apps) don't pile up
+fn remove_expired(last_results: &mut HashMap<String, LastResult>) {
+    last_results.retain(|_, last_result| last_result.finished_at.elapsed() < last_result.keep_for);
+}
+
+/// The collections in flight, by the metrics path they were requested on
+#[derive(Default)]
+pub struct InFlightCollections {
+    collections: Arc<Mutex<HashMap<String, InFlight>>>,
+    next_id: AtomicU64,
+    last_results: Arc<Mutex<HashMap<String, LastResult>>>,
+}
+
+/// A collection in flight, for debugging
+#[derive(Serialize)]
+pub struct InFlightState {
+    key: String,
+    running_for_seconds: f64,
+}
+
+/// A result kept to serve scrapes arriving within the minimum interval, for
+/// debugging
+#[derive(Serialize)]
+pub struct LastResultState {
+    key: String,
+    age_seconds: f64,
+    /// `None` if kept until the next collection replaces it
+    keep_for_seconds: Option<f64>,
+    samples: usize,
+}
+
+pub enum Admission {
+    /// Run the collection, publishing its result with [`Leader::finish`]
+    Lead(Leader),
+    /// Share the result of the collection in flight
+    Join(Follower),
+    /// Wait for the collection in flight to finish, then ask again
+    Wait(Follower),
+    /// Reject the scrape, the collection in flight having started the given
+    /// time ago
+    Reject { running_for: Duration },
+    /// Serve the result of the last collection, which finished the given time
+    /// ago (less than the minimum scrape interval)
+    Cached {
+        collection: Collection,
+        age: Duration,
+    },
+}
+
+impl InFlightCollections {
+    /// Decide what to do with a scrape of the given metrics path. Collections
+    /// in flight for less than `fresh_for` are always shared, as their result
+    /// would be as fresh as that of a new one. The result of the last
+    /// successful collection is served if it finished less than
+    /// `min_interval` ago.
+    pub fn admit(
+        &self,
+        key: &str,
+        policy: OverlapPolicy,
+        fresh_for: Duration,
+        min_interval: Duration,
+    ) -> Admission {
+        let mut last_results = self
+            .last_results
+            .lock()
+            .expect("last collection results lock shouldn't be poisoned");
+        remove_expired(&mut last_results);
+        let last_result = last_results
+            .get(key)
+            .filter(|last_result| last_result.finished_at.elapsed() < min_interval)
+            .map(|last_result| Admission::Cached {
+                collection: last_result.collection.clone(),
+                age: last_result.finished_at.elapsed(),
+            });
+        drop(last_results);
+        if let Some(cached) = last_result {
+            return cached;
+        }
+        let mut collections = self
+            .collections
+            .lock()
+            .expect("in-flight collections lock shouldn't be poisoned");
+        if let Some(in_flight) = collections.get(key) {
+            let running_for = in_flight.started_at.elapsed();
+            let follower = Follower {
+                result: in_flight.result.clone(),
+            };
+            return match policy {
+                _ if running_for < fresh_for => Admission::Join(follower),
+                OverlapPolicy::Coalesce => Admission::Join(follower),
+                OverlapPolicy::Wait => Admission::Wait(follower),
+                OverlapPolicy::Reject => Admission::Reject { running_for },
+            };
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = watch::channel(None);
+        collections.insert(
+            key.to_string(),
+            InFlight {
+                id,
+                started_at: Instant::now(),
+                result: receiver,
+            },
+        );
+        Admission::Lead(Leader {
+            collections: self.collections.clone(),
+            last_results: self.last_results.clone(),
+            key: key.to_string(),
+            id,
+            sender,
+        })
+    }
+
+    /// The collections in flight, sorted by key
+    pub fn in_flight(&self) -> Vec<InFlightState> {
+        let mut in_flight: Vec<_> = self
+            .collections
+            .lock()
+            .expect("in-flight collections lock shouldn't be poisoned")
+            .iter()
+            .map(|(key, in_flight)| InFlightState {
+                key: key.clone(),
+                running_for_seconds: in_flight.started_at.elapsed().as_secs_f64(),
+            })
+            .collect();
+        in_flight.sort_by(|a, b| a.key.cmp(&b.key));
+        in_flight
+    }
+
+    /// The results kept to be served, sorted by key
+    pub fn last_results(&self) -> Vec<LastResultState> {
+        let mut last_results = self
+            .last_results
+            .lock()
+            .expect("last collection results lock shouldn't be poisoned");
+        remove_expired(&mut last_results);
+        let mut last_results: Vec<_> = last_results
+            .iter()
+            .map(|(key, last_result)| LastResultState {
+                key: key.clone(),
+                age_seconds: last_result.finished_at.elapsed().as_secs_f64(),
+                keep_for_seconds: Some(last_result.keep_for)
+                    .filter(|keep_for| *keep_for != Duration::MAX)
+                    .map(|keep_for| keep_for.as_secs_f64()),
+                samples: last_result.collection.metrics.sample_count(),
+            })
+            .collect();
+        last_results.sort_by(|a, b| a.key.cmp(&b.key));
+        last_results
+    }
+}
+
+/// Runs a collection on behalf of all scrapes of its metrics path. Dropping it
+/// without finishing (e.g. because its client disconnected) makes the
+/// followers ask for admission again.
+pub struct Leader {
+    collections: Arc<Mutex<HashMap<String, InFlight>>>,
+    last_results: Arc<Mutex<HashMap<String, LastResult>>>,
+    key: String,
+    id: u64,
+    sender: watch::Sender<Option<CollectionResult>>,
+}
+
+impl Leader {
+    /// Publish the result to the followers, keeping it (if successful) to be
+    /// served for `keep_for` to scrapes admitted with a minimum interval
+    pub fn finish(self, result: &CollectionResult, keep_for: Duration) {
+        let mut last_results = self
+            .last_results
+            .lock()
+            .expect("last collection results lock shouldn't be poisoned");
+        remove_expired(&mut last_results);
+        if let (Ok(collection), false) = (result, keep_for.is_zero()) {
+            last_results.insert(
+                self.key.clone(),
+                LastResult {
+                    finished_at: Instant::now(),
+                    keep_for,
+                    collection: collection.clone(),
+                },
+            );
+        }
+        drop(last_results);
+        self.sender.send_replace(Some(result.clone()));
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        let mut collections = self
+            .collections
+            .lock()
+            .expect("in-flight collections lock shouldn't be poisoned");
+        if collections.get(&self.key).map(|in_flight| in_flight.id) == Some(self.id) {
+            collections.remove(&self.key);
+        }
+    }
+}
+
+pub struct Follower {
+    result: watch::Receiver<Option<CollectionResult>>,
+}
+
+impl Follower {
+    /// Wait for the collection to finish, returning `None` if it was abandoned
+    pub async fn result(mut self) -> Option<CollectionResult> {
+        loop {
+            if let Some(result) = &*self.result.borrow_and_update() {
+                return Some(result.clone());
+            }
+            if self.result.changed().await.is_err() {
+                return self.result.borrow().clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrape_error::ErrorCode;
+
+    static HOUR: Duration = Duration::from_secs(3600);
+
+    fn collection() -> Collection {
+        let mut metrics = Samples::default();
+        metrics.add("up", &[], 1);
+        Collection {
+            metrics,
+            apps: vec![],
+        }
+    }
+
+    fn lead(collections: &InFlightCollections, key: &str) -> Leader {
+        match collections.admit(key, OverlapPolicy::Coalesce, Duration::ZERO, Duration::ZERO) {
+            Admission::Lead(leader) => leader,
+            _ => panic!("scrape should lead a collection"),
+        }
+    }
+
+    #[test]
+    fn applies_overlap_policy() {
+        let collections = InFlightCollections::default();
+        let _leader = lead(&collections, "/metrics");
+        let admit =
+            |policy, fresh_for| collections.admit("/metrics", policy, fresh_for, Duration::ZERO);
+        assert!(matches!(
+            admit(OverlapPolicy::Coalesce, Duration::ZERO),
+            Admission::Join(_)
+        ));
+        assert!(matches!(
+            admit(OverlapPolicy::Wait, Duration::ZERO),
+            Admission::Wait(_)
+        ));
+        assert!(matches!(
+            admit(OverlapPolicy::Reject, Duration::ZERO),
+            Admission::Reject { .. }
+        ));
+        // Collections that just started are shared regardless of the policy
+        assert!(matches!(
+            admit(OverlapPolicy::Reject, HOUR),
+            Admission::Join(_)
+        ));
+        // Other metrics paths are collected separately
+        assert!(matches!(
+            collections.admit(
+                "/metrics/web",
+                OverlapPolicy::Reject,
+                Duration::ZERO,
+                Duration::ZERO
+            ),
+            Admission::Lead(_)
+        ));
+        assert_eq!(collections.in_flight().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn shares_results_with_followers() {
+        let collections = InFlightCollections::default();
+        let leader = lead(&collections, "/metrics");
+        let Admission::Join(follower) = collections.admit(
+            "/metrics",
+            OverlapPolicy::Coalesce,
+            Duration::ZERO,
+            Duration::ZERO,
+        ) else {
+            panic!("scrape should join the collection in flight");
+        };
+        leader.finish(&Ok(collection()), Duration::ZERO);
+        assert_eq!(
+            follower
+                .result()
+                .await
+                .unwrap()
+                .unwrap()
+                .metrics
+                .sample_count(),
+            1
+        );
+        // The next scrape leads a new collection
+        assert!(collections.in_flight().is_empty());
+        lead(&collections, "/metrics");
+    }
+
+    #[tokio::test]
+    async fn releases_followers_of_abandoned_collections() {
+        let collections = InFlightCollections::default();
+        let leader = lead(&collections, "/metrics");
+        let Admission::Wait(follower) = collections.admit(
+            "/metrics",
+            OverlapPolicy::Wait,
+            Duration::ZERO,
+            Duration::ZERO,
+        ) else {
+            panic!("scrape should wait for the collection in flight");
+        };
+        drop(leader);
+        assert!(follower.result().await.is_none());
+        lead(&collections, "/metrics");
+    }
+
+    #[tokio::test]
+    async fn shares_errors() {
+        let collections = InFlightCollections::default();
+        let leader = lead(&collections, "/metrics");
+        let Admission::Join(follower) = collections.admit(
+            "/metrics",
+            OverlapPolicy::Coalesce,
+            Duration::ZERO,
+            Duration::ZERO,
+        ) else {
+            panic!("scrape should join the collection in flight");
+        };
+        leader.finish(
+            &Err(ScrapeError::new(ErrorCode::TimedOut, "timed out")),
+            HOUR,
+        );
+        let result = follower.result().await.unwrap();
+        assert_eq!(result.unwrap_err().code, ErrorCode::TimedOut);
+    }
+
+    #[test]
+    fn serves_results_within_min_interval() {
+        let collections = InFlightCollections::default();
+        lead(&collections, "/metrics").finish(&Ok(collection()), HOUR);
+        let Admission::Cached { collection, .. } =
+            collections.admit("/metrics", OverlapPolicy::Coalesce, Duration::ZERO, HOUR)
+        else {
+            panic!("scrape should be served the last result");
+        };
+        assert_eq!(collection.metrics.sample_count(), 1);
+        assert_eq!(collections.last_results().len(), 1);
+        // Scrapes without a minimum interval collect again
+        lead(&collections, "/metrics");
+    }
+
+    #[test]
+    fn keeps_results_only_while_they_may_be_served() {
+        let collections = InFlightCollections::default();
+        lead(&collections, "/metrics").finish(&Ok(collection()), Duration::ZERO);
+        lead(&collections, "/metrics/web").finish(
+            &Err(ScrapeError::new(ErrorCode::TimedOut, "timed out")),
+            HOUR,
+        );
+        lead(&collections, "/metrics/db").finish(&Ok(collection()), Duration::from_nanos(1));
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(collections.last_results().is_empty());
+        lead(&collections, "/metrics/db");
+    }
+}
@@ -0,0 +1,81 @@
+//! Platform (OS and architecture) of the images services run, to spot
+//! containers that are emulated (e.g. with QEMU) because their image doesn't
+//! match the architecture of the docker engine, a frequent cause of poor
+//! performance on ARM hosts
+
+use std::fmt::Display;
+use tokio::sync::Mutex;
+
+use crate::exec_docker_cmd;
+use crate::scrape_error::{ErrorCode, ScrapeError};
+use crate::DockerCli;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Platform {
+    /// e.g. 'linux'
+    pub os: String,
+    /// e.g. 'amd64' or 'arm64'
+    pub architecture: String,
+    /// e.g. 'v7' for 'arm'
+    pub variant: Option<String>,
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
+impl Platform {
+    /// Whether containers of this (image) platform run emulated on an engine
+    /// with the given platform. Variants are ignored, as engines usually run
+    /// other variants of their architecture natively.
+    pub fn is_emulated_on(&self, engine_platform: &Platform) -> bool {
+        self.os != engine_platform.os || self.architecture != engine_platform.architecture
+    }
+}
+
+/// Platform of the docker engine, read once it's first needed
+#[derive(Default)]
+pub struct EnginePlatform(Mutex<Option<Platform>>);
+
+impl EnginePlatform {
+    /// Get the platform of the docker engine, reading it if it hasn't been
+    /// yet (or reading it failed before)
+    pub async fn get(&self, docker: &DockerCli) -> Result<Platform, Box<dyn std::error::Error>> {
+        let mut platform = self.0.lock().await;
+        if let Some(platform) = platform.as_ref() {
+            return Ok(platform.clone());
+        }
+        let read_platform = read_engine_platform(docker).await?;
+        *platform = Some(read_platform.clone());
+        Ok(read_platform)
+    }
+}
+
+async fn read_engine_platform(docker: &DockerCli) -> Result<Platform, Box<dyn std::error::Error>> {
+    let mut command = docker.command();
+    command.args(["version", "--format", "{{.Server.Os}}/{{.Server.Arch}}"]);
+    let output = exec_docker_cmd(command, docker.command_timeout)
+        .await
+        .map_err(|err| {
+            ScrapeError::context(err, "Failed to read the platform of the docker engine")
+        })?;
+    // e.g. 'linux/arm64'
+    let output = String::from_utf8_lossy(&output);
+    let (os, architecture) = output.trim().split_once('/').ok_or_else(|| {
+        ScrapeError::new(
+            ErrorCode::DockerOutputInvalid,
+            format!("Unexpected `docker version` output: {:?}", output.trim()),
+        )
+    })?;
+    Ok(Platform {
+        os: os.to_string(),
+        architecture: architecture.to_string(),
+        variant: None,
+    })
+}
@@ -0,0 +1,150 @@
+//! The Prometheus protobuf exposition format (length-delimited
+//! `io.prometheus.client.MetricFamily` messages), negotiated with the `Accept`
+//! header (see [`crate::exposition::Format`]) as an alternative to the text format, which is cheaper to parse for
+//! large numbers of services
+//!
+//! The metric families are converted to the messages of
+//! <https://github.com/prometheus/client_model/blob/master/io/prometheus/client/metrics.proto>,
+//! the `prometheus` crate only encoding them with its `protobuf` feature.
+
+use prometheus::proto;
+use prost::Message;
+
+pub static PROTOBUF_CONTENT_TYPE: &str =
+    "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+enum MetricType {
+    Counter = 0,
+    Gauge = 1,
+    Summary = 2,
+    Untyped = 3,
+    Histogram = 4,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct LabelPair {
+    #[prost(string, optional, tag = "1")]
+    name: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    value: Option<String>,
+}
+
+/// Value of a gauge, counter or untyped metric, which are encoded the same
+#[derive(Clone, PartialEq, Message)]
+struct Value {
+    #[prost(double, optional, tag = "1")]
+    value: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Bucket {
+    #[prost(uint64, optional, tag = "1")]
+    cumulative_count: Option<u64>,
+    #[prost(double, optional, tag = "2")]
+    upper_bound: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Histogram {
+    #[prost(uint64, optional, tag = "1")]
+    sample_count: Option<u64>,
+    #[prost(double, optional, tag = "2")]
+    sample_sum: Option<f64>,
+    #[prost(message, repeated, tag = "3")]
+    bucket: Vec<Bucket>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Metric {
+    #[prost(message, repeated, tag = "1")]
+    label: Vec<LabelPair>,
+    #[prost(message, optional, tag = "2")]
+    gauge: Option<Value>,
+    #[prost(message, optional, tag = "3")]
+    counter: Option<Value>,
+    #[prost(message, optional, tag = "5")]
+    untyped: Option<Value>,
+    #[prost(int64, optional, tag = "6")]
+    timestamp_ms: Option<i64>,
+    #[prost(message, optional, tag = "7")]
+    histogram: Option<Histogram>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct MetricFamily {
+    #[prost(string, optional, tag = "1")]
+    name: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    help: Option<String>,
+    #[prost(enumeration = "MetricType", optional, tag = "3")]
+    r#type: Option<i32>,
+    #[prost(message, repeated, tag = "4")]
+    metric: Vec<Metric>,
+}
+
+/// Convert a metric of a family of the given type to its protobuf message
+fn to_metric(metric: &proto::Metric, metric_type: proto::MetricType) -> Metric {
+    let value = |value| Some(Value { value: Some(value) });
+    let mut encoded = Metric {
+        label: metric
+            .get_label()
+            .iter()
+            .map(|label| LabelPair {
+                name: Some(label.name().to_string()),
+                value: Some(label.value().to_string()),
+            })
+            .collect(),
+        timestamp_ms: Some(metric.timestamp_ms()).filter(|timestamp_ms| *timestamp_ms != 0),
+        ..Default::default()
+    };
+    match metric_type {
+        proto::MetricType::COUNTER => encoded.counter = value(metric.get_counter().get_value()),
+        proto::MetricType::HISTOGRAM => {
+            let histogram = metric.get_histogram();
+            encoded.histogram = Some(Histogram {
+                sample_count: Some(histogram.get_sample_count()),
+                sample_sum: Some(histogram.get_sample_sum()),
+                bucket: histogram
+                    .get_bucket()
+                    .iter()
+                    .map(|bucket| Bucket {
+                        cumulative_count: Some(bucket.cumulative_count()),
+                        upper_bound: Some(bucket.upper_bound()),
+                    })
+                    .collect(),
+            })
+        }
+        // The exporter doesn't collect or parse summaries and untyped metrics,
+        // whose values are kept like those of gauges
+        proto::MetricType::GAUGE | proto::MetricType::SUMMARY | proto::MetricType::UNTYPED => {
+            encoded.gauge = value(metric.get_gauge().get_value())
+        }
+    }
+    encoded
+}
+
+/// Encode metric families in the protobuf format
+pub fn encode(families: &[proto::MetricFamily]) -> Vec<u8> {
+    let mut encoded = vec![];
+    for family in families {
+        let metric_type = match family.get_field_type() {
+            proto::MetricType::COUNTER => MetricType::Counter,
+            proto::MetricType::HISTOGRAM => MetricType::Histogram,
+            _ => MetricType::Gauge,
+        };
+        MetricFamily {
+            name: Some(family.name().to_string()),
+            help: Some(family.help().to_string()).filter(|help| !help.is_empty()),
+            r#type: Some(metric_type as i32),
+            metric: family
+                .get_metric()
+                .iter()
+                .map(|metric| to_metric(metric, family.get_field_type()))
+                .collect(),
+        }
+        .encode_length_delimited(&mut encoded)
+        .expect("encoding into a Vec shouldn't fail");
+    }
+    encoded
+}
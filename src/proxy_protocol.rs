@@ -0,0 +1,203 @@
+//! Parsing of HAProxy PROXY protocol (v1 and v2) preambles, which proxies in TCP
+//! mode prepend to connections to pass on the original client address
+//!
+//! See <https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt>
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+static V1_PREFIX: &[u8] = b"PROXY ";
+/// Maximum length of a v1 header, including the CRLF
+static V1_MAX_LEN: usize = 107;
+static V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V2_COMMAND_LOCAL: u8 = 0x0;
+const V2_COMMAND_PROXY: u8 = 0x1;
+const V2_FAMILY_INET: u8 = 0x1;
+const V2_FAMILY_INET6: u8 = 0x2;
+
+fn invalid_header(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid PROXY protocol header: {}", reason),
+    )
+}
+
+/// Read the PROXY protocol header from the start of a connection, leaving the
+/// stream positioned right after it.
+///
+/// Returns the source address the proxy reported, or `None` if it didn't
+/// report one (e.g. for the proxy's own health checks).
+pub async fn read_header(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<SocketAddr>> {
+    let mut header = [0u8; 12];
+    stream.read_exact(&mut header).await?;
+    if header == V2_SIGNATURE {
+        read_v2_header(stream).await
+    } else if header.starts_with(V1_PREFIX) {
+        read_v1_header(stream, header.to_vec()).await
+    } else {
+        Err(invalid_header("missing PROXY protocol signature"))
+    }
+}
+
+/// Read the rest of a v1 header, e.g. `PROXY TCP4 192.0.2.1 192.0.2.2 56324
+/// 443\r\n`
+async fn read_v1_header(
+    stream: &mut (impl AsyncRead + Unpin),
+    mut header: Vec<u8>,
+) -> io::Result<Option<SocketAddr>> {
+    // Read byte-by-byte so as not to consume any of the actual request
+    while !header.ends_with(b"\r\n") {
+        if header.len() >= V1_MAX_LEN {
+            return Err(invalid_header("v1 header too long"));
+        }
+        header.push(stream.read_u8().await?);
+    }
+    let header = std::str::from_utf8(&header[..header.len() - 2])
+        .map_err(|_| invalid_header("v1 header is not valid ASCII"))?;
+    let mut fields = header.split(' ').skip(1);
+    match fields.next() {
+        Some("TCP4") | Some("TCP6") => {
+            let source_ip = fields
+                .next()
+                .and_then(|ip| ip.parse::<IpAddr>().ok())
+                .ok_or_else(|| invalid_header("invalid v1 source address"))?;
+            let source_port = fields
+                .nth(1)
+                .and_then(|port| port.parse::<u16>().ok())
+                .ok_or_else(|| invalid_header("invalid v1 source port"))?;
+            Ok(Some(SocketAddr::new(source_ip, source_port)))
+        }
+        Some("UNKNOWN") => Ok(None),
+        _ => Err(invalid_header("unknown v1 protocol")),
+    }
+}
+
+/// Read the rest of a v2 header (after the signature)
+async fn read_v2_header(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<SocketAddr>> {
+    let version_command = stream.read_u8().await?;
+    let family_protocol = stream.read_u8().await?;
+    let len = stream.read_u16().await? as usize;
+    let mut addresses = vec![0u8; len];
+    stream.read_exact(&mut addresses).await?;
+
+    if version_command >> 4 != 0x2 {
+        return Err(invalid_header("unsupported v2 version"));
+    }
+    match version_command & 0x0f {
+        V2_COMMAND_LOCAL => return Ok(None),
+        V2_COMMAND_PROXY => {}
+        _ => return Err(invalid_header("unknown v2 command")),
+    }
+    match family_protocol >> 4 {
+        V2_FAMILY_INET if len >= 12 => {
+            let source_ip = Ipv4Addr::from(
+                <[u8; 4]>::try_from(&addresses[0..4]).expect("slice length shouldn't differ"),
+            );
+            let source_port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(source_ip.into(), source_port)))
+        }
+        V2_FAMILY_INET6 if len >= 36 => {
+            let source_ip = Ipv6Addr::from(
+                <[u8; 16]>::try_from(&addresses[0..16]).expect("slice length shouldn't differ"),
+            );
+            let source_port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(source_ip.into(), source_port)))
+        }
+        // Unix sockets and unspecified families carry no usable address
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_header(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family << 4 | 0x1);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn reads_v1_headers() {
+        let mut stream: &[u8] = b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\nGET / HTTP/1.1\r\n";
+        let addr = read_header(&mut stream).await.expect("header should parse");
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(stream, b"GET / HTTP/1.1\r\n");
+
+        let mut stream: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n";
+        let addr = read_header(&mut stream).await.expect("header should parse");
+        assert_eq!(addr, Some("[2001:db8::1]:56324".parse().unwrap()));
+
+        let mut stream: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut stream).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_v1_headers() {
+        for header in [
+            &b"GET / HTTP/1.1\r\n"[..],
+            b"PROXY UDP4 192.0.2.1 192.0.2.2 56324 443\r\n",
+            b"PROXY TCP4 not-an-ip 192.0.2.2 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 99999 443\r\n",
+        ] {
+            let mut stream = header;
+            let err = read_header(&mut stream).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let long_header = [&b"PROXY TCP4 "[..], &[b'1'; V1_MAX_LEN]].concat();
+        let err = read_header(&mut &long_header[..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn reads_v2_headers() {
+        let mut addresses = vec![192, 0, 2, 1, 192, 0, 2, 2];
+        addresses.extend_from_slice(&56324u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+        let mut header = v2_header(V2_COMMAND_PROXY, V2_FAMILY_INET, &addresses);
+        header.extend_from_slice(b"GET /");
+        let mut stream = &header[..];
+        let addr = read_header(&mut stream).await.expect("header should parse");
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(stream, b"GET /");
+
+        let source: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let mut addresses = source.octets().to_vec();
+        addresses.extend_from_slice(&[0; 16]);
+        addresses.extend_from_slice(&56324u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+        let header = v2_header(V2_COMMAND_PROXY, V2_FAMILY_INET6, &addresses);
+        let addr = read_header(&mut &header[..])
+            .await
+            .expect("header should parse");
+        assert_eq!(addr, Some("[2001:db8::1]:56324".parse().unwrap()));
+
+        let header = v2_header(V2_COMMAND_LOCAL, 0, &[]);
+        assert_eq!(read_header(&mut &header[..]).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_v2_headers() {
+        let mut header = v2_header(V2_COMMAND_PROXY, V2_FAMILY_INET, &[0; 12]);
+        header[12] = 0x11;
+        let err = read_header(&mut &header[..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let header = v2_header(0x2, V2_FAMILY_INET, &[0; 12]);
+        let err = read_header(&mut &header[..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Truncated address block
+        let header = v2_header(V2_COMMAND_PROXY, V2_FAMILY_INET, &[0; 12]);
+        let err = read_header(&mut &header[..header.len() - 1])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,135 @@
+//! Metrics collected as `prometheus` metric families and gathered through a
+//! registry, together with the metric vectors of the exporter's own telemetry,
+//! which gives them escaped label values, HELP and TYPE lines, and a consistent
+//! order (by name, then label values) in every exposition format
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{Counter, Gauge, LabelPair, Metric, MetricFamily, MetricType};
+use prometheus::Registry;
+
+use crate::{exposition, metrics_schema};
+
+/// Value of a sample, converted to the float all samples have
+pub trait SampleValue {
+    fn as_f64(&self) -> f64;
+}
+
+macro_rules! impl_sample_value {
+    ($($type:ty),*) => {
+        $(impl SampleValue for $type {
+            fn as_f64(&self) -> f64 {
+                *self as f64
+            }
+        })*
+    };
+}
+
+impl_sample_value!(u8, i32, u32, i64, u64, usize, f64);
+
+impl<T: SampleValue> SampleValue for &T {
+    fn as_f64(&self) -> f64 {
+        (*self).as_f64()
+    }
+}
+
+/// Samples of metrics whose label names differ from sample to sample (e.g. by
+/// the grouping labels of apps), so they can't be registered as metric vectors
+/// with fixed label names. Registered as a collector without descriptors, like
+/// the const metrics of other exporters.
+#[derive(Default, Clone, Debug)]
+pub struct Samples {
+    families: Vec<MetricFamily>,
+}
+
+impl Samples {
+    /// Add a sample of the metric with the given name, taking its help text
+    /// and type from the metrics schema (a gauge without help if it isn't in
+    /// there)
+    pub fn add(&mut self, name: &str, labels: &[(&str, &str)], value: impl SampleValue) {
+        let index = match self
+            .families
+            .iter()
+            .position(|family| family.name() == name)
+        {
+            Some(index) => index,
+            None => {
+                let mut family = MetricFamily::default();
+                family.set_name(name.to_string());
+                let (help, metric_type) = match metrics_schema::describe(name) {
+                    Some((help, metrics_schema::MetricType::Counter)) => {
+                        (help, MetricType::COUNTER)
+                    }
+                    Some((help, _)) => (help, MetricType::GAUGE),
+                    None => (String::new(), MetricType::GAUGE),
+                };
+                family.set_help(help);
+                family.set_field_type(metric_type);
+                self.families.push(family);
+                self.families.len() - 1
+            }
+        };
+        let family = &mut self.families[index];
+        let mut metric = Metric::from_label(
+            labels
+                .iter()
+                .map(|(name, value)| label_pair(name, value))
+                .collect(),
+        );
+        if family.get_field_type() == MetricType::COUNTER {
+            let mut counter = Counter::default();
+            counter.set_value(value.as_f64());
+            metric.set_counter(counter);
+        } else {
+            let mut gauge = Gauge::default();
+            gauge.set_value(value.as_f64());
+            metric.set_gauge(gauge);
+        }
+        family.mut_metric().push(metric);
+    }
+
+    /// Add the samples of another collection, e.g. of another app
+    pub fn extend(&mut self, samples: Samples) {
+        exposition::merge_families(&mut self.families, samples.families);
+    }
+
+    /// Number of samples
+    pub fn sample_count(&self) -> usize {
+        self.families
+            .iter()
+            .map(|family| family.get_metric().len())
+            .sum()
+    }
+
+    /// Gather the samples and the metrics of the given collectors (e.g. the
+    /// exporter's own metric vectors) through a registry
+    pub fn gather(self, collectors: Vec<Box<dyn Collector>>) -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        registry
+            .register(Box::new(self))
+            .expect("a collector without descriptors should register");
+        for collector in collectors {
+            registry
+                .register(collector)
+                .expect("the exporter's own collectors should register");
+        }
+        registry.gather()
+    }
+}
+
+impl Collector for Samples {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.families.clone()
+    }
+}
+
+/// A label of a sample, e.g. `compose_name="web"`
+pub fn label_pair(name: &str, value: &str) -> LabelPair {
+    let mut label = LabelPair::default();
+    label.set_name(name.to_string());
+    label.set_value(value.to_string());
+    label
+}
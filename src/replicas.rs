@@ -0,0 +1,140 @@
+//! Services scaled to multiple containers (with `deploy.replicas`, `scale` or
+//! `docker compose up --scale`): which containers belong to which service, and
+//! how many of them are running and healthy, and which containers are orphans
+
+use crate::compose_config::{ComposeConfig, ComposeService};
+use crate::registry::Samples;
+use crate::{add_service_metric, Container, POSSIBLE_STATES_STATE, STATE_NOT_UP};
+
+/// Number of containers the service is configured to have, which doesn't
+/// include overrides with `docker compose up --scale`
+pub fn desired_containers(service: &ComposeService) -> u32 {
+    service
+        .deploy
+        .as_ref()
+        .and_then(|deploy| deploy.replicas)
+        .or(service.scale)
+        .unwrap_or(1)
+}
+
+/// Number of the container with the given name within the given service, if
+/// it has the default name docker compose gives the service's containers,
+/// `<compose name>-<service name>-<number>` (e.g. 2 for 'wiki-web-2')
+fn default_name_number(
+    container_name: &str,
+    compose_name: &str,
+    service_name: &str,
+) -> Option<u32> {
+    container_name
+        .strip_prefix(compose_name)?
+        .strip_prefix('-')?
+        .strip_prefix(service_name)?
+        .strip_prefix('-')?
+        .parse()
+        .ok()
+}
+
+/// Whether the given container belongs to the given service of the app with
+/// the given compose name, by the service docker compose labeled it with, or
+/// else by the service's container name or the default one
+pub fn is_of_service(
+    container: &Container,
+    compose_name: &str,
+    service_name: &str,
+    service: &ComposeService,
+) -> bool {
+    if !container.service.is_empty() {
+        return container.service == service_name;
+    }
+    match &service.container_name {
+        Some(container_name) => *container_name == container.name,
+        None => default_name_number(&container.name, compose_name, service_name).is_some(),
+    }
+}
+
+/// Get the containers of the given service of the app with the given compose
+/// name, with their index (their number within the service, starting at 1),
+/// sorted by index
+pub fn service_containers<'a>(
+    containers: &'a [Container],
+    compose_name: &str,
+    service_name: &str,
+    service: &ComposeService,
+) -> Vec<(u32, &'a Container)> {
+    let mut service_containers: Vec<(Option<u32>, &Container)> = containers
+        .iter()
+        .filter(|container| is_of_service(container, compose_name, service_name, service))
+        .map(|container| {
+            let number = container
+                .number
+                .or_else(|| default_name_number(&container.name, compose_name, service_name));
+            (number, container)
+        })
+        .collect();
+    service_containers
+        .sort_by(|(a_number, a), (b_number, b)| a_number.cmp(b_number).then(a.name.cmp(&b.name)));
+    service_containers
+        .into_iter()
+        .enumerate()
+        // Containers whose number is unknown are numbered in order of name
+        .map(|(position, (number, container))| (number.unwrap_or(position as u32 + 1), container))
+        .collect()
+}
+
+/// Number of the given containers of the app that are running, but belong to
+/// none of the services in its current config (e.g. of a service that was
+/// removed from the compose file since the app was started)
+pub fn orphan_containers(containers: &[Container], compose_config: &ComposeConfig) -> usize {
+    containers
+        .iter()
+        .filter(|container| container.state == "running")
+        .filter(|container| {
+            !compose_config
+                .services
+                .iter()
+                .any(|(service_name, service)| {
+                    is_of_service(container, &compose_config.name, service_name, service)
+                })
+        })
+        .count()
+}
+
+/// Add the number of desired, running and healthy containers of a service, and
+/// the state of each of them, to the samples
+pub fn add_metrics(
+    samples: &mut Samples,
+    app_labels: &[(&str, &str)],
+    service_name: &str,
+    service: &ComposeService,
+    service_containers: &[(u32, &Container)],
+) {
+    let running = service_containers
+        .iter()
+        .filter(|(_, container)| container.state == "running");
+    let healthy = running
+        .clone()
+        .filter(|(_, container)| container.health.as_deref() == Some("healthy"));
+    for (metric, value) in [
+        ("containers_desired", desired_containers(service) as u64),
+        ("containers_running", running.count() as u64),
+        ("containers_healthy", healthy.count() as u64),
+    ] {
+        add_service_metric(samples, app_labels, service_name, metric, &[], value);
+    }
+    for (index, container) in service_containers {
+        let index = index.to_string();
+        for state in POSSIBLE_STATES_STATE
+            .iter()
+            .filter(|state| **state != STATE_NOT_UP)
+        {
+            add_service_metric(
+                samples,
+                app_labels,
+                service_name,
+                "container_state",
+                &[("container_index", &index), ("state", state)],
+                (container.state == *state) as u8,
+            );
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Running the docker commands of an app as the user owning it, e.g. to reach
+//! that user's rootless docker daemon
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Run the docker commands of the apps whose compose file matches `path` as
+/// `user`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RunAsUserRule {
+    /// Glob pattern for compose file paths
+    pub path: String,
+    /// User name or numeric UID
+    pub user: String,
+}
+
+#[derive(Clone, Default, PartialEq)]
+pub struct RunAsUser {
+    rules: Vec<(glob::Pattern, String)>,
+    /// Whether to run the docker commands of apps not matching any rule as the
+    /// owner of their compose file
+    config_owner: bool,
+}
+
+impl RunAsUser {
+    pub fn new(
+        rules: Vec<RunAsUserRule>,
+        config_owner: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if rules.is_empty() && !config_owner {
+            return Ok(RunAsUser::default());
+        }
+        if !cfg!(target_os = "linux") {
+            return Err(
+                "Running docker commands as another user is only supported on Linux".into(),
+            );
+        }
+        #[cfg(target_os = "linux")]
+        if !nix::unistd::geteuid().is_root() {
+            return Err("Running docker commands as another user requires running as root".into());
+        }
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                glob::Pattern::new(&rule.path)
+                    .map(|pattern| (pattern, rule.user))
+                    .map_err(|err| format!("Invalid run_as_user path {:?}: {}", rule.path, err))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(RunAsUser {
+            rules,
+            config_owner,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty() || self.config_owner
+    }
+
+    /// Make the given docker command run as the user owning the app with the
+    /// given compose file, if any. If no docker host was set explicitly, point
+    /// the command to that user's rootless docker daemon if it's running.
+    #[cfg(target_os = "linux")]
+    pub fn apply(
+        &self,
+        command: &mut std::process::Command,
+        config_path: &Path,
+        has_docker_host: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        use nix::unistd::{getgrouplist, setgid, setgroups, setuid, Uid, User};
+        use std::os::unix::{fs::MetadataExt, process::CommandExt};
+
+        let rule_user = self
+            .rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches_path(config_path))
+            .map(|(_, user)| user);
+        let user = match rule_user {
+            Some(user) => match user.parse::<u32>() {
+                Ok(uid) => User::from_uid(Uid::from_raw(uid))?,
+                Err(_) => User::from_name(user)?,
+            }
+            .ok_or_else(|| format!("Unknown user {:?}", user))?,
+            None if self.config_owner => {
+                let uid = std::fs::metadata(config_path)?.uid();
+                User::from_uid(Uid::from_raw(uid))?.ok_or_else(|| {
+                    format!("Unknown owner (UID {}) of {}", uid, config_path.display())
+                })?
+            }
+            None => return Ok(()),
+        };
+
+        let groups = getgrouplist(&std::ffi::CString::new(user.name.as_str())?, user.gid)?;
+        let (uid, gid) = (user.uid, user.gid);
+        let runtime_dir = Path::new("/run/user").join(uid.to_string());
+        let rootless_socket_path = runtime_dir.join("docker.sock");
+        if !has_docker_host && rootless_socket_path.exists() {
+            command
+                .arg("--host")
+                .arg(format!("unix://{}", rootless_socket_path.display()));
+        }
+        command
+            .env("HOME", &user.dir)
+            .env("XDG_RUNTIME_DIR", runtime_dir);
+        // SAFETY: only async-signal-safe syscalls are made between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                setgroups(&groups)?;
+                setgid(gid)?;
+                setuid(uid)?;
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(
+        &self,
+        _command: &mut std::process::Command,
+        _config_path: &Path,
+        _has_docker_host: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//! Errors of metrics collections, tagged with stable error codes so they can be
+//! matched on in e.g. alert annotations and log queries
+
+use serde::Serialize;
+use std::{error::Error, fmt, path::Path};
+
+/// Maximum length of the stderr excerpt included in verbose error responses
+const STDERR_EXCERPT_MAX_CHARS: usize = 1000;
+
+/// Stable codes of the ways a metrics collection can fail. The serialized
+/// names are part of the exporter's interface and must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The compose configs globs are invalid or couldn't be read
+    DiscoveryFailed,
+    /// A docker command couldn't be started (e.g. docker isn't installed)
+    DockerUnavailable,
+    /// A docker command exited with a non-zero status code
+    DockerCommandFailed,
+    /// A docker command was killed after running longer than the docker
+    /// command timeout
+    DockerCommandTimedOut,
+    /// The output of a docker command couldn't be parsed
+    DockerOutputInvalid,
+    /// A docker command couldn't be set up to run as the app's user
+    RunAsUserFailed,
+    /// The collection didn't finish before the scrape timeout
+    TimedOut,
+    /// Any other error
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::DiscoveryFailed => "discovery_failed",
+            ErrorCode::DockerUnavailable => "docker_unavailable",
+            ErrorCode::DockerCommandFailed => "docker_command_failed",
+            ErrorCode::DockerCommandTimedOut => "docker_command_timed_out",
+            ErrorCode::DockerOutputInvalid => "docker_output_invalid",
+            ErrorCode::RunAsUserFailed => "run_as_user_failed",
+            ErrorCode::TimedOut => "timed_out",
+            ErrorCode::Internal => "internal",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrapeError {
+    #[serde(rename = "error_code")]
+    pub code: ErrorCode,
+    /// Compose file of the app whose metrics couldn't be collected, if the
+    /// error is specific to one
+    pub config_path: Option<String>,
+    /// Sanitized excerpt of the stderr of the failing docker command
+    pub stderr: Option<String>,
+    #[serde(skip)]
+    message: String,
+}
+
+impl ScrapeError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ScrapeError {
+            code,
+            config_path: None,
+            stderr: None,
+            message: message.into(),
+        }
+    }
+
+    /// Recover the scrape error from a boxed error, classifying any other
+    /// error as internal
+    pub fn from_boxed(err: Box<dyn Error>) -> Self {
+        match err.downcast::<ScrapeError>() {
+            Ok(err) => *err,
+            Err(err) => ScrapeError::new(ErrorCode::Internal, err.to_string()),
+        }
+    }
+
+    /// Prefix the message of the given error with some context, keeping its
+    /// error code
+    pub fn context(err: Box<dyn Error>, context: impl fmt::Display) -> Self {
+        let err = ScrapeError::from_boxed(err);
+        ScrapeError {
+            message: format!("{}: {}", context, err.message),
+            ..err
+        }
+    }
+
+    pub fn with_config_path(mut self, config_path: &Path) -> Self {
+        self.config_path = Some(config_path.display().to_string());
+        self
+    }
+
+    pub fn with_stderr(mut self, stderr: &str) -> Self {
+        self.stderr = Some(sanitize_stderr(stderr));
+        self
+    }
+}
+
+impl fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ScrapeError {}
+
+/// Strip terminal escape sequences and other control characters from a
+/// command's stderr, and cut it down to its last (most relevant) part
+fn sanitize_stderr(stderr: &str) -> String {
+    let mut sanitized = String::with_capacity(stderr.len());
+    let mut chars = stderr.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip e.g. color codes ('ESC [ 3 1 m') up to their final byte
+            if chars.next() == Some('[') {
+                chars.by_ref().find(|c| ('@'..='~').contains(c));
+            }
+        } else if c == '\n' || c == '\t' || !c.is_control() {
+            sanitized.push(c);
+        }
+    }
+    let sanitized = sanitized.trim();
+    let nbro_chars = sanitized.chars().count();
+    if nbro_chars <= STDERR_EXCERPT_MAX_CHARS {
+        sanitized.to_string()
+    } else {
+        let excerpt: String = sanitized
+            .chars()
+            .skip(nbro_chars - STDERR_EXCERPT_MAX_CHARS)
+            .collect();
+        format!("...{}", excerpt)
+    }
+}
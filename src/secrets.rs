@@ -0,0 +1,112 @@
+//! Secrets and configs of apps, and whether the files they are read from
+//! exist, to catch a missing secret file before the stack fails to start (e.g.
+//! after a reboot)
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::add_service_metric;
+use crate::compose_config::{ComposeConfig, ComposeFileObject, ComposeServiceFileReference};
+use crate::registry::Samples;
+
+/// Where the value of a secret or config comes from
+fn source_type(object: &ComposeFileObject) -> &'static str {
+    if object.external {
+        "external"
+    } else if object.file.is_some() {
+        "file"
+    } else if object.environment.is_some() {
+        "environment"
+    } else if object.content.is_some() {
+        "content"
+    } else {
+        "unknown"
+    }
+}
+
+/// Add the secrets or configs (`kind`) a service references as info metrics
+fn add_service_references(
+    samples: &mut Samples,
+    app_labels: &[(&str, &str)],
+    service_name: &str,
+    kind: &str,
+    references: &[ComposeServiceFileReference],
+    objects: &HashMap<String, ComposeFileObject>,
+) {
+    for reference in references {
+        let source = reference.source();
+        add_service_metric(
+            samples,
+            app_labels,
+            service_name,
+            &format!("{}_info", kind),
+            &[
+                (kind, source),
+                ("source", objects.get(source).map_or("unknown", source_type)),
+            ],
+            1,
+        );
+    }
+}
+
+/// Add whether the files of the given secrets or configs (`kind`) exist to the
+/// samples. Files whose existence can't be determined (e.g. for lack of
+/// permissions) are left out.
+fn add_files_exist(
+    samples: &mut Samples,
+    app_labels: &[(&str, &str)],
+    kind: &str,
+    objects: &HashMap<String, ComposeFileObject>,
+) {
+    let mut files: Vec<_> = objects
+        .iter()
+        .filter(|(_, object)| !object.external)
+        .filter_map(|(name, object)| Some((name, object.file.as_deref()?)))
+        .collect();
+    files.sort();
+    for (name, file) in files {
+        let Ok(exists) = Path::new(file).try_exists() else {
+            continue;
+        };
+        let mut labels = app_labels.to_vec();
+        labels.push((kind, name));
+        labels.push(("path", file));
+        samples.add(
+            &format!("compose_app_{}_file_exists", kind),
+            &labels,
+            exists as u8,
+        );
+    }
+}
+
+/// Add the secrets and configs of the given app, and those its services
+/// reference, to the samples
+pub fn add_metrics(
+    samples: &mut Samples,
+    app_labels: &[(&str, &str)],
+    compose_config: &ComposeConfig,
+) {
+    let mut service_names: Vec<_> = compose_config.services.keys().collect();
+    service_names.sort();
+    for service_name in service_names {
+        let service = &compose_config.services[service_name];
+        add_service_references(
+            samples,
+            app_labels,
+            service_name,
+            "secret",
+            &service.secrets,
+            &compose_config.secrets,
+        );
+        add_service_references(
+            samples,
+            app_labels,
+            service_name,
+            "config",
+            &service.configs,
+            &compose_config.configs,
+        );
+    }
+    add_files_exist(samples, app_labels, "secret", &compose_config.secrets);
+    add_files_exist(samples, app_labels, "config", &compose_config.configs);
+}
@@ -0,0 +1,49 @@
+//! Security-relevant settings of services (privileges, capabilities, host
+//! namespaces, user and read-only root file system), so compose fleets can be
+//! audited for risky settings from Prometheus
+
+use crate::add_service_metric;
+use crate::compose_config::ComposeService;
+use crate::registry::Samples;
+
+/// Whether the given `user:` of a service (`user[:group]`) is root
+fn is_root_user(user: &str) -> bool {
+    let user = user.split(':').next().unwrap_or_default();
+    user == "root" || user == "0"
+}
+
+/// Add the security-relevant settings of a service to the samples
+pub fn add_metrics(
+    samples: &mut Samples,
+    app_labels: &[(&str, &str)],
+    service_name: &str,
+    service: &ComposeService,
+) {
+    let non_root_user = service
+        .user
+        .as_deref()
+        .is_some_and(|user| !is_root_user(user));
+    let settings = [
+        ("privileged", service.privileged),
+        (
+            "host_network",
+            service.network_mode.as_deref() == Some("host"),
+        ),
+        ("host_pid", service.pid.as_deref() == Some("host")),
+        ("non_root_user", non_root_user),
+        ("read_only", service.read_only),
+    ];
+    for (metric, value) in settings {
+        add_service_metric(samples, app_labels, service_name, metric, &[], value as u8);
+    }
+    for capability in &service.cap_add {
+        add_service_metric(
+            samples,
+            app_labels,
+            service_name,
+            "capability_added",
+            &[("capability", capability)],
+            1,
+        );
+    }
+}
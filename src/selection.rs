@@ -0,0 +1,102 @@
+//! Selection of the apps to collect per scrape with query parameters on the
+//! metrics path (`?app=<compose name>` and `?glob=<compose file glob>`), so
+//! Prometheus can scrape apps as separate targets with their own intervals,
+//! blackbox-exporter style
+
+use std::path::Path;
+
+/// Apps selected by the query of a scrape: those with any of the compose names,
+/// or with a compose file matching any of the globs
+pub struct Selection {
+    compose_names: Vec<String>,
+    globs: Vec<glob::Pattern>,
+}
+
+impl Selection {
+    /// Parse the selection from the query of a request, `None` if it doesn't
+    /// select any apps. Other query parameters are ignored.
+    pub fn from_query(query: Option<&str>) -> Result<Option<Self>, String> {
+        let mut selection = Selection {
+            compose_names: vec![],
+            globs: vec![],
+        };
+        for (key, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            match key.as_ref() {
+                "app" => selection.compose_names.push(value.into_owned()),
+                "glob" => selection.globs.push(
+                    glob::Pattern::new(&value)
+                        .map_err(|err| format!("Invalid glob {:?}: {}", value, err))?,
+                ),
+                _ => {}
+            }
+        }
+        if selection.compose_names.is_empty() && selection.globs.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(selection))
+    }
+
+    /// Canonical query of the selection (sorted, without duplicates or other
+    /// query parameters), to tell apart collections of different selections
+    pub fn key(&self) -> String {
+        let mut compose_names: Vec<&str> = self.compose_names.iter().map(String::as_str).collect();
+        compose_names.sort_unstable();
+        compose_names.dedup();
+        let mut globs: Vec<&str> = self.globs.iter().map(glob::Pattern::as_str).collect();
+        globs.sort_unstable();
+        globs.dedup();
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        query.extend_pairs(compose_names.into_iter().map(|name| ("app", name)));
+        query.extend_pairs(globs.into_iter().map(|glob| ("glob", glob)));
+        query.finish()
+    }
+
+    /// Whether the app with the given compose file and compose name is
+    /// selected
+    pub fn selects(&self, config_path: &Path, compose_name: &str) -> bool {
+        self.compose_names.iter().any(|name| name == compose_name)
+            || self.globs.iter().any(|glob| glob.matches_path(config_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection(query: &str) -> Selection {
+        Selection::from_query(Some(query))
+            .expect("query should parse")
+            .expect("query should select apps")
+    }
+
+    #[test]
+    fn parses_queries() {
+        assert!(Selection::from_query(None).unwrap().is_none());
+        assert!(Selection::from_query(Some("format=text"))
+            .unwrap()
+            .is_none());
+        assert!(Selection::from_query(Some("glob=%5B")).is_err());
+
+        let selection = selection("app=web&glob=%2Fsrv%2F*%2Fcompose.yml&format=text");
+        assert_eq!(selection.compose_names, ["web"]);
+        assert_eq!(selection.globs.len(), 1);
+    }
+
+    #[test]
+    fn keys_are_canonical() {
+        assert_eq!(
+            selection("glob=%2Fsrv%2F*&app=web&app=db&app=web&format=text").key(),
+            selection("app=db&glob=%2Fsrv%2F*&app=web").key()
+        );
+        assert_eq!(selection("app=db&app=web").key(), "app=db&app=web");
+        assert_ne!(selection("app=web").key(), selection("glob=web").key());
+    }
+
+    #[test]
+    fn selects_apps_by_name_or_glob() {
+        let selection = selection("app=web&glob=%2Fsrv%2Fapps%2F*%2Fcompose.yml");
+        assert!(selection.selects(Path::new("/opt/web/compose.yml"), "web"));
+        assert!(selection.selects(Path::new("/srv/apps/db/compose.yml"), "db"));
+        assert!(!selection.selects(Path::new("/srv/other/db/compose.yml"), "db"));
+    }
+}
@@ -0,0 +1,85 @@
+//! The `compose_service_info` metric, carrying details of services as labels
+//! (e.g. the deployed image, or the version from an allowlisted environment
+//! variable) to be joined against the other metrics
+
+use crate::compose_config::ComposeService;
+use crate::registry::Samples;
+use crate::{add_service_metric, Container};
+
+/// Labels of the metric besides the environment variables, see
+/// [`add_metric`]
+pub static LABELS: [&str; 4] = ["image", "image_id", "restart_policy", "compose_version"];
+
+/// Name of the label carrying the value of the given environment variable,
+/// e.g. 'env_git_sha' for 'GIT_SHA'
+pub fn environment_label_name(variable: &str) -> String {
+    format!("env_{}", variable.to_ascii_lowercase())
+}
+
+/// Whether the value of the given environment variable can be a label, i.e.
+/// whether its name only contains letters, digits and underscores
+pub fn is_valid_environment_label(variable: &str) -> bool {
+    !variable.is_empty()
+        && variable
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Restart policy of the service, e.g. 'unless-stopped' or 'on-failure:3', from
+/// `restart` or the condition of its deploy restart policy, defaulting to 'no'
+/// like docker
+fn restart_policy(service: &ComposeService) -> &str {
+    service
+        .restart
+        .as_deref()
+        .or_else(|| {
+            service
+                .deploy
+                .as_ref()?
+                .restart_policy
+                .as_ref()?
+                .condition
+                .as_deref()
+        })
+        .unwrap_or("no")
+}
+
+/// Add the info of a service to the samples, with its image, the ID of the
+/// image and the docker compose version of its container (if any), its restart
+/// policy, and the values of the given environment variables (those the
+/// service sets) as labels
+pub fn add_metric(
+    samples: &mut Samples,
+    app_labels: &[(&str, &str)],
+    service_name: &str,
+    service: &ComposeService,
+    container: Option<&Container>,
+    environment_labels: &[String],
+) {
+    let environment_labels: Vec<(String, &str)> = environment_labels
+        .iter()
+        .filter_map(|variable| {
+            let value = service.environment.get(variable)?.as_deref()?;
+            Some((environment_label_name(variable), value))
+        })
+        .collect();
+    let mut extra_labels: Vec<(&str, &str)> = LABELS
+        .into_iter()
+        .zip([
+            service.image.as_deref().unwrap_or_default(),
+            container
+                .and_then(|container| container.image_id.as_deref())
+                .unwrap_or_default(),
+            restart_policy(service),
+            container
+                .and_then(|container| container.compose_version.as_deref())
+                .unwrap_or_default(),
+        ])
+        .collect();
+    extra_labels.extend(
+        environment_labels
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value)),
+    );
+    add_service_metric(samples, app_labels, service_name, "info", &extra_labels, 1);
+}
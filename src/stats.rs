@@ -0,0 +1,265 @@
+//! Resource usage of containers (CPU, memory, network and block I/O), as
+//! reported by `docker stats` or the engine API
+
+use bollard::container::{MemoryStatsStats, Stats};
+use futures::future::try_join_all;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::scrape_error::{ErrorCode, ScrapeError};
+use crate::{exec_docker_app_cmd, DockerCli};
+
+/// Line of `docker stats --format '{{json .}}'`, with human-readable values
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct StatsLine {
+    name: String,
+    /// e.g. '12.50%', relative to one CPU
+    #[serde(rename = "CPUPerc")]
+    cpu_perc: String,
+    /// e.g. '12.5MiB / 1.944GiB'
+    mem_usage: String,
+    /// e.g. '1.2kB / 648B'
+    #[serde(rename = "NetIO")]
+    net_io: String,
+    /// e.g. '0B / 4.1kB'
+    #[serde(rename = "BlockIO")]
+    block_io: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ContainerStats {
+    /// CPU time used per second, e.g. 1.5 for one and a half CPUs
+    pub cpu_usage_ratio: f64,
+    pub memory_usage_bytes: f64,
+    /// The container's memory limit, or the host's memory if it has none
+    pub memory_limit_bytes: f64,
+    pub network_receive_bytes: f64,
+    pub network_transmit_bytes: f64,
+    pub block_read_bytes: f64,
+    pub block_write_bytes: f64,
+}
+
+/// Read the resource usage of the given (running) containers, from the engine
+/// API if enabled, or else using a single `docker stats` call in the context
+/// of the app with the given compose file. This takes a couple of seconds, as
+/// docker samples the CPU usage.
+pub async fn read_container_stats(
+    docker: &DockerCli,
+    config_path: &Path,
+    container_names: &[&str],
+) -> Result<HashMap<String, ContainerStats>, Box<dyn std::error::Error>> {
+    if container_names.is_empty() {
+        return Ok(HashMap::new());
+    }
+    if let Some(api) = &docker.api {
+        let stats = try_join_all(container_names.iter().map(|name| async move {
+            api.read_container_stats(name)
+                .await
+                .map_err(ScrapeError::from_boxed)
+        }))
+        .await
+        .map_err(|err| ScrapeError::context(err.into(), "Failed to read the containers' stats"))?;
+        return Ok(container_names
+            .iter()
+            .zip(stats)
+            .map(|(name, stats)| (name.to_string(), ContainerStats::from_engine_stats(&stats)))
+            .collect());
+    }
+    let mut stats_args = vec!["stats", "--no-stream", "--format", "{{json .}}"];
+    stats_args.extend(container_names);
+    let output = exec_docker_app_cmd(docker, config_path, &stats_args)
+        .await
+        .map_err(|err| ScrapeError::context(err, "Failed to execute `docker stats`"))?;
+    String::from_utf8_lossy(&output)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let invalid = |err: String| {
+                ScrapeError::new(
+                    ErrorCode::DockerOutputInvalid,
+                    format!("Failed to parse `docker stats` output: {}", err),
+                )
+            };
+            let line: StatsLine =
+                serde_json::from_str(line).map_err(|err| invalid(err.to_string()))?;
+            let stats = line.parse().map_err(invalid)?;
+            Ok((line.name, stats))
+        })
+        .collect()
+}
+
+impl ContainerStats {
+    /// Convert a sample of the engine API, computing the values the way
+    /// `docker stats` does
+    fn from_engine_stats(stats: &Stats) -> Self {
+        let cpu_delta = stats
+            .cpu_stats
+            .cpu_usage
+            .total_usage
+            .saturating_sub(stats.precpu_stats.cpu_usage.total_usage);
+        let system_delta = stats
+            .cpu_stats
+            .system_cpu_usage
+            .unwrap_or_default()
+            .saturating_sub(stats.precpu_stats.system_cpu_usage.unwrap_or_default());
+        let online_cpus = stats.cpu_stats.online_cpus.unwrap_or_else(|| {
+            stats
+                .cpu_stats
+                .cpu_usage
+                .percpu_usage
+                .as_ref()
+                .map_or(1, |percpu_usage| percpu_usage.len() as u64)
+        });
+        let cpu_usage_ratio = if system_delta == 0 {
+            0.0
+        } else {
+            cpu_delta as f64 / system_delta as f64 * online_cpus as f64
+        };
+        // Like docker, don't count the page cache that can be reclaimed
+        let inactive_file = match stats.memory_stats.stats {
+            Some(MemoryStatsStats::V1(memory_stats)) => memory_stats.total_inactive_file,
+            Some(MemoryStatsStats::V2(memory_stats)) => memory_stats.inactive_file,
+            None => 0,
+        };
+        let memory_usage = stats.memory_stats.usage.unwrap_or_default();
+        let (network_receive_bytes, network_transmit_bytes) = stats.networks.iter().flatten().fold(
+            (0, 0),
+            |(received, transmitted), (_, network)| {
+                (received + network.rx_bytes, transmitted + network.tx_bytes)
+            },
+        );
+        let block_bytes = |op: &str| {
+            stats
+                .blkio_stats
+                .io_service_bytes_recursive
+                .iter()
+                .flatten()
+                .filter(|entry| entry.op.eq_ignore_ascii_case(op))
+                .map(|entry| entry.value)
+                .sum::<u64>()
+        };
+        ContainerStats {
+            cpu_usage_ratio,
+            memory_usage_bytes: memory_usage.saturating_sub(inactive_file) as f64,
+            memory_limit_bytes: stats.memory_stats.limit.unwrap_or_default() as f64,
+            network_receive_bytes: network_receive_bytes as f64,
+            network_transmit_bytes: network_transmit_bytes as f64,
+            block_read_bytes: block_bytes("read") as f64,
+            block_write_bytes: block_bytes("write") as f64,
+        }
+    }
+}
+
+impl StatsLine {
+    fn parse(&self) -> Result<ContainerStats, String> {
+        let cpu_usage_ratio = self
+            .cpu_perc
+            .trim()
+            .strip_suffix('%')
+            .and_then(|percentage| percentage.parse::<f64>().ok())
+            .ok_or_else(|| format!("invalid CPU percentage {:?}", self.cpu_perc))?
+            / 100.0;
+        let (memory_usage_bytes, memory_limit_bytes) = parse_size_pair(&self.mem_usage)?;
+        let (network_receive_bytes, network_transmit_bytes) = parse_size_pair(&self.net_io)?;
+        let (block_read_bytes, block_write_bytes) = parse_size_pair(&self.block_io)?;
+        Ok(ContainerStats {
+            cpu_usage_ratio,
+            memory_usage_bytes,
+            memory_limit_bytes,
+            network_receive_bytes,
+            network_transmit_bytes,
+            block_read_bytes,
+            block_write_bytes,
+        })
+    }
+}
+
+/// Parse two sizes separated by a slash, e.g. '12.5MiB / 1.944GiB'
+fn parse_size_pair(pair: &str) -> Result<(f64, f64), String> {
+    let (first, second) = pair
+        .split_once('/')
+        .ok_or_else(|| format!("invalid size pair {:?}", pair))?;
+    Ok((parse_size(first)?, parse_size(second)?))
+}
+
+/// Parse a human-readable size as docker prints it, with decimal (e.g. 'kB')
+/// or binary (e.g. 'MiB') units
+fn parse_size(size: &str) -> Result<f64, String> {
+    let size = size.trim();
+    let unit_start = size
+        .find(|c: char| c.is_ascii_alphabetic())
+        .ok_or_else(|| format!("size {:?} has no unit", size))?;
+    let (number, unit) = size.split_at(unit_start);
+    let multiplier: f64 = match unit {
+        "B" => 1.0,
+        "kB" | "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        "PB" => 1e15,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0_f64.powi(2),
+        "GiB" => 1024.0_f64.powi(3),
+        "TiB" => 1024.0_f64.powi(4),
+        "PiB" => 1024.0_f64.powi(5),
+        _ => return Err(format!("size {:?} has an unknown unit", size)),
+    };
+    let number = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("invalid size {:?}", size))?;
+    Ok((number * multiplier).round())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_engine_stats_like_docker() {
+        let stats: Stats = serde_json::from_value(serde_json::json!({
+            "read": "2026-10-16T12:00:02Z",
+            "preread": "2026-10-16T12:00:01Z",
+            "num_procs": 0,
+            "pids_stats": {},
+            "networks": {
+                "eth0": {"rx_bytes": 1000, "tx_bytes": 200, "rx_dropped": 0, "rx_errors": 0,
+                         "rx_packets": 0, "tx_dropped": 0, "tx_errors": 0, "tx_packets": 0},
+                "eth1": {"rx_bytes": 24, "tx_bytes": 448, "rx_dropped": 0, "rx_errors": 0,
+                         "rx_packets": 0, "tx_dropped": 0, "tx_errors": 0, "tx_packets": 0}
+            },
+            "memory_stats": {"usage": 10_000_000, "limit": 2_000_000_000},
+            "blkio_stats": {"io_service_bytes_recursive": [
+                {"major": 8, "minor": 0, "op": "read", "value": 4096},
+                {"major": 8, "minor": 0, "op": "Write", "value": 512},
+                {"major": 8, "minor": 16, "op": "write", "value": 512}
+            ]},
+            "cpu_stats": {
+                "cpu_usage": {"total_usage": 3_000_000_000u64, "usage_in_kernelmode": 0,
+                              "usage_in_usermode": 0},
+                "system_cpu_usage": 20_000_000_000u64,
+                "online_cpus": 4,
+                "throttling_data": {"periods": 0, "throttled_periods": 0, "throttled_time": 0}
+            },
+            "precpu_stats": {
+                "cpu_usage": {"total_usage": 2_000_000_000u64, "usage_in_kernelmode": 0,
+                              "usage_in_usermode": 0},
+                "system_cpu_usage": 16_000_000_000u64,
+                "throttling_data": {"periods": 0, "throttled_periods": 0, "throttled_time": 0}
+            },
+            "storage_stats": {}
+        }))
+        .unwrap();
+        let stats = ContainerStats::from_engine_stats(&stats);
+        // A second of CPU time while the four CPUs had four, i.e. one CPU
+        assert_eq!(stats.cpu_usage_ratio, 1.0);
+        assert_eq!(stats.memory_usage_bytes, 10_000_000.0);
+        assert_eq!(stats.memory_limit_bytes, 2_000_000_000.0);
+        assert_eq!(stats.network_receive_bytes, 1024.0);
+        assert_eq!(stats.network_transmit_bytes, 648.0);
+        assert_eq!(stats.block_read_bytes, 4096.0);
+        assert_eq!(stats.block_write_bytes, 1024.0);
+    }
+}
@@ -0,0 +1,179 @@
+//! Lightweight HTTP and TCP checks run by the exporter itself, for services
+//! whose image ships no HEALTHCHECK
+
+use hyper::{header, Body, Client, Request, StatusCode, Uri};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::registry::Samples;
+
+fn default_timeout_seconds() -> f64 {
+    5.0
+}
+
+/// Check of a single compose service, as configured
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SyntheticCheckConfig {
+    pub compose_name: String,
+    pub service_name: String,
+    /// Name to tell multiple checks of the same service apart by, defaults to
+    /// the check type
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub probe: ProbeConfig,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: f64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProbeConfig {
+    /// Send a GET request to `url` (http:// only), expecting `expected_status`
+    /// or any 2xx status code if not set
+    Http {
+        url: String,
+        expected_status: Option<u16>,
+    },
+    /// Open a TCP connection to `address` (e.g. 'localhost:5432')
+    Tcp { address: String },
+}
+
+#[derive(Clone)]
+enum Probe {
+    Http {
+        uri: Uri,
+        expected_status: Option<StatusCode>,
+    },
+    Tcp {
+        address: String,
+    },
+}
+
+impl Probe {
+    fn kind(&self) -> &'static str {
+        match self {
+            Probe::Http { .. } => "http",
+            Probe::Tcp { .. } => "tcp",
+        }
+    }
+
+    /// Whether the probed service is healthy. Failing to reach it at all counts
+    /// as unhealthy.
+    async fn run(&self) -> bool {
+        match self {
+            Probe::Http {
+                uri,
+                expected_status,
+            } => {
+                let request = Request::get(uri.clone())
+                    .header(
+                        header::USER_AGENT,
+                        concat!("compose-apps-exporter/", env!("CARGO_PKG_VERSION")),
+                    )
+                    .body(Body::empty())
+                    .expect("synthetic check request should be valid");
+                match Client::new().request(request).await {
+                    Ok(response) => match expected_status {
+                        Some(expected_status) => response.status() == *expected_status,
+                        None => response.status().is_success(),
+                    },
+                    Err(_) => false,
+                }
+            }
+            Probe::Tcp { address } => tokio::net::TcpStream::connect(address).await.is_ok(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SyntheticCheck {
+    compose_name: String,
+    service_name: String,
+    name: String,
+    probe: Probe,
+    timeout: Duration,
+}
+
+impl SyntheticCheck {
+    /// Compose name of the app whose service is checked
+    pub fn compose_name(&self) -> &str {
+        &self.compose_name
+    }
+}
+
+impl TryFrom<SyntheticCheckConfig> for SyntheticCheck {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(config: SyntheticCheckConfig) -> Result<Self, Self::Error> {
+        let invalid = |reason: String| {
+            format!(
+                "Invalid synthetic check for service {:?} of {:?}: {}",
+                config.service_name, config.compose_name, reason
+            )
+        };
+        let probe = match &config.probe {
+            ProbeConfig::Http {
+                url,
+                expected_status,
+            } => {
+                let uri = Uri::try_from(url.as_str())
+                    .map_err(|err| invalid(format!("invalid URL {:?}: {}", url, err)))?;
+                if uri.scheme_str() != Some("http") {
+                    return Err(
+                        invalid(format!("unsupported URL {:?}, must be http://", url)).into(),
+                    );
+                }
+                let expected_status = expected_status
+                    .map(StatusCode::from_u16)
+                    .transpose()
+                    .map_err(|err| invalid(err.to_string()))?;
+                Probe::Http {
+                    uri,
+                    expected_status,
+                }
+            }
+            ProbeConfig::Tcp { address } => Probe::Tcp {
+                address: address.clone(),
+            },
+        };
+        let timeout = Duration::try_from_secs_f64(config.timeout_seconds)
+            .map_err(|err| invalid(format!("invalid timeout: {}", err)))?;
+        Ok(SyntheticCheck {
+            compose_name: config.compose_name,
+            service_name: config.service_name,
+            name: config.name.unwrap_or_else(|| probe.kind().to_string()),
+            probe,
+            timeout,
+        })
+    }
+}
+
+/// Run the given checks concurrently and add their outcomes to the given
+/// samples
+pub async fn add_metrics(samples: &mut Samples, checks: &[SyntheticCheck]) {
+    let handles: Vec<_> = checks
+        .iter()
+        .cloned()
+        .map(|check| {
+            tokio::spawn(async move {
+                let healthy = tokio::time::timeout(check.timeout, check.probe.run())
+                    .await
+                    .unwrap_or(false);
+                (check, healthy)
+            })
+        })
+        .collect();
+    for handle in handles {
+        let (check, healthy) = handle.await.expect("synthetic check shouldn't panic");
+        samples.add(
+            "compose_service_synthetic_health",
+            &[
+                ("compose_name", &check.compose_name),
+                ("service_name", &check.service_name),
+                ("check", &check.name),
+            ],
+            healthy as u8,
+        );
+    }
+}
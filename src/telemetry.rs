@@ -0,0 +1,123 @@
+//! Metrics about the exporter itself: the build it runs, how long collections
+//! take, and how long docker commands (and engine API requests) take and how
+//! often they fail, to monitor the exporter and tune scrape timeouts
+
+use prometheus::core::Collector;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use crate::metrics_schema;
+use crate::registry::Samples;
+
+/// Options of docker and docker compose taking a value, to tell them apart from
+/// (sub)commands
+static OPTIONS_WITH_VALUE: [&str; 7] = [
+    "--host",
+    "-H",
+    "-f",
+    "--file",
+    "--format",
+    "--tail",
+    "--project-name",
+];
+/// Commands whose first argument is a subcommand, e.g. `compose ps`
+static COMMANDS_WITH_SUBCOMMANDS: [&str; 6] = [
+    "compose",
+    "container",
+    "image",
+    "network",
+    "system",
+    "volume",
+];
+
+/// Help text of the metric with the given name, from the metrics schema
+fn help(name: &str) -> String {
+    metrics_schema::describe(name)
+        .map(|(help, _)| help)
+        .unwrap_or_default()
+}
+
+/// Duration of docker commands, by command kind (see [`command_kind`]), in the
+/// default buckets of Prometheus' client libraries
+static COMMAND_DURATIONS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    let name = "compose_apps_exporter_docker_command_duration_seconds";
+    HistogramVec::new(HistogramOpts::new(name, help(name)), &["command"])
+        .expect("the command duration histogram should be valid")
+});
+
+/// Failed docker commands, by command kind
+static COMMAND_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let name = "compose_apps_exporter_docker_command_errors_total";
+    IntCounterVec::new(Opts::new(name, help(name)), &["command"])
+        .expect("the command errors counter should be valid")
+});
+
+/// Kind of the docker command with the given program and arguments, e.g.
+/// `compose ps` or `image inspect`, without its options, arguments or the
+/// program it's run through (e.g. `sudo -n`)
+pub fn command_kind(program: &str, args: &[String]) -> String {
+    let args = match args.iter().position(|arg| arg == "docker") {
+        Some(index) if !program.ends_with("docker") => &args[index + 1..],
+        _ => args,
+    };
+    let mut words = vec![];
+    let mut flags = vec![];
+    let mut remaining = args.iter();
+    while let Some(arg) = remaining.next() {
+        if OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+            remaining.next();
+        } else if arg.starts_with('-') {
+            flags.push(arg.as_str());
+        } else {
+            words.push(arg.as_str());
+            if !COMMANDS_WITH_SUBCOMMANDS.contains(&arg.as_str()) || words.len() == 2 {
+                break;
+            }
+        }
+    }
+    if words.is_empty() {
+        // e.g. `docker --version`
+        return flags.join(" ");
+    }
+    words.join(" ")
+}
+
+/// Record a docker command (or engine API request) of the given kind
+pub fn record_command(kind: &str, duration: Duration, failed: bool) {
+    COMMAND_DURATIONS
+        .with_label_values(&[kind])
+        .observe(duration.as_secs_f64());
+    // Commands that never failed are reported with no errors
+    let errors = COMMAND_ERRORS.with_label_values(&[kind]);
+    if failed {
+        errors.inc();
+    }
+}
+
+/// The metric vectors of docker commands, to register in the registry the
+/// metrics are gathered through
+pub fn collectors() -> Vec<Box<dyn Collector>> {
+    vec![
+        Box::new(COMMAND_DURATIONS.clone()),
+        Box::new(COMMAND_ERRORS.clone()),
+    ]
+}
+
+/// Add the metrics of the build the exporter runs and of the last collection,
+/// given its duration
+pub fn add_metrics(samples: &mut Samples, last_collection_duration: Option<Duration>) {
+    samples.add(
+        "compose_apps_exporter_build_info",
+        &[
+            ("version", env!("CARGO_PKG_VERSION")),
+            ("commit", env!("BUILD_GIT_REVISION")),
+        ],
+        1,
+    );
+    samples.add(
+        "compose_apps_exporter_scrape_duration_seconds",
+        &[],
+        last_collection_duration.unwrap_or_default().as_secs_f64(),
+    );
+}
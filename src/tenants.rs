@@ -0,0 +1,163 @@
+//! Access tokens scoped to a subset of apps, so several tenants (e.g. the
+//! customers of a shared hosting box) can each scrape only their own apps from
+//! a single exporter
+
+use hyper::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::auth;
+
+/// A tenant, who can see the apps whose compose file matches one of `paths`
+/// with its `token`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TenantConfig {
+    pub name: String,
+    pub token: String,
+    /// Glob patterns for compose file paths
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub name: String,
+    token: String,
+    paths: Vec<glob::Pattern>,
+}
+
+impl Tenant {
+    /// Whether the app with the given compose file belongs to the tenant
+    pub fn owns(&self, config_path: &Path) -> bool {
+        self.paths
+            .iter()
+            .any(|pattern| pattern.matches_path(config_path))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Tenants(Vec<Tenant>);
+
+impl Tenants {
+    pub fn new(configs: Vec<TenantConfig>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut names = HashSet::new();
+        let mut tokens = HashSet::new();
+        let tenants = configs
+            .into_iter()
+            .map(|config| {
+                if config.token.is_empty() {
+                    return Err(format!("Tenant {:?} has an empty token", config.name));
+                }
+                if !names.insert(config.name.clone()) {
+                    return Err(format!("Duplicate tenant {:?}", config.name));
+                }
+                if !tokens.insert(config.token.clone()) {
+                    return Err(format!(
+                        "Tenant {:?} has the same token as another tenant",
+                        config.name
+                    ));
+                }
+                let paths = config
+                    .paths
+                    .iter()
+                    .map(|path| {
+                        glob::Pattern::new(path).map_err(|err| {
+                            format!(
+                                "Invalid path {:?} of tenant {:?}: {}",
+                                path, config.name, err
+                            )
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Tenant {
+                    name: config.name,
+                    token: config.token,
+                    paths,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Tenants(tenants))
+    }
+
+    /// Whether tenants are configured, in which case the metrics and JSON
+    /// endpoints require the token of one of them
+    pub fn are_enabled(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    /// Get the tenant whose token the given request headers carry, if any
+    pub fn authorize(&self, headers: &HeaderMap) -> Option<&Tenant> {
+        self.0
+            .iter()
+            .find(|tenant| auth::is_bearer_authorized(headers, &tenant.token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::{self, HeaderValue};
+
+    fn tenant(name: &str, token: &str, paths: &[&str]) -> TenantConfig {
+        TenantConfig {
+            name: name.to_string(),
+            token: token.to_string(),
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+        }
+    }
+
+    fn bearer(token: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static(token));
+        headers
+    }
+
+    #[test]
+    fn validates_tenants() {
+        assert!(Tenants::new(vec![tenant("acme", "", &["/srv/acme/*"])]).is_err());
+        assert!(Tenants::new(vec![tenant("acme", "a", &["/srv/[acme/*"])]).is_err());
+        assert!(Tenants::new(vec![
+            tenant("acme", "a", &["/srv/acme/*"]),
+            tenant("acme", "b", &["/srv/other/*"]),
+        ])
+        .is_err());
+        assert!(Tenants::new(vec![
+            tenant("acme", "a", &["/srv/acme/*"]),
+            tenant("other", "a", &["/srv/other/*"]),
+        ])
+        .is_err());
+        assert!(!Tenants::new(vec![]).unwrap().are_enabled());
+    }
+
+    #[test]
+    fn authorizes_tenants_by_token() {
+        let tenants = Tenants::new(vec![
+            tenant("acme", "a", &["/srv/acme/*"]),
+            tenant("other", "b", &["/srv/other/*", "/opt/other/*"]),
+        ])
+        .unwrap();
+        assert!(tenants.are_enabled());
+        assert_eq!(
+            tenants
+                .authorize(&bearer("Bearer b"))
+                .map(|tenant| tenant.name.as_str()),
+            Some("other")
+        );
+        assert!(tenants.authorize(&bearer("Bearer c")).is_none());
+        assert!(tenants.authorize(&HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn owns_apps_by_path() {
+        let tenants = Tenants::new(vec![tenant(
+            "other",
+            "b",
+            &["/srv/other/*", "/opt/other/*"],
+        )])
+        .unwrap();
+        let tenant = tenants.authorize(&bearer("Bearer b")).unwrap();
+        assert!(tenant.owns(Path::new("/srv/other/compose.yaml")));
+        assert!(tenant.owns(Path::new("/opt/other/compose.yaml")));
+        assert!(!tenant.owns(Path::new("/srv/acme/compose.yaml")));
+    }
+}
@@ -0,0 +1,202 @@
+//! Terminating TLS on the metrics endpoint, for hosts without a reverse proxy,
+//! with the certificate and key reloaded whenever their files change (e.g.
+//! when renewed by certbot)
+
+use serde::Deserialize;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{version, RootCertStore, ServerConfig, SupportedProtocolVersion};
+use tokio_rustls::TlsAcceptor;
+
+/// Whether to request and verify client certificates, named as in the
+/// exporter toolkit's web config
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClientAuthType {
+    #[default]
+    NoClientCert,
+    /// Verify client certificates against the client CA, but also accept
+    /// clients without one
+    VerifyClientCertIfGiven,
+    RequireAndVerifyClientCert,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    #[serde(rename = "TLS12")]
+    Tls12,
+    #[serde(rename = "TLS13")]
+    Tls13,
+}
+
+impl TlsVersion {
+    fn protocol_version(self) -> &'static SupportedProtocolVersion {
+        match self {
+            TlsVersion::Tls12 => &version::TLS12,
+            TlsVersion::Tls13 => &version::TLS13,
+        }
+    }
+}
+
+/// Certificate (chain), private key and client CA files, PEM-encoded, and how
+/// to negotiate connections
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsOptions {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    pub client_auth_type: ClientAuthType,
+    pub client_ca_file: Option<PathBuf>,
+    pub min_version: TlsVersion,
+    pub max_version: TlsVersion,
+    /// Whether to offer HTTP/2 through ALPN
+    pub http2: bool,
+}
+
+impl TlsOptions {
+    /// Options for just a certificate and key, as set by the `tls_cert_file`
+    /// and `tls_key_file` options
+    pub fn new(cert_file: PathBuf, key_file: PathBuf) -> Self {
+        TlsOptions {
+            cert_file,
+            key_file,
+            client_auth_type: ClientAuthType::NoClientCert,
+            client_ca_file: None,
+            min_version: TlsVersion::Tls12,
+            max_version: TlsVersion::Tls13,
+            http2: true,
+        }
+    }
+
+    /// Files to reload the acceptor on changes of
+    fn files(&self) -> impl Iterator<Item = &Path> {
+        [&self.cert_file, &self.key_file]
+            .into_iter()
+            .chain(&self.client_ca_file)
+            .map(PathBuf::as_path)
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.files().map(modified).collect()
+    }
+}
+
+struct Loaded {
+    acceptor: TlsAcceptor,
+    /// Modification times of the files when loaded
+    modified: Vec<Option<SystemTime>>,
+}
+
+/// Accepts TLS connections with the certificate currently in the files
+pub struct ReloadingAcceptor {
+    options: TlsOptions,
+    loaded: Mutex<Loaded>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn load(options: &TlsOptions) -> Result<Loaded, Box<dyn std::error::Error>> {
+    let modified = options.modified();
+    let open = |path: &Path| {
+        std::fs::File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| format!("Failed to open {}: {}", path.display(), err))
+    };
+    let read_certs = |path: &Path| {
+        let certs = rustls_pemfile::certs(&mut open(path)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("Invalid certificate file {}: {}", path.display(), err))?;
+        if certs.is_empty() {
+            return Err(format!("No certificates found in {}", path.display()));
+        }
+        Ok(certs)
+    };
+    let certs = read_certs(&options.cert_file)?;
+    let key = rustls_pemfile::private_key(&mut open(&options.key_file)?)
+        .map_err(|err| format!("Invalid key file: {}", err))?
+        .ok_or_else(|| format!("No private key found in {}", options.key_file.display()))?;
+    let versions = [TlsVersion::Tls12, TlsVersion::Tls13]
+        .into_iter()
+        .filter(|version| (options.min_version..=options.max_version).contains(version))
+        .map(TlsVersion::protocol_version)
+        .collect::<Vec<_>>();
+    let builder = ServerConfig::builder_with_protocol_versions(&versions);
+    let builder = match (&options.client_ca_file, options.client_auth_type) {
+        (Some(client_ca_file), client_auth_type)
+            if client_auth_type != ClientAuthType::NoClientCert =>
+        {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(client_ca_file)? {
+                roots
+                    .add(cert)
+                    .map_err(|err| format!("Invalid client CA certificate: {}", err))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+            let verifier = if client_auth_type == ClientAuthType::VerifyClientCertIfGiven {
+                verifier.allow_unauthenticated()
+            } else {
+                verifier
+            };
+            builder.with_client_cert_verifier(
+                verifier
+                    .build()
+                    .map_err(|err| format!("Invalid client CA: {}", err))?,
+            )
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|err| format!("Invalid certificate or key: {}", err))?;
+    config.alpn_protocols = if options.http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    Ok(Loaded {
+        acceptor: TlsAcceptor::from(Arc::new(config)),
+        modified,
+    })
+}
+
+impl ReloadingAcceptor {
+    /// Load the certificate, key and client CA, failing if they're invalid
+    pub fn new(options: TlsOptions) -> Result<Self, Box<dyn std::error::Error>> {
+        let loaded = load(&options)?;
+        Ok(ReloadingAcceptor {
+            options,
+            loaded: Mutex::new(loaded),
+        })
+    }
+
+    /// Get the acceptor for a new connection, reloading the certificate, key
+    /// and client CA first if their files changed. If they can't be reloaded
+    /// (e.g. while only one of them was replaced yet), the previous ones are
+    /// kept.
+    pub fn acceptor(&self) -> TlsAcceptor {
+        let mut loaded = self
+            .loaded
+            .lock()
+            .expect("TLS acceptor lock shouldn't be poisoned");
+        let modified = self.options.modified();
+        if modified != loaded.modified {
+            match load(&self.options) {
+                Ok(reloaded) => {
+                    println!("Reloaded the TLS certificate and key");
+                    *loaded = reloaded;
+                }
+                Err(e) => {
+                    eprintln!("Error reloading the TLS certificate and key: {}", e);
+                    // Don't retry until they change again
+                    loaded.modified = modified;
+                }
+            }
+        }
+        loaded.acceptor.clone()
+    }
+}
@@ -0,0 +1,229 @@
+//! Persistent history of service availability transitions, for status-page
+//! style uptime percentages over days without a long Prometheus retention
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Windows over which uptimes are computed, with their names
+pub static UPTIME_WINDOWS: [(&str, Duration); 3] = [
+    ("24h", Duration::from_secs(24 * 60 * 60)),
+    ("7d", Duration::from_secs(7 * 24 * 60 * 60)),
+    ("30d", Duration::from_secs(30 * 24 * 60 * 60)),
+];
+
+/// Line of the history file
+#[derive(Deserialize, Serialize)]
+struct Transition {
+    /// Unix timestamp, in seconds
+    at: f64,
+    compose_name: String,
+    service_name: String,
+    available: bool,
+}
+
+/// Uptime of a service, as served by the uptime API
+#[derive(Serialize)]
+pub struct ServiceUptime {
+    pub compose_name: String,
+    pub service_name: String,
+    pub available: bool,
+    /// Fraction of each window the service was available, by window name
+    pub uptime: BTreeMap<&'static str, f64>,
+}
+
+/// Transitions (timestamp, available) by compose and service name, oldest
+/// first
+type Transitions = HashMap<(String, String), Vec<(f64, bool)>>;
+
+pub struct UptimeHistory {
+    path: PathBuf,
+    file: Mutex<File>,
+    transitions: Mutex<Transitions>,
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+impl UptimeHistory {
+    /// Load the history from the given file (if it exists), dropping
+    /// transitions older than the longest window, and open it for appending
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut transitions: Transitions = HashMap::new();
+        match File::open(path) {
+            Ok(file) => {
+                for (i, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    let Ok(transition) = serde_json::from_str::<Transition>(&line) else {
+                        // e.g. a line cut short by a crash
+                        eprintln!(
+                            "Skipping invalid line {} of uptime history {}",
+                            i + 1,
+                            path.display()
+                        );
+                        continue;
+                    };
+                    transitions
+                        .entry((transition.compose_name, transition.service_name))
+                        .or_default()
+                        .push((transition.at, transition.available));
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(
+                    format!("Failed to read uptime history {}: {}", path.display(), err).into(),
+                )
+            }
+        }
+        let longest_window = UPTIME_WINDOWS[UPTIME_WINDOWS.len() - 1].1;
+        let cutoff = unix_now() - longest_window.as_secs_f64();
+        for service_transitions in transitions.values_mut() {
+            // Clock changes can leave the file out of order
+            service_transitions.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+            // Keep the last transition from before the cutoff, as it holds at
+            // the start of the longest window
+            let nbro_old = service_transitions
+                .iter()
+                .filter(|(at, _)| *at < cutoff)
+                .count();
+            service_transitions.drain(..nbro_old.saturating_sub(1));
+        }
+        // Rewrite the file without the dropped transitions
+        let compacted_path = path.with_extension("compacting");
+        let mut compacted_file = File::create(&compacted_path)?;
+        for ((compose_name, service_name), service_transitions) in &transitions {
+            for (at, available) in service_transitions {
+                let transition = Transition {
+                    at: *at,
+                    compose_name: compose_name.clone(),
+                    service_name: service_name.clone(),
+                    available: *available,
+                };
+                writeln!(compacted_file, "{}", serde_json::to_string(&transition)?)?;
+            }
+        }
+        compacted_file.sync_all()?;
+        std::fs::rename(&compacted_path, path)?;
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(UptimeHistory {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            transitions: Mutex::new(transitions),
+        })
+    }
+
+    /// Record whether the given service is currently available, appending a
+    /// transition to the history if that changed
+    pub fn observe(&self, compose_name: &str, service_name: &str, available: bool) {
+        let mut transitions = self
+            .transitions
+            .lock()
+            .expect("uptime history lock shouldn't be poisoned");
+        let service_transitions = transitions
+            .entry((compose_name.to_string(), service_name.to_string()))
+            .or_default();
+        if service_transitions.last().map(|(_, available)| *available) == Some(available) {
+            return;
+        }
+        let at = unix_now();
+        service_transitions.push((at, available));
+        let transition = Transition {
+            at,
+            compose_name: compose_name.to_string(),
+            service_name: service_name.to_string(),
+            available,
+        };
+        let line = serde_json::to_string(&transition).expect("transition should serialize to JSON");
+        let mut file = self
+            .file
+            .lock()
+            .expect("uptime history file lock shouldn't be poisoned");
+        if let Err(e) = writeln!(file, "{}", line) {
+            eprintln!(
+                "Error writing to uptime history {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Get the fraction of each of the [`UPTIME_WINDOWS`] the given service
+    /// was available (since it was first seen, if that's more recent), if it
+    /// has been seen at all
+    pub fn uptime(&self, compose_name: &str, service_name: &str) -> Option<Vec<f64>> {
+        let transitions = self
+            .transitions
+            .lock()
+            .expect("uptime history lock shouldn't be poisoned");
+        let service_transitions =
+            transitions.get(&(compose_name.to_string(), service_name.to_string()))?;
+        Some(uptimes(service_transitions, unix_now()))
+    }
+
+    /// Get the uptime of all services in the history
+    pub fn uptimes(&self) -> Vec<ServiceUptime> {
+        let now = unix_now();
+        let transitions = self
+            .transitions
+            .lock()
+            .expect("uptime history lock shouldn't be poisoned");
+        let mut uptimes: Vec<_> = transitions
+            .iter()
+            .filter_map(|((compose_name, service_name), service_transitions)| {
+                let (_, available) = service_transitions.last()?;
+                Some(ServiceUptime {
+                    compose_name: compose_name.clone(),
+                    service_name: service_name.clone(),
+                    available: *available,
+                    uptime: UPTIME_WINDOWS
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .zip(self::uptimes(service_transitions, now))
+                        .collect(),
+                })
+            })
+            .collect();
+        uptimes.sort_by(|a, b| {
+            (&a.compose_name, &a.service_name).cmp(&(&b.compose_name, &b.service_name))
+        });
+        uptimes
+    }
+}
+
+/// Fraction of each of the [`UPTIME_WINDOWS`] a service with the given
+/// transitions was available
+fn uptimes(transitions: &[(f64, bool)], now: f64) -> Vec<f64> {
+    UPTIME_WINDOWS
+        .iter()
+        .map(|(_, window)| {
+            let window_start = now - window.as_secs_f64();
+            let mut available_time = 0.0;
+            let mut observed_time = 0.0;
+            for (i, (at, available)) in transitions.iter().enumerate() {
+                let until = transitions.get(i + 1).map_or(now, |(next_at, _)| *next_at);
+                let from = at.max(window_start);
+                if until <= from {
+                    continue;
+                }
+                observed_time += until - from;
+                if *available {
+                    available_time += until - from;
+                }
+            }
+            if observed_time > 0.0 {
+                available_time / observed_time
+            } else {
+                transitions.last().is_some_and(|(_, available)| *available) as u8 as f64
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,81 @@
+//! Disk usage of named volumes, attributed to the apps declaring them, so
+//! volume bloat (e.g. database WAL or media caches) shows up per app
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::compose_config::ComposeConfig;
+use crate::scrape_error::{ErrorCode, ScrapeError};
+use crate::{exec_docker_global_cmd, DockerCli};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SystemDf {
+    #[serde(default)]
+    volumes: Vec<SystemDfVolume>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SystemDfVolume {
+    name: String,
+    /// Human-readable, e.g. '39.5MB', or 'N/A' if unknown
+    size: String,
+}
+
+/// Read the size of all volumes on the docker engine, by volume name
+pub async fn read_volume_sizes(
+    docker: &DockerCli,
+) -> Result<HashMap<String, u64>, Box<dyn std::error::Error>> {
+    let output = exec_docker_global_cmd(docker, &["system", "df", "-v", "--format", "{{json .}}"])
+        .await
+        .map_err(|err| ScrapeError::context(err, "Failed to execute `docker system df`"))?;
+    let system_df: SystemDf = serde_json::from_slice(&output).map_err(|err| {
+        ScrapeError::new(
+            ErrorCode::DockerOutputInvalid,
+            format!("Failed to parse `docker system df` output: {}", err),
+        )
+    })?;
+    Ok(system_df
+        .volumes
+        .into_iter()
+        .filter_map(|volume| Some((volume.name, parse_human_size(&volume.size)?)))
+        .collect())
+}
+
+/// Parse a size as formatted by docker (in decimal units with three significant
+/// digits), e.g. '0B', '1.5kB' or '39.5MB'
+fn parse_human_size(size: &str) -> Option<u64> {
+    let unit_start = size.find(|c: char| c.is_ascii_alphabetic())?;
+    let (number, unit) = size.split_at(unit_start);
+    let multiplier: u64 = match unit {
+        "B" => 1,
+        "kB" | "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        "PB" => 1_000_000_000_000_000,
+        _ => return None,
+    };
+    let number: f64 = number.trim().parse().ok()?;
+    Some((number * multiplier as f64).round() as u64)
+}
+
+/// Get the engine names of the volumes the given app declares, by their key in
+/// its compose file
+pub fn declared_volume_names(compose_config: &ComposeConfig) -> Vec<(&str, String)> {
+    compose_config
+        .volumes
+        .iter()
+        .map(|(key, volume)| {
+            let name = volume.name.clone().unwrap_or_else(|| {
+                if volume.external {
+                    key.clone()
+                } else {
+                    format!("{}_{}", compose_config.name, key)
+                }
+            });
+            (key.as_str(), name)
+        })
+        .collect()
+}
@@ -0,0 +1,189 @@
+//! The `watch` subcommand: a live table of the services of all apps in the
+//! terminal, refreshed from the same collections as the metrics, for
+//! troubleshooting over SSH without a monitoring stack
+
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::app_state::{AppState, ServiceState};
+use crate::{availability, collect_metrics, Exporter, MetricsTarget};
+
+static CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+static GREEN: &str = "\x1b[32m";
+static YELLOW: &str = "\x1b[33m";
+static RED: &str = "\x1b[31m";
+static RESET: &str = "\x1b[0m";
+
+#[derive(Default)]
+struct Row {
+    state: String,
+    health: String,
+    uptime: Option<f64>,
+}
+
+/// Where to show the uptime of services from
+enum UptimeWindow {
+    /// An availability window, by its suffix (e.g. '1h')
+    Availability(String),
+    /// A window of the uptime history, by its name (e.g. '24h')
+    History(&'static str),
+}
+
+impl UptimeWindow {
+    fn uptime_of(&self, service: &ServiceState) -> Option<f64> {
+        match self {
+            UptimeWindow::Availability(suffix) => service.available_ratios.get(suffix),
+            UptimeWindow::History(name) => service.uptime_ratios.get(*name),
+        }
+        .copied()
+    }
+}
+
+/// Window to show the uptime of services for, and the column header for it:
+/// the first availability window, or the last 24 hours of the uptime history
+fn uptime_window(exporter: &Exporter) -> Option<(UptimeWindow, String)> {
+    let config = exporter.config();
+    if let Some(window) = config.availability_windows.first() {
+        let suffix = availability::window_suffix(*window);
+        let header = format!("UPTIME ({})", suffix);
+        return Some((UptimeWindow::Availability(suffix), header));
+    }
+    if exporter.uptime_history.is_some() && config.uptime_history_metrics {
+        return Some((UptimeWindow::History("24h"), "UPTIME (24h)".to_string()));
+    }
+    None
+}
+
+/// Get the services of the collected apps, by compose and service name. Apps
+/// that failed to be collected get a row with their error code as state.
+fn rows(
+    apps: &[AppState],
+    uptime_window: Option<&UptimeWindow>,
+) -> BTreeMap<(String, String), Row> {
+    let mut rows: BTreeMap<(String, String), Row> = BTreeMap::new();
+    for app in apps {
+        if let Some(error) = &app.error {
+            rows.insert(
+                (app.compose_name.clone(), "-".to_string()),
+                Row {
+                    state: error.code.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+        for service in &app.services {
+            rows.insert(
+                (app.compose_name.clone(), service.service_name.clone()),
+                Row {
+                    state: service.state.clone().unwrap_or_default(),
+                    health: service.health.clone().unwrap_or_default(),
+                    uptime: uptime_window.and_then(|window| window.uptime_of(service)),
+                },
+            );
+        }
+    }
+    rows
+}
+
+fn color_of(value: &str) -> &'static str {
+    match value {
+        "running" | "healthy" => GREEN,
+        "starting" | "restarting" | "created" | "paused" => YELLOW,
+        "no_check" | "" => RESET,
+        _ => RED,
+    }
+}
+
+/// Render the services as a table, colored if `colored`
+fn render(
+    rows: &BTreeMap<(String, String), Row>,
+    uptime_header: Option<&str>,
+    colored: bool,
+) -> String {
+    let mut table = vec![vec![
+        "APP".to_string(),
+        "SERVICE".to_string(),
+        "STATE".to_string(),
+        "HEALTH".to_string(),
+    ]];
+    if let Some(uptime_header) = uptime_header {
+        table[0].push(uptime_header.to_string());
+    }
+    for ((compose_name, service_name), row) in rows {
+        let mut cells = vec![
+            compose_name.clone(),
+            service_name.clone(),
+            row.state.clone(),
+            row.health.clone(),
+        ];
+        if uptime_header.is_some() {
+            cells.push(
+                row.uptime
+                    .map_or("-".to_string(), |uptime| format!("{:.1}%", uptime * 100.0)),
+            );
+        }
+        table.push(cells);
+    }
+    let widths: Vec<usize> = (0..table[0].len())
+        .map(|column| {
+            table
+                .iter()
+                .map(|cells| cells[column].len())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut rendered = String::new();
+    for (index, cells) in table.iter().enumerate() {
+        let mut line = String::new();
+        for (column, cell) in cells.iter().enumerate() {
+            let padded = format!("{:width$}  ", cell, width = widths[column]);
+            // Color the state and health, but not the header
+            if colored && index > 0 && (column == 2 || column == 3) {
+                line.push_str(&format!("{}{}{}", color_of(cell), padded, RESET));
+            } else {
+                line.push_str(&padded);
+            }
+        }
+        rendered.push_str(line.trim_end());
+        rendered.push('\n');
+    }
+    rendered
+}
+
+/// Collect all apps and redraw the table every `interval`, until interrupted
+pub async fn run(exporter: Arc<Exporter>, interval: Duration) {
+    let colored = std::io::stdout().is_terminal();
+    loop {
+        let uptime_window = uptime_window(&exporter);
+        // The only collection of all apps in watch mode
+        let screen = match collect_metrics(&exporter, MetricsTarget::All, None, None, true).await {
+            Ok(collection) => {
+                let rows = rows(
+                    &collection.apps,
+                    uptime_window.as_ref().map(|(window, _)| window),
+                );
+                render(
+                    &rows,
+                    uptime_window.as_ref().map(|(_, header)| header.as_str()),
+                    colored,
+                )
+            }
+            Err(e) => format!("Collection failed: [{}] {}\n", e.code, e),
+        };
+        let mut stdout = std::io::stdout().lock();
+        let _ = write!(
+            stdout,
+            "{}{} (every {}s, Ctrl+C to quit)\n\n{}",
+            CLEAR_SCREEN,
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            interval.as_secs(),
+            screen
+        );
+        let _ = stdout.flush();
+        drop(stdout);
+        tokio::time::sleep(interval).await;
+    }
+}
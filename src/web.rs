@@ -0,0 +1,163 @@
+//! Web configuration file in the schema of the Prometheus exporter toolkit
+//! (`--web.config.file`), so the exporter's TLS, authentication and HTTP
+//! settings can be deployed the same way as node_exporter's
+
+use hyper::header::{self, HeaderName};
+use hyper::http::HeaderValue;
+use hyper::{Body, Response};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::tls::{ClientAuthType, TlsOptions, TlsVersion};
+
+/// Response headers that may be set in `http_server_config.headers`, as in the
+/// exporter toolkit
+static ALLOWED_HEADERS: [HeaderName; 5] = [
+    header::STRICT_TRANSPORT_SECURITY,
+    header::X_CONTENT_TYPE_OPTIONS,
+    header::X_FRAME_OPTIONS,
+    header::X_XSS_PROTECTION,
+    header::CONTENT_SECURITY_POLICY,
+];
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct WebConfigFile {
+    tls_server_config: Option<TlsServerConfig>,
+    #[serde(default)]
+    http_server_config: HttpServerConfig,
+    /// bcrypt hashes of the passwords, by user name
+    #[serde(default)]
+    basic_auth_users: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TlsServerConfig {
+    cert_file: std::path::PathBuf,
+    key_file: std::path::PathBuf,
+    #[serde(default)]
+    client_auth_type: ClientAuthType,
+    client_ca_file: Option<std::path::PathBuf>,
+    min_version: Option<TlsVersion>,
+    max_version: Option<TlsVersion>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct HttpServerConfig {
+    #[serde(default = "default_http2")]
+    http2: bool,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        HttpServerConfig {
+            http2: default_http2(),
+            headers: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_http2() -> bool {
+    true
+}
+
+/// Settings of the web configuration file
+#[derive(Debug)]
+pub struct WebConfig {
+    pub tls: Option<TlsOptions>,
+    pub http2: bool,
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    /// bcrypt hashes of the passwords, by user name
+    pub basic_auth_users: Vec<(String, String)>,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        WebConfig {
+            tls: None,
+            http2: default_http2(),
+            headers: vec![],
+            basic_auth_users: vec![],
+        }
+    }
+}
+
+impl WebConfig {
+    /// Read and validate the web configuration file, resolving the paths in it
+    /// relative to the file's directory like the exporter toolkit
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read web config file {}: {}", path.display(), err))?;
+        let file: WebConfigFile = serde_yaml::from_str(&contents)
+            .map_err(|err| format!("Invalid web config file {}: {}", path.display(), err))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let tls = match file.tls_server_config {
+            Some(tls) => {
+                if tls.client_auth_type == ClientAuthType::NoClientCert
+                    && tls.client_ca_file.is_some()
+                {
+                    return Err(
+                        "client_ca_file is set, but client_auth_type doesn't verify client certificates"
+                            .into(),
+                    );
+                }
+                if tls.client_auth_type != ClientAuthType::NoClientCert
+                    && tls.client_ca_file.is_none()
+                {
+                    return Err(format!(
+                        "client_auth_type {:?} requires client_ca_file",
+                        tls.client_auth_type
+                    )
+                    .into());
+                }
+                let min_version = tls.min_version.unwrap_or(TlsVersion::Tls12);
+                let max_version = tls.max_version.unwrap_or(TlsVersion::Tls13);
+                if min_version > max_version {
+                    return Err("The TLS min_version is above the max_version".into());
+                }
+                Some(TlsOptions {
+                    cert_file: directory.join(tls.cert_file),
+                    key_file: directory.join(tls.key_file),
+                    client_auth_type: tls.client_auth_type,
+                    client_ca_file: tls.client_ca_file.map(|file| directory.join(file)),
+                    min_version,
+                    max_version,
+                    http2: file.http_server_config.http2,
+                })
+            }
+            None => None,
+        };
+        let headers = file
+            .http_server_config
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .ok()
+                    .filter(|name| ALLOWED_HEADERS.contains(name))
+                    .ok_or_else(|| format!("Header {:?} can't be set in the web config", name))?;
+                let value = HeaderValue::from_str(value)
+                    .map_err(|_| format!("Invalid value of header {}: {:?}", name, value))?;
+                Ok((name, value))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(WebConfig {
+            tls,
+            http2: file.http_server_config.http2,
+            headers,
+            basic_auth_users: file.basic_auth_users.into_iter().collect(),
+        })
+    }
+
+    /// Add the configured headers to a response
+    pub fn apply(&self, response: &mut Response<Body>) {
+        for (name, value) in &self.headers {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+}
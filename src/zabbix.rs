@@ -0,0 +1,140 @@
+//! Pushing the state and health of services to a Zabbix server or proxy with
+//! the Zabbix sender protocol, for shops that standardize on Zabbix
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Port Zabbix servers and proxies listen on for sender data by default
+static DEFAULT_PORT: u16 = 10051;
+static HEADER: &[u8] = b"ZBXD\x01";
+
+struct Observation {
+    at: Instant,
+    state: String,
+    health: String,
+}
+
+/// State and health of services as last observed, by compose and service name
+#[derive(Default)]
+pub struct ZabbixItems {
+    observed: Mutex<HashMap<(String, String), Observation>>,
+}
+
+#[derive(Deserialize)]
+struct SenderResponse {
+    response: String,
+    #[serde(default)]
+    info: String,
+}
+
+/// Quote a parameter of an item key if needed, see
+/// <https://www.zabbix.com/documentation/current/en/manual/config/items/item/key>
+fn key_parameter(value: &str) -> String {
+    if value.contains([',', ']', '"']) || value.starts_with([' ', '[']) {
+        format!("\"{}\"", value.replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Get the item key of a metric of a service from the key template
+fn item_key(key_template: &str, metric: &str, compose_name: &str, service_name: &str) -> String {
+    key_template
+        .replace("{metric}", metric)
+        .replace("{compose_name}", &key_parameter(compose_name))
+        .replace("{service_name}", &key_parameter(service_name))
+}
+
+impl ZabbixItems {
+    /// Record the current state and health of a service
+    pub fn observe(&self, compose_name: &str, service_name: &str, state: &str, health: &str) {
+        self.observed
+            .lock()
+            .expect("observed Zabbix items lock shouldn't be poisoned")
+            .insert(
+                (compose_name.to_string(), service_name.to_string()),
+                Observation {
+                    at: Instant::now(),
+                    state: state.to_string(),
+                    health: health.to_string(),
+                },
+            );
+    }
+
+    /// Get the items of the services observed in the last `max_age`, for the
+    /// given Zabbix host
+    pub fn items(&self, max_age: Duration, host: &str, key_template: &str) -> Vec<Value> {
+        let clock = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut observed = self
+            .observed
+            .lock()
+            .expect("observed Zabbix items lock shouldn't be poisoned");
+        // Forget services that are gone
+        observed.retain(|_, observation| observation.at.elapsed() <= max_age);
+        let mut items = vec![];
+        for ((compose_name, service_name), observation) in observed.iter() {
+            for (metric, value) in [
+                ("state", &observation.state),
+                ("health", &observation.health),
+            ] {
+                items.push(json!({
+                    "host": host,
+                    "key": item_key(key_template, metric, compose_name, service_name),
+                    "value": value,
+                    "clock": clock,
+                }));
+            }
+        }
+        items
+    }
+}
+
+/// Send the given items to the Zabbix server or proxy at the given address
+/// (`host` or `host:port`), returning the server's summary, e.g. 'processed:
+/// 2; failed: 0; total: 2; seconds spent: 0.000055'
+pub async fn send(server: &str, items: Vec<Value>) -> Result<String, Box<dyn std::error::Error>> {
+    let address = if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{}:{}", server, DEFAULT_PORT)
+    };
+    let data = serde_json::to_vec(&json!({
+        "request": "sender data",
+        "data": items,
+    }))?;
+    let mut packet = HEADER.to_vec();
+    packet.extend((data.len() as u64).to_le_bytes());
+    packet.extend(data);
+    let exchange = async {
+        let mut stream = TcpStream::connect(&address).await?;
+        stream.write_all(&packet).await?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = tokio::time::timeout(Duration::from_secs(10), exchange)
+        .await
+        .map_err(|_| "Timed out")??;
+    let body = response
+        .strip_prefix(HEADER)
+        .filter(|rest| rest.len() >= 8)
+        .map(|rest| &rest[8..])
+        .ok_or("Invalid response header")?;
+    let response: SenderResponse = serde_json::from_slice(body)?;
+    if response.response != "success" {
+        return Err(format!(
+            "Zabbix responded with {}: {}",
+            response.response, response.info
+        )
+        .into());
+    }
+    Ok(response.info)
+}